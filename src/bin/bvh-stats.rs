@@ -0,0 +1,74 @@
+use rand::prelude::*;
+use rand::rngs::SmallRng;
+use std::time::{Duration, Instant};
+
+use rtlib::hittable::bvh::{BvhBuilder, BvhStats};
+use rtlib::hittable::linear_bvh::LinearBvh;
+use rtlib::hittable::{Hittable, Hittables};
+use rtlib::sampler::{independent::IndependentSampler, Sampler};
+use rtlib::scenes::{next_week_final::next_week_final, random_world::random_world, Scene};
+
+const WIDTH: u64 = 200;
+const HEIGHT: u64 = 200;
+const SEED: u64 = 0;
+
+// Trace one camera ray per pixel through each builder's tree and report how
+// many nodes and objects were tested on average.
+fn compare(name: &str, scene: Scene) {
+    let node = match scene.hittables {
+        Hittables::BvhNode(node) => node,
+        _ => panic!("{} isn't a BVH", name),
+    };
+    println!("{}", name);
+    println!(
+        "  {:<12} {:>10} {:>10} {:>10} {:>10} {:>10}",
+        "builder", "sah cost", "nodes/ray", "objs/ray", "ms", "linear ms"
+    );
+    let builders = [
+        ("median", BvhBuilder::Median),
+        ("sah leaf=1", BvhBuilder::Sah { leaf_size: 1 }),
+        ("sah leaf=2", BvhBuilder::Sah { leaf_size: 2 }),
+        ("sah leaf=4", BvhBuilder::Sah { leaf_size: 4 }),
+        ("sah leaf=8", BvhBuilder::Sah { leaf_size: 8 }),
+    ];
+    for (label, builder) in builders.iter() {
+        let bvh = node.rebuild(0.0, 1.0, *builder);
+        let linear = LinearBvh::from_bvh(bvh.clone(), 0.0, 1.0);
+        let mut rng = SmallRng::seed_from_u64(SEED);
+        let mut sampler = IndependentSampler::new(SEED);
+        let mut stats = BvhStats::default();
+        let mut elapsed = Duration::default();
+        let mut linear_elapsed = Duration::default();
+        for j in 0..HEIGHT {
+            for i in 0..WIDTH {
+                sampler.start_pixel_sample(i as usize, j as usize, 0);
+                let (du, dv) = sampler.get_2d();
+                let u = (i as f64 + du) / (WIDTH - 1) as f64;
+                let v = (j as f64 + dv) / (HEIGHT - 1) as f64;
+                let ray = scene.camera.get_ray(u, v, &mut sampler);
+                let start = Instant::now();
+                bvh.hit(&ray, 0.0001, f64::MAX, &mut rng);
+                elapsed += start.elapsed();
+                let start = Instant::now();
+                linear.hit(&ray, 0.0001, f64::MAX, &mut rng);
+                linear_elapsed += start.elapsed();
+                bvh.traversal_stats(&ray, 0.0001, f64::MAX, &mut rng, &mut stats);
+            }
+        }
+        let rays = (WIDTH * HEIGHT) as f64;
+        println!(
+            "  {:<12} {:>10.2} {:>10.2} {:>10.2} {:>10} {:>10}",
+            label,
+            bvh.sah_cost(),
+            stats.nodes as f64 / rays,
+            stats.objects as f64 / rays,
+            elapsed.as_millis(),
+            linear_elapsed.as_millis()
+        );
+    }
+}
+
+fn main() {
+    compare("random_world", random_world(0.0, 1.0, 1.0, SEED));
+    compare("next_week_final", next_week_final(0.0, 1.0, 1.0));
+}
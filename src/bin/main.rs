@@ -1,20 +1,28 @@
 #[cfg(feature = "profile")]
 use cpuprofiler::PROFILER;
 
-use indicatif::{ParallelProgressIterator, ProgressBar, ProgressStyle};
-use rand::prelude::*;
-use rand::rngs::SmallRng;
-use rayon::iter::ParallelIterator;
-use rayon::prelude::*;
+use indicatif::{ProgressBar, ProgressStyle};
+use serde::{Deserialize, Serialize};
 use std::io::{self};
-use std::sync::Arc;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 use structopt::StructOpt;
 
-use rtlib::color::{color, write_color, Color};
-use rtlib::hittable::{Hittable, Hittables};
-use rtlib::material::Material;
-use rtlib::pdf::{HittablePdf, MixturePdf, Pdf};
-use rtlib::ray::Ray;
+use rtlib::hittable::{bvh::BvhBuilder, linear_bvh::LinearBvh, Hittables};
+use rtlib::loader::scene::load_scene;
+use rtlib::output::tonemap::{Operator, ToneMap};
+use rtlib::output::{write_image, write_ppm, OutputFormat};
+use rtlib::render::aov::Aov;
+use rtlib::render::denoise::Denoiser;
+use rtlib::render::distributed::{work, Address, Coordinator};
+use rtlib::render::filter::{Filter, FilterKind};
+use rtlib::render::tile::{tiles, Tile, TileOrder};
+use rtlib::render::{
+    adaptive::Adaptive, checkpoint::Checkpoint, Accumulator, Framebuffer, RenderSettings, Renderer,
+};
+use rtlib::sampler::SamplerKind;
+use rtlib::scenes::Scene;
 
 #[allow(unused_imports)]
 use rtlib::scenes::{
@@ -33,52 +41,22 @@ use rtlib::scenes::{
     simple_light::simple_light,
 };
 
-#[allow(dead_code)]
-fn ray_color(
-    ray: Ray,
-    background: Color,
-    world: &Hittables,
-    lights: Arc<Hittables>,
-    depth: u32,
-    rng: &mut SmallRng,
-) -> Color {
-    if depth <= 0 {
-        return color(0.0, 0.0, 0.0);
-    }
-    match world.hit(&ray, 0.0001, std::f64::MAX, rng) {
-        Some(hit) => {
-            let emitted = hit.mat.emitted(&ray, &hit, hit.u, hit.v, hit.point);
-            match hit.mat.scatter(&ray, &hit, rng) {
-                Some(scatter) => {
-                    let pdf = scatter.pdf;
-                    if pdf.is_none() || lights.length() == 0 {
-                        return scatter.attenuation
-                            * ray_color(scatter.ray, background, world, lights.clone(), depth - 1, rng);
-                    } else {
-                        let pdf = pdf.unwrap();
-                        let light_pdf = HittablePdf::new(hit.point, lights.clone());
-                        let pdf = MixturePdf::new(Arc::new(light_pdf), Arc::new(pdf));
-                        let scattered = Ray {
-                            origin: hit.point,
-                            direction: pdf.generate(rng),
-                            time: ray.time,
-                        };
-                        let pdf_val = pdf.value(scattered.direction, rng);
-                        return emitted
-                            + scatter.attenuation
-                                * hit.mat.scattering_pdf(&ray, &hit, &scattered)
-                                * ray_color(scattered, background, world, lights.clone(), depth - 1, rng)
-                                * (1.0 / pdf_val);
-                    }
-                }
-                None => {
-                    return emitted;
-                }
-            }
-        }
-        None => {
-            return background;
-        }
+fn builtin_scene(name: &str, time0: f64, time1: f64, aspect_ratio: f64, seed: u64) -> Scene {
+    match name {
+        "cornell_box" => cornell_box(time0, time1, aspect_ratio),
+        "cornell_box_sphere" => cornell_box_sphere(time0, time1, aspect_ratio),
+        "cornell_smoke" => cornell_smoke(time0, time1, aspect_ratio),
+        "next_week_final" => next_week_final(time0, time1, aspect_ratio),
+        "marble" => marble(time0, time1, aspect_ratio),
+        "noise" => noise(time0, time1, aspect_ratio),
+        "turbulence" => turbulence(time0, time1, aspect_ratio),
+        "random_world" => random_world(time0, time1, aspect_ratio, seed),
+        "random_world_checkered" => random_world_checkered(time0, time1, aspect_ratio, seed),
+        "random_world_earth" => random_world_earth(time0, time1, aspect_ratio, seed),
+        "random_world_original" => random_world_original(time0, time1, aspect_ratio, seed),
+        "rotate_test" => rotate_test(time0, time1, aspect_ratio),
+        "simple_light" => simple_light(time0, time1, aspect_ratio),
+        _ => random_world(time0, time1, aspect_ratio, seed),
     }
 }
 
@@ -91,29 +69,305 @@ struct Opt {
     #[structopt(short, long, default_value = "100")]
     samples: u64,
 
+    /// Stop sampling a pixel once its relative error is below this; --samples is then the maximum
+    #[structopt(long)]
+    adaptive: Option<f64>,
+
+    /// Samples a pixel takes before adaptive sampling checks it, and between checks
+    #[structopt(long, default_value = "16")]
+    min_samples: u64,
+
+    /// Write an image of the number of samples each pixel took
+    #[structopt(long, parse(from_os_str))]
+    heatmap: Option<PathBuf>,
+
+    /// Exposure in stops applied before tone mapping PNG and PPM output
+    #[structopt(long, default_value = "0", allow_hyphen_values = true)]
+    exposure: f64,
+
+    /// Tone mapping for PNG and PPM output: clamp, reinhard, reinhard-extended, hable or aces
+    #[structopt(long, default_value = "clamp")]
+    tonemap: Operator,
+
+    /// Luminance that reinhard-extended maps to white, by default the brightest pixel's
+    #[structopt(long)]
+    white: Option<f64>,
+
     #[structopt(short, long, default_value = "1.0")]
     aspect_ratio: f64,
 
     #[structopt(short, long, default_value = "50")]
     depth: u32,
 
+    /// Seed for scene generation and sampling; the same seed renders the same image
+    #[structopt(long)]
+    seed: Option<u64>,
+
+    /// Sample sequence: independent, stratified, halton or sobol
+    #[structopt(long, default_value = "independent")]
+    sampler: SamplerKind,
+
+    /// Reconstruction filter: box, tent, gaussian, mitchell or lanczos
+    #[structopt(long, default_value = "box")]
+    filter: FilterKind,
+
+    /// Filter radius in pixels, by default 0.5 for box, 1 for tent, 1.5 for gaussian, 2 for
+    /// mitchell and 3 for lanczos
+    #[structopt(long)]
+    filter_radius: Option<f64>,
+
+    /// Scale samples down to at most this luminance, to suppress fireflies
+    #[structopt(long)]
+    clamp: Option<f64>,
+
+    /// Trace the samples again looking for NaN, infinite and negative ones, report what caused
+    /// them and write an image of the pixels they're in
+    #[structopt(long, parse(from_os_str))]
+    diagnose: Option<PathBuf>,
+
+    /// Bounces before Russian roulette starts terminating paths
+    #[structopt(long, default_value = "5")]
+    rr_depth: u32,
+
     #[structopt(short, long, default_value = "random_world")]
     scene: String,
+
+    /// Load the scene from a JSON scene description instead of --scene
+    #[structopt(long, parse(from_os_str))]
+    scene_file: Option<PathBuf>,
+
+    /// Write the image to a .png, .ppm, .hdr or .exr file instead of PPM on stdout
+    #[structopt(short, long, parse(from_os_str))]
+    output: Option<PathBuf>,
+
+    /// AOVs to write with the image, comma separated: albedo, normal, depth, position, uv, object
+    /// and material. Layers in an EXR --output, otherwise files named after it.
+    #[structopt(long, use_delimiter = true)]
+    aovs: Vec<Aov>,
+
+    /// Samples per pixel averaged into the AOVs
+    #[structopt(long, default_value = "16")]
+    aov_samples: u64,
+
+    /// Denoise the image, guided by the albedo, normal and depth AOVs and each pixel's variance
+    #[structopt(long)]
+    denoise: bool,
+
+    /// BVH builder for the scene: median or sah
+    #[structopt(long, default_value = "median", possible_values = &["median", "sah"])]
+    bvh: String,
+
+    /// Maximum number of objects in a SAH leaf
+    #[structopt(long, default_value = "4")]
+    leaf_size: usize,
+
+    /// Flatten the scene BVH into an array for faster traversal
+    #[structopt(long)]
+    linear_bvh: bool,
+
+    /// Samples each pixel takes per progressive pass, 0 for a single pass
+    #[structopt(long, default_value = "16")]
+    pass_samples: u64,
+
+    /// Periodically save the render so far to this file, to continue it with --resume
+    #[structopt(long, parse(from_os_str))]
+    checkpoint: Option<PathBuf>,
+
+    /// Seconds between checkpoints
+    #[structopt(long, default_value = "60")]
+    checkpoint_interval: u64,
+
+    /// Width and height of the tiles the image is rendered in
+    #[structopt(long, default_value = "32")]
+    tile_size: usize,
+
+    /// Order tiles are rendered in: scanline, spiral or hilbert
+    #[structopt(long, default_value = "hilbert")]
+    tile_order: TileOrder,
+
+    /// Seconds between writes of the finished tiles to --output while rendering
+    #[structopt(long, default_value = "10")]
+    flush_interval: u64,
+
+    /// Continue the render saved in this checkpoint, with its scene and settings
+    #[structopt(long, parse(from_os_str))]
+    resume: Option<PathBuf>,
+
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(StructOpt, Debug)]
+enum Command {
+    /// Hand the tiles of this render out to worker processes instead of rendering them here
+    Serve {
+        /// Address to listen on: tcp:host:port or unix:path
+        #[structopt(long)]
+        listen: Address,
+    },
+    /// Render tiles for a coordinator started with serve
+    Worker {
+        /// Address of the coordinator: tcp:host:port or unix:path
+        #[structopt(long)]
+        connect: Address,
+
+        /// Connections to render on at once, one per CPU by default
+        #[structopt(long)]
+        threads: Option<usize>,
+    },
+    /// Combine checkpoints of renders with different seeds into one image, weighted by sample count
+    Merge {
+        /// Image to write: .png, .ppm, .hdr or .exr
+        #[structopt(short, long, parse(from_os_str))]
+        output: PathBuf,
+
+        #[structopt(parse(from_os_str), required = true)]
+        checkpoints: Vec<PathBuf>,
+    },
+}
+
+// Everything --resume needs to build the same scene again, kept in the
+// checkpoint as JSON.
+#[derive(Serialize, Deserialize, Debug)]
+struct SceneArgs {
+    scene: String,
+    scene_file: Option<PathBuf>,
+    aspect_ratio: f64,
+    bvh: String,
+    leaf_size: usize,
+    linear_bvh: bool,
+}
+
+fn build_scene(args: &SceneArgs, time0: f64, time1: f64, seed: u64) -> Scene {
+    // Scene
+    let scene = match &args.scene_file {
+        Some(path) => match load_scene(path, time0, time1, args.aspect_ratio) {
+            Ok(scene) => scene,
+            Err(e) => {
+                eprintln!("Problem loading scene: {}", e);
+                std::process::exit(1);
+            }
+        },
+        None => builtin_scene(&args.scene, time0, time1, args.aspect_ratio, seed),
+    };
+
+    // World
+    let world = match (args.bvh.as_str(), scene.hittables) {
+        ("sah", Hittables::BvhNode(node)) => {
+            let builder = BvhBuilder::Sah {
+                leaf_size: args.leaf_size,
+            };
+            Hittables::from(node.rebuild(time0, time1, builder))
+        }
+        (_, hittables) => hittables,
+    };
+    let world = match world {
+        Hittables::BvhNode(node) if args.linear_bvh => {
+            Hittables::from(LinearBvh::from_bvh(node, time0, time1))
+        }
+        world => world,
+    };
+    Scene {
+        hittables: world,
+        ..scene
+    }
+}
+
+fn worker(address: &Address, threads: Option<usize>) -> Result<(), std::io::Error> {
+    let threads = threads.unwrap_or_else(num_cpus::get);
+    eprintln!("Rendering for {} with {} threads", address, threads);
+    let tiles = work(address, threads, |scene, settings| {
+        let scene_args: SceneArgs =
+            serde_json::from_str(scene).expect("Coordinator sent a bad scene description");
+        let seed = settings.seed.expect("Renders from main are always seeded");
+        build_scene(&scene_args, 0.0, 1.0, seed)
+    })?;
+    eprintln!("Rendered {} tiles", tiles);
+    Ok(())
+}
+
+fn merge(checkpoints: &[PathBuf], output: &Path, tone_map: &ToneMap) -> Result<(), std::io::Error> {
+    if OutputFormat::from_path(output).is_none() {
+        eprintln!(
+            "Unknown output format {}, expected .png, .ppm, .hdr or .exr",
+            output.display()
+        );
+        std::process::exit(1);
+    }
+    let mut merged: Option<Checkpoint> = None;
+    for path in checkpoints {
+        let checkpoint = Checkpoint::read(path)?;
+        match &mut merged {
+            None => merged = Some(checkpoint),
+            Some(merged) => {
+                let (a, b) = (&merged.accumulator, &checkpoint.accumulator);
+                if (a.width, a.height) != (b.width, b.height) {
+                    eprintln!(
+                        "{} is {}x{}, expected {}x{}",
+                        path.display(),
+                        b.width,
+                        b.height,
+                        a.width,
+                        a.height
+                    );
+                    std::process::exit(1);
+                }
+                if a.seed == b.seed {
+                    eprintln!(
+                        "Warning: {} has the same seed as an earlier render, its samples add no information",
+                        path.display()
+                    );
+                }
+                merged.accumulator.merge(&checkpoint.accumulator);
+            }
+        }
+    }
+    let framebuffer = merged.unwrap().accumulator.framebuffer();
+    eprintln!(
+        "Merged {} renders, {:.1} samples per pixel",
+        checkpoints.len(),
+        framebuffer.mean_samples()
+    );
+    write_image(
+        output,
+        framebuffer.width,
+        framebuffer.height,
+        &framebuffer.pixels,
+        tone_map,
+    )
 }
 
 fn main() -> Result<(), std::io::Error> {
     let opt = Opt::from_args();
-    let aspect_ratio = opt.aspect_ratio;
-    let image_width = opt.width;
-    let image_height = ((image_width as f64) / aspect_ratio) as u64;
-    let num_pixels = image_width * image_height;
-    let samples_per_pixel = opt.samples;
-    let max_depth = opt.depth;
-
-    rayon::ThreadPoolBuilder::new()
-        .num_threads(num_cpus::get() - 1)
-        .build_global()
-        .unwrap();
+    let tone_map = ToneMap {
+        exposure: opt.exposure,
+        operator: opt.tonemap,
+        white: opt.white,
+    };
+
+    match &opt.command {
+        Some(Command::Worker { connect, threads }) => return worker(connect, *threads),
+        Some(Command::Merge {
+            output,
+            checkpoints,
+        }) => return merge(checkpoints, output, &tone_map),
+        _ => {}
+    }
+
+    let images = opt.output.iter().chain(opt.heatmap.iter());
+    for path in images.chain(opt.diagnose.iter()) {
+        if OutputFormat::from_path(path).is_none() {
+            eprintln!(
+                "Unknown output format {}, expected .png, .ppm, .hdr or .exr",
+                path.display()
+            );
+            std::process::exit(1);
+        }
+    }
+    if !opt.aovs.is_empty() && opt.output.is_none() {
+        eprintln!("--aovs needs an --output to write them next to");
+        std::process::exit(1);
+    }
 
     #[cfg(feature = "profile")]
     {
@@ -124,66 +378,227 @@ fn main() -> Result<(), std::io::Error> {
             .expect("Couldn't start");
     }
 
-    // Time
-    let (time0, time1) = (0.0, 1.0);
-
-    // Scene
-    let scene = match opt.scene.as_ref() {
-        "cornell_box" => cornell_box(time0, time1, aspect_ratio),
-        "cornell_box_sphere" => cornell_box_sphere(time0, time1, aspect_ratio),
-        "cornell_smoke" => cornell_smoke(time0, time1, aspect_ratio),
-        "next_week_final" => next_week_final(time0, time1, aspect_ratio),
-        "marble" => marble(time0, time1, aspect_ratio),
-        "noise" => noise(time0, time1, aspect_ratio),
-        "turbulence" => turbulence(time0, time1, aspect_ratio),
-        "random_world" => random_world(time0, time1, aspect_ratio),
-        "random_world_checkered" => random_world_checkered(time0, time1, aspect_ratio),
-        "random_world_earth" => random_world_earth(time0, time1, aspect_ratio),
-        "random_world_original" => random_world_original(time0, time1, aspect_ratio),
-        "rotate_test" => rotate_test(time0, time1, aspect_ratio),
-        "simple_light" => simple_light(time0, time1, aspect_ratio),
-        _ => random_world(time0, time1, aspect_ratio),
+    // Settings, from the command line or the checkpoint being resumed
+    let (scene_args, settings, accumulator) = match &opt.resume {
+        Some(path) => {
+            let checkpoint = Checkpoint::read(path).unwrap_or_else(|e| {
+                eprintln!("Problem reading checkpoint: {}", e);
+                std::process::exit(1);
+            });
+            let scene_args: SceneArgs = serde_json::from_str(&checkpoint.scene)?;
+            let settings = RenderSettings {
+                threads: num_cpus::get() - 1,
+                ..checkpoint.settings
+            };
+            eprintln!(
+                "Resuming {} at {:.1} samples per pixel",
+                path.display(),
+                checkpoint.accumulator.framebuffer().mean_samples()
+            );
+            (scene_args, settings, Some(checkpoint.accumulator))
+        }
+        None => {
+            let image_height = ((opt.width as f64) / opt.aspect_ratio) as usize;
+            // Pick a seed when none is given, it's reported below so any run
+            // can be repeated.
+            let seed = opt.seed.unwrap_or_else(rand::random);
+            let scene_args = SceneArgs {
+                scene: opt.scene.clone(),
+                scene_file: opt.scene_file.clone(),
+                aspect_ratio: opt.aspect_ratio,
+                bvh: opt.bvh.clone(),
+                leaf_size: opt.leaf_size,
+                linear_bvh: opt.linear_bvh,
+            };
+            let settings = RenderSettings {
+                width: opt.width as usize,
+                height: image_height,
+                samples_per_pixel: opt.samples,
+                adaptive: opt.adaptive.map(|threshold| Adaptive {
+                    threshold,
+                    min_samples: opt.min_samples,
+                }),
+                pass_samples: opt.pass_samples,
+                max_depth: opt.depth,
+                rr_depth: opt.rr_depth,
+                seed: Some(seed),
+                sampler: opt.sampler,
+                filter: Filter {
+                    kind: opt.filter,
+                    radius: opt
+                        .filter_radius
+                        .unwrap_or_else(|| opt.filter.default_radius()),
+                },
+                clamp: opt.clamp,
+                tile_size: opt.tile_size,
+                tile_order: opt.tile_order,
+                threads: num_cpus::get() - 1,
+            };
+            (scene_args, settings, None)
+        }
     };
-
-    // World
-    let world = scene.hittables;
-    let lights = Arc::new(scene.lights);
-
-    // Camera
-    let camera = scene.camera;
-    let background = camera.background;
+    let seed = settings.seed.expect("Renders from main are always seeded");
+    eprintln!("Seed: {}", seed);
+    let accumulator =
+        accumulator.unwrap_or_else(|| Accumulator::new(settings.width, settings.height, seed));
 
     // Progress Bar
-    let bar = ProgressBar::new(num_pixels);
+    let bar = ProgressBar::new(0);
     bar.set_style(
         ProgressStyle::default_bar()
             .template("[{elapsed_precise}] {wide_bar} {pos:>7}/{len:7} {msg}"),
     );
 
-    // Do it
-    eprintln!("Tracing rays....");
-    let pixels: Vec<Color> = (0..num_pixels)
-        .into_par_iter()
-        .progress_with(bar)
-        .map_init(SmallRng::from_entropy, |rng, i| {
-            let h = image_height - (i / image_width) - 1;
-            let w = i % image_width;
-            let mut pixel_color = color(0.0, 0.0, 0.0);
-            for _i in 0..samples_per_pixel {
-                let ur: f64 = rng.gen();
-                let vr: f64 = rng.gen();
-                let u: f64 = ((w as f64) + ur) / ((image_width - 1) as f64);
-                let v: f64 = ((h as f64) + vr) / ((image_height - 1) as f64);
-                let r = camera.get_ray(u, v, rng);
-                pixel_color += ray_color(r, background, &world, lights.clone(), max_depth, rng);
+    // Checkpoints
+    let checkpoint_path = opt.checkpoint.clone().or_else(|| opt.resume.clone());
+    let interval = Duration::from_secs(opt.checkpoint_interval);
+    let last_checkpoint = Mutex::new(Instant::now());
+    let scene_json = serde_json::to_string(&scene_args)?;
+    let checkpoint_scene = scene_json.clone();
+    let checkpoint_settings = settings.clone();
+    let save = move |accumulator: &Accumulator| {
+        let mut last = last_checkpoint.lock().unwrap();
+        if last.elapsed() < interval && !accumulator.is_done(&checkpoint_settings) {
+            return;
+        }
+        *last = Instant::now();
+        if let Some(path) = &checkpoint_path {
+            let checkpoint = Checkpoint {
+                scene: checkpoint_scene.clone(),
+                settings: checkpoint_settings.clone(),
+                accumulator: accumulator.clone(),
+            };
+            if let Err(e) = checkpoint.write(path) {
+                eprintln!("Problem writing checkpoint: {}", e);
             }
-            pixel_color
-        })
-        .collect();
+        }
+    };
+
+    // Finished tiles go into a preview that's written to --output while the
+    // rest of the image renders.
+    let preview = Mutex::new((accumulator.framebuffer(), Instant::now()));
+    let preview_path = opt.output.clone();
+    let flush_interval = Duration::from_secs(opt.flush_interval);
+    let flush = move |tile: &Tile, pixels: &Framebuffer| {
+        let path = match &preview_path {
+            Some(path) => path,
+            None => return,
+        };
+        let mut preview = preview.lock().unwrap();
+        let (framebuffer, last) = &mut *preview;
+        framebuffer.set_tile(tile, pixels);
+        if last.elapsed() < flush_interval {
+            return;
+        }
+        *last = Instant::now();
+        let (width, height) = (framebuffer.width, framebuffer.height);
+        if let Err(e) = write_image(path, width, height, &framebuffer.pixels, &tone_map) {
+            eprintln!("Problem writing preview: {}", e);
+        }
+    };
+
+    // Do it
+    let (accumulator, scene) = match &opt.command {
+        Some(Command::Serve { listen }) => {
+            let coordinator = Coordinator::bind(listen)?;
+            eprintln!("Waiting for workers on {}", coordinator.address()?);
+            let (width, height) = (settings.width, settings.height);
+            let tiles = tiles(width, height, settings.tile_size, settings.tile_order);
+            bar.set_length(tiles.len() as u64);
+            let accumulator =
+                coordinator.run(&scene_json, &settings, accumulator, |tile, pixels| {
+                    flush(tile, pixels);
+                    bar.inc(1);
+                })?;
+            save(&accumulator);
+            (accumulator, None)
+        }
+        _ => {
+            // Time
+            let (time0, time1) = (0.0, 1.0);
 
-    println!("P3\n{} {}\n255", image_width, image_height);
-    for pixel in pixels {
-        write_color(&mut io::stdout(), pixel, samples_per_pixel).expect("Unable to write data");
+            let scene = build_scene(&scene_args, time0, time1, seed);
+
+            eprintln!("Tracing rays....");
+            let progress = bar.clone();
+            let renderer = Renderer::new(settings.clone())
+                .on_progress(move |p| {
+                    progress.set_length(p.tiles as u64);
+                    progress.set_position(p.tiles_done as u64);
+                    progress.set_message(&format!("pass {}/{}", p.pass + 1, p.passes));
+                })
+                .on_pass(save)
+                .on_tile(flush);
+            let accumulator = renderer
+                .resume(&scene, accumulator)
+                .expect("Render was cancelled");
+            (accumulator, Some(scene))
+        }
+    };
+    let framebuffer = accumulator.framebuffer();
+    bar.finish();
+    if settings.adaptive.is_some() {
+        eprintln!("Mean samples per pixel: {:.1}", framebuffer.mean_samples());
+    }
+    if let Some(path) = &opt.heatmap {
+        write_image(
+            path,
+            framebuffer.width,
+            framebuffer.height,
+            &framebuffer.sample_heatmap(),
+            &ToneMap::default(),
+        )?;
+    }
+
+    let needs_scene = !opt.aovs.is_empty() || opt.denoise || opt.diagnose.is_some();
+    let scene = match scene {
+        None if needs_scene => Some(build_scene(&scene_args, 0.0, 1.0, seed)),
+        scene => scene,
+    };
+    if let (Some(path), Some(scene)) = (&opt.diagnose, &scene) {
+        eprintln!("Diagnosing....");
+        let renderer = Renderer::new(settings.clone());
+        let diagnostics = renderer.diagnose(scene, seed, &framebuffer.samples);
+        eprintln!("{}", diagnostics);
+        let (width, height) = (framebuffer.width, framebuffer.height);
+        let mask = diagnostics.mask();
+        write_image(path, width, height, &mask, &ToneMap::default())?;
+    }
+    let aovs = match &scene {
+        Some(scene) if !opt.aovs.is_empty() || opt.denoise => {
+            eprintln!("Rendering AOVs....");
+            let renderer = Renderer::new(settings.clone());
+            Some(renderer.render_aovs(scene, seed, opt.aov_samples))
+        }
+        _ => None,
+    };
+    let pixels = match &aovs {
+        Some(aovs) if opt.denoise => {
+            eprintln!("Denoising....");
+            let variance = accumulator.variance();
+            Denoiser::default().denoise(&framebuffer.pixels, aovs, Some(&variance))
+        }
+        _ => framebuffer.pixels.clone(),
+    };
+
+    match (&opt.output, &aovs) {
+        (Some(path), Some(aovs)) => {
+            aovs.write(path, &pixels, &opt.aovs, &tone_map)?;
+        }
+        (Some(path), None) => {
+            write_image(
+                path,
+                framebuffer.width,
+                framebuffer.height,
+                &pixels,
+                &tone_map,
+            )?;
+        }
+        (None, _) => {
+            let mut out = io::BufWriter::new(io::stdout());
+            let (width, height) = (framebuffer.width, framebuffer.height);
+            write_ppm(&mut out, width, height, &pixels, &tone_map)?;
+        }
     }
 
     eprintln!("Done!\n");
@@ -1,9 +1,8 @@
 use crate::color::Color;
 use crate::ray::Ray;
+use crate::sampler::{Sampler, SamplerType};
 use crate::util::*;
 use crate::vec::Vec3;
-use rand::prelude::*;
-use rand::rngs::SmallRng;
 
 #[derive(Debug)]
 pub struct CameraConfig {
@@ -62,8 +61,8 @@ impl Camera {
         }
     }
 
-    pub fn get_ray(&self, s: f64, t: f64, rng: &mut SmallRng) -> Ray {
-        let rd = self.lens_radius * Vec3::random_in_unit_disk(rng);
+    pub fn get_ray(&self, s: f64, t: f64, sampler: &mut SamplerType) -> Ray {
+        let rd = self.lens_radius * sample_in_unit_disk(sampler.get_2d());
         let offset = self.u * rd.x + self.v * rd.y;
         Ray {
             origin: self.origin + offset,
@@ -71,10 +70,11 @@ impl Camera {
                 - self.origin
                 - offset,
             time: if self.time0 != self.time1 {
-                rng.gen_range(self.time0, self.time1)
+                self.time0 + sampler.get_1d() * (self.time1 - self.time0)
             } else {
                 self.time0
             },
+            wavelength: None,
         }
     }
 }
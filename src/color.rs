@@ -1,6 +1,5 @@
 use rand::prelude::*;
 use rand::rngs::SmallRng;
-use std::io::{self, Write};
 use std::ops;
 
 use crate::util::clamp;
@@ -92,31 +91,39 @@ impl Color {
             b: rng.gen_range(min, max),
         }
     }
+
+    // Rec. 709 relative luminance of a linear color.
+    pub fn luminance(&self) -> f64 {
+        0.2126 * self.r + 0.7152 * self.g + 0.0722 * self.b
+    }
 }
 
-pub fn write_color(_out: &mut impl Write, color: Color, samples_per_pixel: u64) -> io::Result<()> {
-    let r = if color.r.is_nan() || color.r.is_infinite() {
-        0.0
-    } else {
-        color.r
+// Encode a display color in [0, 1] with the sRGB transfer function and
+// quantize it to 8 bits per channel.
+pub fn to_rgb8(color: Color) -> [u8; 3] {
+    let encode = |x: f64| {
+        let x = if x.is_nan() || x.is_infinite() {
+            0.0
+        } else {
+            x
+        };
+        (255.0 * linear_to_srgb(clamp(x, 0.0, 1.0))).round() as u8
     };
-    let g = if color.g.is_nan() || color.g.is_infinite() {
-        0.0
+    [encode(color.r), encode(color.g), encode(color.b)]
+}
+
+pub fn linear_to_srgb(x: f64) -> f64 {
+    if x <= 0.0031308 {
+        12.92 * x
     } else {
-        color.g
-    };
-    let b = if color.b.is_nan() || color.b.is_infinite() {
-        0.0
+        1.055 * x.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+pub fn srgb_to_linear(x: f64) -> f64 {
+    if x <= 0.04045 {
+        x / 12.92
     } else {
-        color.b
-    };
-    let color = Color { r: r, g: g, b: b };
-    let scale = 1.0 / (samples_per_pixel as f64);
-    let c = color * scale;
-    let ir = (255.999 * clamp(f64::sqrt(c.r), 0.0, 0.999)) as u64;
-    let ig = (255.999 * clamp(f64::sqrt(c.g), 0.0, 0.999)) as u64;
-    let ib = (255.999 * clamp(f64::sqrt(c.b), 0.0, 0.999)) as u64;
-    let out = format!("{} {} {}\n", ir, ig, ib);
-    _out.write_all(out.as_bytes())?;
-    Ok(())
+        ((x + 0.055) / 1.055).powf(2.4)
+    }
 }
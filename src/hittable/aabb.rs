@@ -33,6 +33,15 @@ impl Aabb {
             maximum: large,
         }
     }
+
+    pub fn surface_area(&self) -> f64 {
+        let d = self.maximum - self.minimum;
+        2.0 * (d.x * d.y + d.y * d.z + d.z * d.x)
+    }
+
+    pub fn centroid(&self) -> Vec3 {
+        0.5 * (self.minimum + self.maximum)
+    }
 }
 
 impl Default for Aabb {
@@ -1,6 +1,6 @@
 use crate::hittable::{
-    aabb::Aabb, hittable_list::HittableList, rect::XyRect, rect::XzRect, rect::YzRect, HitRecord,
-    Hittable, Hittables,
+    aabb::Aabb, hittable_list::HittableList, object_id, rect::XyRect, rect::XzRect, rect::YzRect,
+    HitRecord, Hittable, Hittables,
 };
 use crate::material::MaterialType;
 use crate::ray::Ray;
@@ -37,7 +37,11 @@ impl Box3D {
 
 impl Hittable for Box3D {
     fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, rng: &mut SmallRng) -> Option<HitRecord> {
-        self.sides.hit(ray, t_min, t_max, rng)
+        let hit = self.sides.hit(ray, t_min, t_max, rng)?;
+        Some(HitRecord {
+            object: object_id(self),
+            ..hit
+        })
     }
 
     fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<Aabb> {
@@ -1,8 +1,17 @@
-use crate::hittable::{aabb::Aabb, hittable_list::HittableList, HitRecord, Hittable, Hittables};
+use crate::hittable::{
+    aabb::Aabb,
+    hittable_list::HittableList,
+    linear_bvh::LinearBvh,
+    rotate::{RotateX, RotateY, RotateZ},
+    translate::Translate,
+    triangle::TriangleMesh,
+    HitRecord, Hittable, Hittables,
+};
 use crate::ray::Ray;
-use rand::prelude::*;
+use crate::vec::Vec3;
 use rand::rngs::SmallRng;
 use std::cmp::Ordering;
+use std::sync::Arc;
 
 #[derive(Debug, Clone)]
 pub struct BvhNode {
@@ -11,35 +20,142 @@ pub struct BvhNode {
     pub right: Box<Hittables>,
 }
 
+// How a BvhNode splits its objects.
+//
+// Median sorts on a random axis and splits the list in half. Sah bins object
+// centroids along every axis and picks the split with the lowest surface area
+// heuristic cost, stopping once a leaf of at most `leaf_size` objects is
+// cheaper than splitting further.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum BvhBuilder {
+    #[default]
+    Median,
+    Sah {
+        leaf_size: usize,
+    },
+}
+
+// Relative cost of visiting a node vs intersecting an object.
+const SAH_TRAVERSAL_COST: f64 = 1.0;
+const SAH_INTERSECTION_COST: f64 = 1.0;
+const SAH_BINS: usize = 16;
+
+// Nodes and objects tested while tracing rays through a BVH.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BvhStats {
+    pub nodes: usize,
+    pub objects: usize,
+}
+
 impl BvhNode {
     pub fn new(hl: HittableList, time0: f64, time1: f64) -> BvhNode {
-        let mut rng = SmallRng::from_entropy();
-        let mut objects = hl.hittables.clone();
-        let axis = rng.gen::<u32>() % 3;
-        objects.sort_by(|a, b| box_compare(a, b, axis));
-        return BvhNode::_new(&objects, time0, time1, &mut rng);
+        BvhNode::build(hl, time0, time1, BvhBuilder::Median)
+    }
+
+    pub fn build(hl: HittableList, time0: f64, time1: f64, builder: BvhBuilder) -> BvhNode {
+        match builder {
+            BvhBuilder::Median => {
+                let mut objects = hl.hittables.clone();
+                // Sort along the longest axis rather than a random one, so the
+                // tree, and the order media consume random numbers in while
+                // tracing through it, is the same every run.
+                let bbox = objects
+                    .iter()
+                    .map(|h| {
+                        h.bounding_box(time0, time1)
+                            .expect("Bounding box doesn't exist")
+                    })
+                    .fold(None, |acc, b| Some(merge(acc, b)))
+                    .expect("BvhNode needs at least one object");
+                let d = bbox.maximum - bbox.minimum;
+                let axis = if d.x >= d.y && d.x >= d.z {
+                    0
+                } else if d.y >= d.z {
+                    1
+                } else {
+                    2
+                };
+                objects.sort_by(|a, b| box_compare(a, b, axis));
+                BvhNode::_new(&objects, time0, time1)
+            }
+            BvhBuilder::Sah { leaf_size } => {
+                let objects = hl
+                    .hittables
+                    .into_iter()
+                    .map(|h| match h.bounding_box(time0, time1) {
+                        Some(bbox) => (h, bbox),
+                        None => panic!("Bounding box doesn't exist"),
+                    })
+                    .collect();
+                match BvhNode::sah(objects, leaf_size.max(1)) {
+                    Hittables::BvhNode(node) => node,
+                    leaf => BvhNode::leaf(leaf, time0, time1),
+                }
+            }
+        }
+    }
+
+    // Rebuild with a different builder. Nested BvhNodes and leaf lists are
+    // flattened, so the new tree is built over all of the objects at once, and
+    // BVHs inside transforms and meshes are rebuilt in place.
+    pub fn rebuild(&self, time0: f64, time1: f64, builder: BvhBuilder) -> BvhNode {
+        let mut objects = HittableList {
+            hittables: Vec::new(),
+        };
+        self.collect_objects(&mut objects);
+        let objects = HittableList {
+            hittables: objects
+                .hittables
+                .iter()
+                .map(|h| rebuild_object(h, time0, time1, builder))
+                .collect(),
+        };
+        BvhNode::build(objects, time0, time1, builder)
+    }
+
+    fn collect_objects(&self, objects: &mut HittableList) {
+        for child in [&self.left, &self.right].iter() {
+            match child.as_ref() {
+                Hittables::BvhNode(node) => node.collect_objects(objects),
+                Hittables::HittableList(list) => {
+                    for h in list.hittables.iter() {
+                        match h {
+                            Hittables::BvhNode(node) => node.collect_objects(objects),
+                            _ => objects.add(h.clone()),
+                        }
+                    }
+                }
+                Hittables::LinearBvh(bvh) => objects.hittables.extend(bvh.objects.iter().cloned()),
+                h => objects.add(h.clone()),
+            }
+        }
     }
 
-    fn _new(objects: &Vec<Hittables>, time0: f64, time1: f64, rng: &mut SmallRng) -> BvhNode {
+    // A node with a single child; the empty right side is never hit.
+    fn leaf(object: Hittables, time0: f64, time1: f64) -> BvhNode {
+        BvhNode {
+            bbox: object
+                .bounding_box(time0, time1)
+                .expect("Bounding box doesn't exist"),
+            left: Box::new(object),
+            right: Box::new(Hittables::from(HittableList {
+                hittables: Vec::new(),
+            })),
+        }
+    }
+
+    fn _new(objects: &Vec<Hittables>, time0: f64, time1: f64) -> BvhNode {
         //eprintln!("length {:?} :: {:?}\n", objects.len(), objects);
         let (left, right) = match objects.len() {
-            1 => (objects[0].clone(), objects[0].clone()),
+            1 => {
+                return BvhNode::leaf(objects[0].clone(), time0, time1);
+            }
             2 => (objects[0].clone(), objects[1].clone()),
             _ => {
                 let midpoint = objects.len() / 2;
                 (
-                    Hittables::from(BvhNode::_new(
-                        &objects[..midpoint].to_vec(),
-                        time0,
-                        time1,
-                        rng,
-                    )),
-                    Hittables::from(BvhNode::_new(
-                        &objects[midpoint..].to_vec(),
-                        time0,
-                        time1,
-                        rng,
-                    )),
+                    Hittables::from(BvhNode::_new(&objects[..midpoint].to_vec(), time0, time1)),
+                    Hittables::from(BvhNode::_new(&objects[midpoint..].to_vec(), time0, time1)),
                 )
             }
         };
@@ -57,6 +173,153 @@ impl BvhNode {
             right: Box::new(right),
         }
     }
+
+    // Returns a BvhNode, or the leaf itself when it isn't worth splitting.
+    fn sah(mut objects: Vec<(Hittables, Aabb)>, leaf_size: usize) -> Hittables {
+        let bbox = objects
+            .iter()
+            .map(|(_, b)| *b)
+            .fold(objects[0].1, Aabb::surrounding_box);
+        let n = objects.len();
+        if n == 1 {
+            return objects.pop().unwrap().0;
+        }
+
+        let mut centroid_min = objects[0].1.centroid();
+        let mut centroid_max = centroid_min;
+        for (_, b) in objects.iter() {
+            let c = b.centroid();
+            centroid_min = vec_min(centroid_min, c);
+            centroid_max = vec_max(centroid_max, c);
+        }
+
+        // Best split as (cost, axis, number of bins on the left).
+        let mut best: Option<(f64, usize, usize)> = None;
+        for axis in 0..3 {
+            let lo = component(centroid_min, axis);
+            let extent = component(centroid_max, axis) - lo;
+            if extent <= 0.0 {
+                continue;
+            }
+            let mut counts = [0usize; SAH_BINS];
+            let mut bounds: [Option<Aabb>; SAH_BINS] = [None; SAH_BINS];
+            for (_, b) in objects.iter() {
+                let i = bin(component(b.centroid(), axis), lo, extent);
+                counts[i] += 1;
+                bounds[i] = Some(merge(bounds[i], *b));
+            }
+            // Sweep from the right to get the area and count right of each split.
+            let mut right_area = [0.0; SAH_BINS];
+            let mut right_count = [0usize; SAH_BINS];
+            let mut acc: Option<Aabb> = None;
+            let mut count = 0;
+            for i in (1..SAH_BINS).rev() {
+                if let Some(b) = bounds[i] {
+                    acc = Some(merge(acc, b));
+                }
+                count += counts[i];
+                right_area[i] = acc.map_or(0.0, |b| b.surface_area());
+                right_count[i] = count;
+            }
+            let mut acc: Option<Aabb> = None;
+            let mut count = 0;
+            for i in 1..SAH_BINS {
+                if let Some(b) = bounds[i - 1] {
+                    acc = Some(merge(acc, b));
+                }
+                count += counts[i - 1];
+                if count == 0 || right_count[i] == 0 {
+                    continue;
+                }
+                let left_area = acc.map_or(0.0, |b| b.surface_area());
+                let cost = SAH_TRAVERSAL_COST
+                    + SAH_INTERSECTION_COST
+                        * (left_area * count as f64 + right_area[i] * right_count[i] as f64)
+                        / bbox.surface_area();
+                if best.is_none_or(|(c, _, _)| cost < c) {
+                    best = Some((cost, axis, i));
+                }
+            }
+        }
+
+        let leaf_cost = SAH_INTERSECTION_COST * n as f64;
+        let split = match best {
+            Some((cost, _, _)) if n <= leaf_size && leaf_cost <= cost => None,
+            Some((_, axis, i)) => Some((axis, i)),
+            None if n <= leaf_size => None,
+            // Every centroid is in the same spot, fall back to splitting the list.
+            None => Some((0, 0)),
+        };
+        let (axis, i) = match split {
+            Some(split) => split,
+            None => {
+                return Hittables::from(HittableList {
+                    hittables: objects.into_iter().map(|(h, _)| h).collect(),
+                })
+            }
+        };
+
+        let right = if i == 0 {
+            objects.split_off(n / 2)
+        } else {
+            let lo = component(centroid_min, axis);
+            let extent = component(centroid_max, axis) - lo;
+            let (left, right): (Vec<_>, Vec<_>) = objects
+                .into_iter()
+                .partition(|(_, b)| bin(component(b.centroid(), axis), lo, extent) < i);
+            objects = left;
+            right
+        };
+        let left = BvhNode::sah(objects, leaf_size);
+        let right = BvhNode::sah(right, leaf_size);
+        Hittables::from(BvhNode {
+            bbox,
+            left: Box::new(left),
+            right: Box::new(right),
+        })
+    }
+
+    // Trace a ray like `hit` does, counting the work done along the way.
+    pub fn traversal_stats(
+        &self,
+        ray: &Ray,
+        t_min: f64,
+        t_max: f64,
+        rng: &mut SmallRng,
+        stats: &mut BvhStats,
+    ) {
+        stats.nodes += 1;
+        if self.bbox.hit(ray, t_min, t_max, rng).is_none() {
+            return;
+        }
+        for child in [&self.left, &self.right].iter() {
+            match child.as_ref() {
+                Hittables::BvhNode(node) => node.traversal_stats(ray, t_min, t_max, rng, stats),
+                Hittables::HittableList(list) => stats.objects += list.hittables.len(),
+                _ => stats.objects += 1,
+            }
+        }
+    }
+
+    // Expected cost of tracing a ray through the tree, by the surface area
+    // heuristic. Lower is better; useful to compare builders.
+    pub fn sah_cost(&self) -> f64 {
+        self.subtree_cost() / self.bbox.surface_area()
+    }
+
+    fn subtree_cost(&self) -> f64 {
+        let mut cost = SAH_TRAVERSAL_COST * self.bbox.surface_area();
+        for child in [&self.left, &self.right].iter() {
+            cost += match child.as_ref() {
+                Hittables::BvhNode(node) => node.subtree_cost(),
+                Hittables::HittableList(list) => {
+                    SAH_INTERSECTION_COST * list.hittables.len() as f64 * self.bbox.surface_area()
+                }
+                _ => SAH_INTERSECTION_COST * self.bbox.surface_area(),
+            }
+        }
+        cost
+    }
 }
 
 impl Hittable for BvhNode {
@@ -89,6 +352,72 @@ impl Hittable for BvhNode {
     }
 }
 
+fn rebuild_object(object: &Hittables, time0: f64, time1: f64, builder: BvhBuilder) -> Hittables {
+    let inner = |o: &Arc<Hittables>| Arc::new(rebuild_object(o, time0, time1, builder));
+    match object {
+        Hittables::BvhNode(node) => Hittables::from(node.rebuild(time0, time1, builder)),
+        Hittables::LinearBvh(bvh) => {
+            let objects = HittableList {
+                hittables: bvh
+                    .objects
+                    .iter()
+                    .map(|h| rebuild_object(h, time0, time1, builder))
+                    .collect(),
+            };
+            LinearBvh::new(objects, time0, time1, builder)
+        }
+        Hittables::Translate(t) => Hittables::from(Translate {
+            object: inner(&t.object),
+            ..t.clone()
+        }),
+        Hittables::RotateX(r) => Hittables::from(RotateX {
+            object: inner(&r.object),
+            ..r.clone()
+        }),
+        Hittables::RotateY(r) => Hittables::from(RotateY {
+            object: inner(&r.object),
+            ..r.clone()
+        }),
+        Hittables::RotateZ(r) => Hittables::from(RotateZ {
+            object: inner(&r.object),
+            ..r.clone()
+        }),
+        Hittables::TriangleMesh(m) => Hittables::from(TriangleMesh {
+            triangles: inner(&m.triangles),
+            ..m.clone()
+        }),
+        _ => object.clone(),
+    }
+}
+
+pub(crate) fn component(v: Vec3, axis: usize) -> f64 {
+    match axis {
+        0 => v.x,
+        1 => v.y,
+        _ => v.z,
+    }
+}
+
+fn vec_min(a: Vec3, b: Vec3) -> Vec3 {
+    Vec3::new(a.x.min(b.x), a.y.min(b.y), a.z.min(b.z))
+}
+
+fn vec_max(a: Vec3, b: Vec3) -> Vec3 {
+    Vec3::new(a.x.max(b.x), a.y.max(b.y), a.z.max(b.z))
+}
+
+fn merge(a: Option<Aabb>, b: Aabb) -> Aabb {
+    match a {
+        Some(a) => Aabb::surrounding_box(a, b),
+        None => b,
+    }
+}
+
+fn bin(c: f64, lo: f64, extent: f64) -> usize {
+    let i = ((c - lo) / extent * SAH_BINS as f64) as usize;
+    i.min(SAH_BINS - 1)
+}
+
 pub fn box_compare(a: &Hittables, b: &Hittables, axis: u32) -> Ordering {
     // X: axis == 0
     // Y: axis == 1
@@ -107,3 +436,59 @@ pub fn box_compare(a: &Hittables, b: &Hittables, axis: u32) -> Ordering {
         Ordering::Greater
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittable::sphere::Sphere;
+    use crate::material::MaterialType;
+    use crate::vec::vec3;
+    use rand::prelude::*;
+
+    // A big ground sphere and a grid of small ones, like random_world.
+    fn spheres() -> HittableList {
+        let mat = Arc::new(MaterialType::default());
+        let mut list = HittableList {
+            hittables: Vec::new(),
+        };
+        list.add(Sphere::new(vec3(0.0, -1000.0, 0.0), 1000.0, mat.clone()));
+        for a in -10..10 {
+            for b in -10..10 {
+                let center = vec3(a as f64, 0.2, b as f64);
+                list.add(Sphere::new(center, 0.2, mat.clone()));
+            }
+        }
+        list
+    }
+
+    #[test]
+    fn sah_finds_the_same_hits() {
+        let median = BvhNode::build(spheres(), 0.0, 1.0, BvhBuilder::Median);
+        let sah = median.rebuild(0.0, 1.0, BvhBuilder::Sah { leaf_size: 4 });
+        let mut rng = SmallRng::seed_from_u64(0);
+        for _ in 0..1000 {
+            let ray = Ray {
+                origin: vec3(13.0, 2.0, 3.0),
+                direction: Vec3::random(-1.0, 1.0, &mut rng),
+                time: 0.0,
+                wavelength: None,
+            };
+            let a = median.hit(&ray, 0.001, f64::INFINITY, &mut rng);
+            let b = sah.hit(&ray, 0.001, f64::INFINITY, &mut rng);
+            assert_eq!(a.map(|h| h.t), b.map(|h| h.t));
+        }
+    }
+
+    #[test]
+    fn sah_is_cheaper_than_median() {
+        let median = BvhNode::build(spheres(), 0.0, 1.0, BvhBuilder::Median);
+        let sah = BvhNode::build(spheres(), 0.0, 1.0, BvhBuilder::Sah { leaf_size: 4 });
+        assert!(sah.sah_cost() < median.sah_cost());
+
+        let mut objects = HittableList {
+            hittables: Vec::new(),
+        };
+        sah.collect_objects(&mut objects);
+        assert_eq!(objects.hittables.len(), 401);
+    }
+}
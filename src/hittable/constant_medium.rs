@@ -1,4 +1,4 @@
-use crate::hittable::{aabb::Aabb, HitRecord, Hittable, Hittables};
+use crate::hittable::{aabb::Aabb, object_id, HitRecord, Hittable, Hittables};
 use crate::material::{isotropic::Isotropic, MaterialType};
 use crate::ray::Ray;
 use crate::texture::Texture;
@@ -73,6 +73,7 @@ impl Hittable for ConstantMedium {
             normal: vec3(1.0, 0.0, 0.0), // arbitrary
             front_face: true,            // arbitrary
             mat: self.phase_fn.clone(),
+            object: object_id(self),
         })
     }
 
@@ -1,5 +1,7 @@
 use crate::hittable::{aabb::Aabb, HitRecord, Hittable, Hittables};
 use crate::ray::Ray;
+use crate::sampler::SamplerType;
+use crate::vec::Vec3;
 use rand::rngs::SmallRng;
 
 #[derive(Debug, Clone)]
@@ -30,6 +32,7 @@ impl Hittable for FlipFace {
                     v: hit.v,
                     front_face: !hit.front_face,
                     mat: hit.mat.clone(),
+                    object: hit.object,
                 });
             }
         }
@@ -38,4 +41,12 @@ impl Hittable for FlipFace {
     fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
         self.object.bounding_box(time0, time1)
     }
+
+    fn pdf_value(&self, origin: Vec3, v: Vec3, rng: &mut SmallRng) -> f64 {
+        self.object.pdf_value(origin, v, rng)
+    }
+
+    fn random(&self, origin: Vec3, sampler: &mut SamplerType) -> Vec3 {
+        self.object.random(origin, sampler)
+    }
 }
@@ -1,7 +1,7 @@
 use crate::hittable::{aabb::Aabb, HitRecord, Hittable, Hittables};
 use crate::ray::Ray;
+use crate::sampler::{Sampler, SamplerType};
 use crate::vec::Vec3;
-use rand::prelude::*;
 use rand::rngs::SmallRng;
 
 #[derive(Debug, Clone)]
@@ -72,13 +72,16 @@ impl Hittable for HittableList {
         return sum;
     }
 
-    fn random(&self, origin: Vec3, rng: &mut SmallRng) -> Vec3 {
+    fn random(&self, origin: Vec3, sampler: &mut SamplerType) -> Vec3 {
         let sz = self.hittables.len();
         match sz {
             0 => {
                 return Vec3::new(1.0, 0.0, 0.0);
             }
-            _ => return self.hittables[rng.gen_range(0, sz)].random(origin, rng),
+            _ => {
+                let i = ((sampler.get_1d() * sz as f64) as usize).min(sz - 1);
+                return self.hittables[i].random(origin, sampler);
+            }
         }
     }
 
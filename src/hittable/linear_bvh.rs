@@ -0,0 +1,266 @@
+use crate::hittable::{
+    aabb::Aabb,
+    bvh::{component, BvhBuilder, BvhNode},
+    hittable_list::HittableList,
+    HitRecord, Hittable, Hittables,
+};
+use crate::ray::Ray;
+use rand::rngs::SmallRng;
+
+// Nodes the traversal stack holds before it spills onto the heap, which
+// only lopsided trees need.
+const STACK_SIZE: usize = 64;
+
+// A BVH flattened into an array in depth first order, as in pbrt's
+// LinearBVHNode. The first child of an interior node directly follows it and
+// `offset` points at the second; a leaf covers `count` objects from `offset`.
+#[derive(Debug, Clone, Copy)]
+pub struct LinearBvhNode {
+    pub bbox: Aabb,
+    pub offset: usize,
+    pub count: usize,
+    pub axis: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct LinearBvh {
+    pub nodes: Vec<LinearBvhNode>,
+    pub objects: Vec<Hittables>,
+}
+
+impl LinearBvh {
+    pub fn new(hl: HittableList, time0: f64, time1: f64, builder: BvhBuilder) -> Hittables {
+        let node = BvhNode::build(hl, time0, time1, builder);
+        Hittables::from(LinearBvh::from_bvh(node, time0, time1))
+    }
+
+    pub fn from_bvh(node: BvhNode, time0: f64, time1: f64) -> LinearBvh {
+        let mut bvh = LinearBvh {
+            nodes: Vec::new(),
+            objects: Vec::new(),
+        };
+        let bbox = node.bbox;
+        bvh.flatten(Hittables::from(node), bbox, time0, time1);
+        bvh
+    }
+
+    // Append `object` and everything below it.
+    fn flatten(&mut self, object: Hittables, bbox: Aabb, time0: f64, time1: f64) {
+        match object {
+            Hittables::BvhNode(node) => {
+                let mut children: Vec<(Hittables, Aabb)> = vec![*node.left, *node.right]
+                    .into_iter()
+                    .filter_map(|child| {
+                        let bbox = child.bounding_box(time0, time1)?;
+                        Some((child, bbox))
+                    })
+                    .collect();
+                if children.len() == 1 {
+                    let (child, bbox) = children.pop().unwrap();
+                    return self.flatten(child, bbox, time0, time1);
+                }
+                let (mut right, mut rbox) = children.pop().unwrap();
+                let (mut left, mut lbox) = children.pop().unwrap();
+
+                // Split along whichever axis separates the children the most,
+                // with the first child on its negative side, which is what
+                // `hit` takes to be nearer for rays going in +axis.
+                let d = rbox.centroid() - lbox.centroid();
+                let axis = (0..3)
+                    .max_by(|a, b| component(d, *a).abs().total_cmp(&component(d, *b).abs()))
+                    .unwrap();
+                if component(d, axis) < 0.0 {
+                    std::mem::swap(&mut left, &mut right);
+                    std::mem::swap(&mut lbox, &mut rbox);
+                }
+
+                let index = self.nodes.len();
+                self.nodes.push(LinearBvhNode {
+                    bbox,
+                    offset: 0,
+                    count: 0,
+                    axis,
+                });
+                self.flatten(left, lbox, time0, time1);
+                self.nodes[index].offset = self.nodes.len();
+                self.flatten(right, rbox, time0, time1);
+            }
+            Hittables::HittableList(list) => self.leaf(list.hittables, bbox),
+            object => self.leaf(vec![object], bbox),
+        }
+    }
+
+    fn leaf(&mut self, objects: Vec<Hittables>, bbox: Aabb) {
+        self.nodes.push(LinearBvhNode {
+            bbox,
+            offset: self.objects.len(),
+            count: objects.len(),
+            axis: 0,
+        });
+        self.objects.extend(objects);
+    }
+}
+
+impl Hittable for LinearBvh {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, rng: &mut SmallRng) -> Option<HitRecord> {
+        let dir_is_neg = [
+            ray.direction.x < 0.0,
+            ray.direction.y < 0.0,
+            ray.direction.z < 0.0,
+        ];
+        let mut closest = t_max;
+        let mut hit = None;
+        let mut stack = [0usize; STACK_SIZE];
+        let mut spilled = Vec::new();
+        let mut top = 0;
+        let mut current = 0;
+        loop {
+            let node = &self.nodes[current];
+            if node.bbox.hit(ray, t_min, closest, rng).is_some() {
+                if node.count > 0 {
+                    for object in self.objects[node.offset..node.offset + node.count].iter() {
+                        if let Some(rec) = object.hit(ray, t_min, closest, rng) {
+                            closest = rec.t;
+                            hit = Some(rec);
+                        }
+                    }
+                } else {
+                    // Visit the nearer child first so the farther one can be
+                    // culled by the shrunken t_max.
+                    let (near, far) = if dir_is_neg[node.axis] {
+                        (node.offset, current + 1)
+                    } else {
+                        (current + 1, node.offset)
+                    };
+                    if top < STACK_SIZE {
+                        stack[top] = far;
+                    } else {
+                        spilled.push(far);
+                    }
+                    top += 1;
+                    current = near;
+                    continue;
+                }
+            }
+            if top == 0 {
+                break;
+            }
+            top -= 1;
+            current = if top < STACK_SIZE {
+                stack[top]
+            } else {
+                spilled.pop().unwrap()
+            };
+        }
+        hit
+    }
+
+    fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<Aabb> {
+        Some(self.nodes[0].bbox)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittable::sphere::Sphere;
+    use crate::material::MaterialType;
+    use crate::vec::{vec3, Vec3};
+    use rand::prelude::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn same_hits_as_bvh_node() {
+        let mat = Arc::new(MaterialType::default());
+        let mut rng = SmallRng::seed_from_u64(0);
+        let mut list = HittableList {
+            hittables: Vec::new(),
+        };
+        for _ in 0..500 {
+            let center = Vec3::random(-10.0, 10.0, &mut rng);
+            list.add(Sphere::new(center, 0.3, mat.clone()));
+        }
+        for builder in [BvhBuilder::Median, BvhBuilder::Sah { leaf_size: 4 }].iter() {
+            let tree = BvhNode::build(list.clone(), 0.0, 1.0, *builder);
+            let linear = LinearBvh::from_bvh(tree.clone(), 0.0, 1.0);
+            assert_eq!(linear.objects.len(), 500);
+            for _ in 0..1000 {
+                let ray = Ray {
+                    origin: vec3(0.0, 0.0, 20.0),
+                    direction: Vec3::random(-1.0, 1.0, &mut rng),
+                    time: 0.0,
+                    wavelength: None,
+                };
+                let a = tree.hit(&ray, 0.001, f64::INFINITY, &mut rng);
+                let b = linear.hit(&ray, 0.001, f64::INFINITY, &mut rng);
+                assert_eq!(a.map(|h| h.t), b.map(|h| h.t));
+            }
+        }
+    }
+
+    // Near-first traversal takes the first child of every interior node to
+    // be on the negative side of its axis.
+    #[test]
+    fn first_child_is_on_the_negative_side() {
+        let mat = Arc::new(MaterialType::default());
+        let mut rng = SmallRng::seed_from_u64(1);
+        let mut list = HittableList {
+            hittables: Vec::new(),
+        };
+        for _ in 0..500 {
+            let center = Vec3::random(-10.0, 10.0, &mut rng);
+            list.add(Sphere::new(center, 0.3, mat.clone()));
+        }
+        for builder in [BvhBuilder::Median, BvhBuilder::Sah { leaf_size: 4 }].iter() {
+            let tree = BvhNode::build(list.clone(), 0.0, 1.0, *builder);
+            let linear = LinearBvh::from_bvh(tree, 0.0, 1.0);
+            for (i, node) in linear.nodes.iter().enumerate() {
+                if node.count > 0 {
+                    continue;
+                }
+                let first = linear.nodes[i + 1].bbox.centroid();
+                let second = linear.nodes[node.offset].bbox.centroid();
+                assert!(component(first, node.axis) <= component(second, node.axis));
+            }
+        }
+    }
+
+    // A tree deeper than the traversal stack, as SAH builds for objects of
+    // geometrically shrinking size, spills onto the heap.
+    #[test]
+    fn trees_deeper_than_the_stack() {
+        let mat = Arc::new(MaterialType::default());
+        let sphere = |x: f64| Sphere::new(vec3(x, 0.0, 0.0), 0.3, mat.clone());
+        let n = 3 * STACK_SIZE;
+        let mut tree = BvhNode {
+            bbox: Aabb::surrounding_box(
+                sphere(n as f64 - 2.0).bounding_box(0.0, 1.0).unwrap(),
+                sphere(n as f64 - 1.0).bounding_box(0.0, 1.0).unwrap(),
+            ),
+            left: Box::new(sphere(n as f64 - 2.0)),
+            right: Box::new(sphere(n as f64 - 1.0)),
+        };
+        for i in (0..n - 2).rev() {
+            let left = sphere(i as f64);
+            tree = BvhNode {
+                bbox: Aabb::surrounding_box(left.bounding_box(0.0, 1.0).unwrap(), tree.bbox),
+                left: Box::new(left),
+                right: Box::new(Hittables::from(tree)),
+            };
+        }
+        let linear = LinearBvh::from_bvh(tree.clone(), 0.0, 1.0);
+        let mut rng = SmallRng::seed_from_u64(2);
+        for &(x, dx) in [(-10.0, 1.0), (n as f64 + 10.0, -1.0)].iter() {
+            let ray = Ray {
+                origin: vec3(x, 0.0, 0.0),
+                direction: vec3(dx, 0.0, 0.0),
+                time: 0.0,
+                wavelength: None,
+            };
+            let a = tree.hit(&ray, 0.001, f64::INFINITY, &mut rng);
+            let b = linear.hit(&ray, 0.001, f64::INFINITY, &mut rng);
+            assert!(b.is_some());
+            assert_eq!(a.map(|h| h.t), b.map(|h| h.t));
+        }
+    }
+}
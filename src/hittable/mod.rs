@@ -5,13 +5,16 @@ use crate::hittable::{
     constant_medium::ConstantMedium,
     flip_face::FlipFace,
     hittable_list::HittableList,
+    linear_bvh::LinearBvh,
     rect::{XyRect, XzRect, YzRect},
     rotate::{RotateX, RotateY, RotateZ},
     sphere::{MovingSphere, Sphere},
     translate::Translate,
+    triangle::{Triangle, TriangleMesh},
 };
 use crate::material::MaterialType;
 use crate::ray::Ray;
+use crate::sampler::SamplerType;
 use crate::vec::Vec3;
 use enum_dispatch::enum_dispatch;
 use rand::rngs::SmallRng;
@@ -23,10 +26,12 @@ pub mod bvh;
 pub mod constant_medium;
 pub mod flip_face;
 pub mod hittable_list;
+pub mod linear_bvh;
 pub mod rect;
 pub mod rotate;
 pub mod sphere;
 pub mod translate;
+pub mod triangle;
 
 pub struct HitRecord {
     pub point: Vec3,
@@ -36,6 +41,14 @@ pub struct HitRecord {
     pub v: f64,
     pub front_face: bool,
     pub mat: Arc<MaterialType>,
+    // Which object was hit, from `object_id`.
+    pub object: usize,
+}
+
+// Tells objects apart for as long as the scene exists, by address. Not
+// stable between runs, so not an ID to save.
+pub fn object_id<T>(object: &T) -> usize {
+    object as *const T as usize
 }
 
 #[enum_dispatch]
@@ -45,7 +58,7 @@ pub trait Hittable {
     fn pdf_value(&self, _origin: Vec3, _v: Vec3, _rng: &mut SmallRng) -> f64 {
         0.0
     }
-    fn random(&self, _origin: Vec3, _rng: &mut SmallRng) -> Vec3 {
+    fn random(&self, _origin: Vec3, _sampler: &mut SamplerType) -> Vec3 {
         Vec3::new(1.0, 0.0, 0.0)
     }
     fn length(&self) -> usize {
@@ -61,12 +74,15 @@ pub enum Hittables {
     ConstantMedium,
     FlipFace,
     HittableList,
+    LinearBvh,
     MovingSphere,
     RotateX,
     RotateY,
     RotateZ,
     Sphere,
     Translate,
+    Triangle,
+    TriangleMesh,
     XyRect,
     XzRect,
     YzRect,
@@ -1,8 +1,8 @@
-use crate::hittable::{aabb::Aabb, HitRecord, Hittable, Hittables};
+use crate::hittable::{aabb::Aabb, object_id, HitRecord, Hittable, Hittables};
 use crate::material::MaterialType;
 use crate::ray::{face_normal, Ray};
+use crate::sampler::{Sampler, SamplerType};
 use crate::vec::{vec3, Vec3};
-use rand::prelude::*;
 use rand::rngs::SmallRng;
 use std::sync::Arc;
 
@@ -51,6 +51,7 @@ impl Hittable for XyRect {
             normal: normal,
             front_face: front_face,
             mat: self.mat.clone(),
+            object: object_id(self),
         })
     }
 
@@ -60,6 +61,36 @@ impl Hittable for XyRect {
             vec3(self.x1, self.y1, self.k + 0.0001),
         ))
     }
+
+    fn pdf_value(&self, origin: Vec3, v: Vec3, rng: &mut SmallRng) -> f64 {
+        let ray = Ray {
+            origin: origin,
+            direction: v,
+            time: 0.0, // arbitrary
+            wavelength: None,
+        };
+        match self.hit(&ray, 0.001, std::f64::INFINITY, rng) {
+            None => {
+                return 0.0;
+            }
+            Some(hit) => {
+                let area = (self.x1 - self.x0) * (self.y1 - self.y0);
+                let distance_squared = hit.t * hit.t * v.length_squared();
+                let cosine = f64::abs(v.dot(hit.normal) / v.length());
+                return distance_squared / (cosine * area);
+            }
+        }
+    }
+
+    fn random(&self, origin: Vec3, sampler: &mut SamplerType) -> Vec3 {
+        let (u, v) = sampler.get_2d();
+        let random_point = Vec3 {
+            x: self.x0 + u * (self.x1 - self.x0),
+            y: self.y0 + v * (self.y1 - self.y0),
+            z: self.k,
+        };
+        return random_point - origin;
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -107,6 +138,7 @@ impl Hittable for XzRect {
             normal: normal,
             front_face: front_face,
             mat: self.mat.clone(),
+            object: object_id(self),
         })
     }
 
@@ -122,6 +154,7 @@ impl Hittable for XzRect {
             origin: origin,
             direction: v,
             time: 0.0, // arbitrary
+            wavelength: None,
         };
         match self.hit(&ray, 0.001, std::f64::INFINITY, rng) {
             None => {
@@ -136,11 +169,12 @@ impl Hittable for XzRect {
         }
     }
 
-    fn random(&self, origin: Vec3, rng: &mut SmallRng) -> Vec3 {
+    fn random(&self, origin: Vec3, sampler: &mut SamplerType) -> Vec3 {
+        let (u, v) = sampler.get_2d();
         let random_point = Vec3 {
-            x: rng.gen_range(self.x0, self.x1),
+            x: self.x0 + u * (self.x1 - self.x0),
             y: self.k,
-            z: rng.gen_range(self.z0, self.z1),
+            z: self.z0 + v * (self.z1 - self.z0),
         };
         return random_point - origin;
     }
@@ -191,6 +225,7 @@ impl Hittable for YzRect {
             normal: normal,
             front_face: front_face,
             mat: self.mat.clone(),
+            object: object_id(self),
         })
     }
 
@@ -200,4 +235,34 @@ impl Hittable for YzRect {
             vec3(self.k + 0.0001, self.y1, self.z1),
         ))
     }
+
+    fn pdf_value(&self, origin: Vec3, v: Vec3, rng: &mut SmallRng) -> f64 {
+        let ray = Ray {
+            origin: origin,
+            direction: v,
+            time: 0.0, // arbitrary
+            wavelength: None,
+        };
+        match self.hit(&ray, 0.001, std::f64::INFINITY, rng) {
+            None => {
+                return 0.0;
+            }
+            Some(hit) => {
+                let area = (self.y1 - self.y0) * (self.z1 - self.z0);
+                let distance_squared = hit.t * hit.t * v.length_squared();
+                let cosine = f64::abs(v.dot(hit.normal) / v.length());
+                return distance_squared / (cosine * area);
+            }
+        }
+    }
+
+    fn random(&self, origin: Vec3, sampler: &mut SamplerType) -> Vec3 {
+        let (u, v) = sampler.get_2d();
+        let random_point = Vec3 {
+            x: self.k,
+            y: self.y0 + u * (self.y1 - self.y0),
+            z: self.z0 + v * (self.z1 - self.z0),
+        };
+        return random_point - origin;
+    }
 }
@@ -180,6 +180,7 @@ impl Hittable for RotateX {
             origin: origin,
             direction: direction,
             time: ray.time,
+            wavelength: ray.wavelength,
         };
 
         match self.object.hit(&rotated, t_min, t_max, rng) {
@@ -202,6 +203,7 @@ impl Hittable for RotateX {
                     normal: normal,
                     front_face: front_face,
                     mat: hit.mat.clone(),
+                    object: hit.object,
                 })
             }
         }
@@ -225,6 +227,7 @@ impl Hittable for RotateY {
             origin: origin,
             direction: direction,
             time: ray.time,
+            wavelength: ray.wavelength,
         };
 
         match self.object.hit(&rotated, t_min, t_max, rng) {
@@ -246,6 +249,7 @@ impl Hittable for RotateY {
                     normal: normal,
                     front_face: front_face,
                     mat: hit.mat.clone(),
+                    object: hit.object,
                 })
             }
         }
@@ -269,6 +273,7 @@ impl Hittable for RotateZ {
             origin: origin,
             direction: direction,
             time: ray.time,
+            wavelength: ray.wavelength,
         };
 
         match self.object.hit(&rotated, t_min, t_max, rng) {
@@ -290,6 +295,7 @@ impl Hittable for RotateZ {
                     normal: normal,
                     front_face: front_face,
                     mat: hit.mat.clone(),
+                    object: hit.object,
                 })
             }
         }
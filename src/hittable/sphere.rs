@@ -1,8 +1,9 @@
-use crate::hittable::{aabb::Aabb, HitRecord, Hittable, Hittables};
+use crate::hittable::{aabb::Aabb, object_id, HitRecord, Hittable, Hittables};
 use crate::material::MaterialType;
 use crate::onb::Onb;
 use crate::ray::{face_normal, Ray};
-use crate::util::random_to_sphere;
+use crate::sampler::{Sampler, SamplerType};
+use crate::util::sample_to_sphere;
 use crate::vec::{vec3, Vec3};
 use rand::rngs::SmallRng;
 use std::sync::Arc;
@@ -51,6 +52,7 @@ impl Hittable for Sphere {
                     normal: normal,
                     front_face: front_face,
                     mat: self.mat.clone(),
+                    object: object_id(self),
                 });
             }
 
@@ -70,6 +72,7 @@ impl Hittable for Sphere {
                     normal: normal,
                     front_face: front_face,
                     mat: self.mat.clone(),
+                    object: object_id(self),
                 });
             }
         }
@@ -88,6 +91,7 @@ impl Hittable for Sphere {
             origin: origin,
             direction: v,
             time: 0.0,
+            wavelength: None,
         };
         let hit = self.hit(&ray, 0.001, std::f64::INFINITY, rng);
         match hit {
@@ -104,11 +108,15 @@ impl Hittable for Sphere {
         }
     }
 
-    fn random(&self, origin: Vec3, rng: &mut SmallRng) -> Vec3 {
+    fn random(&self, origin: Vec3, sampler: &mut SamplerType) -> Vec3 {
         let direction = self.center - origin;
         let dist_squared = direction.length_squared();
         let uvw = Onb::new(&direction);
-        return uvw.local(&random_to_sphere(self.radius, dist_squared, rng));
+        return uvw.local(&sample_to_sphere(
+            self.radius,
+            dist_squared,
+            sampler.get_2d(),
+        ));
     }
 }
 
@@ -174,6 +182,7 @@ impl Hittable for MovingSphere {
                     normal: normal,
                     front_face: front_face,
                     mat: self.mat.clone(),
+                    object: object_id(self),
                 });
             }
 
@@ -193,6 +202,7 @@ impl Hittable for MovingSphere {
                     normal: normal,
                     front_face: front_face,
                     mat: self.mat.clone(),
+                    object: object_id(self),
                 });
             }
         }
@@ -25,6 +25,7 @@ impl Hittable for Translate {
             origin: ray.origin - self.offset,
             direction: ray.direction,
             time: ray.time,
+            wavelength: ray.wavelength,
         };
         match self.object.hit(&moved, t_min, t_max, rng) {
             Some(hit) => {
@@ -37,6 +38,7 @@ impl Hittable for Translate {
                     normal: normal,
                     front_face: front_face,
                     mat: hit.mat.clone(),
+                    object: hit.object,
                 })
             }
             None => None,
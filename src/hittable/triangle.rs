@@ -0,0 +1,429 @@
+use crate::hittable::{
+    aabb::Aabb, bvh::BvhNode, hittable_list::HittableList, object_id, HitRecord, Hittable,
+    Hittables,
+};
+use crate::material::MaterialType;
+use crate::ray::Ray;
+use crate::sampler::{Sampler, SamplerType};
+use crate::vec::{vec3, Vec3};
+use rand::rngs::SmallRng;
+use std::sync::Arc;
+
+// Indices into the vertex, normal and uv buffers of a MeshData for one triangle.
+// Normals and uvs are optional per face, as they are in most asset formats.
+#[derive(Debug, Clone, Copy)]
+pub struct Face {
+    pub vertices: [usize; 3],
+    pub normals: Option<[usize; 3]>,
+    pub uvs: Option<[usize; 3]>,
+}
+
+// Vertex data shared by every triangle of a mesh.
+#[derive(Debug, Clone, Default)]
+pub struct MeshData {
+    pub vertices: Vec<Vec3>,
+    pub normals: Vec<Vec3>,
+    pub uvs: Vec<(f64, f64)>,
+    pub faces: Vec<Face>,
+}
+
+impl MeshData {
+    pub fn triangle(&self, face: usize) -> (Vec3, Vec3, Vec3) {
+        let idx = self.faces[face].vertices;
+        (
+            self.vertices[idx[0]],
+            self.vertices[idx[1]],
+            self.vertices[idx[2]],
+        )
+    }
+
+    pub fn area(&self, face: usize) -> f64 {
+        let (v0, v1, v2) = self.triangle(face);
+        0.5 * (v1 - v0).cross(v2 - v0).length()
+    }
+
+    // A point uniformly distributed over the face.
+    pub fn sample_point(&self, face: usize, (u1, u2): (f64, f64)) -> Vec3 {
+        let (v0, v1, v2) = self.triangle(face);
+        let su = f64::sqrt(u1);
+        let b1 = u2 * su;
+        let b0 = 1.0 - su;
+        b0 * v0 + b1 * v1 + (1.0 - b0 - b1) * v2
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Triangle {
+    pub mesh: Arc<MeshData>,
+    pub face: usize,
+    pub mat: Arc<MaterialType>,
+}
+
+impl Triangle {
+    pub fn new(v0: Vec3, v1: Vec3, v2: Vec3, mat: Arc<MaterialType>) -> Hittables {
+        let mesh = MeshData {
+            vertices: vec![v0, v1, v2],
+            normals: Vec::new(),
+            uvs: Vec::new(),
+            faces: vec![Face {
+                vertices: [0, 1, 2],
+                normals: None,
+                uvs: None,
+            }],
+        };
+        Hittables::from(Triangle {
+            mesh: Arc::new(mesh),
+            face: 0,
+            mat,
+        })
+    }
+}
+
+impl Hittable for Triangle {
+    // Moller-Trumbore
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, _rng: &mut SmallRng) -> Option<HitRecord> {
+        let (v0, v1, v2) = self.mesh.triangle(self.face);
+        let e1 = v1 - v0;
+        let e2 = v2 - v0;
+        let pvec = ray.direction.cross(e2);
+        let det = e1.dot(pvec);
+        if f64::abs(det) < 1e-12 {
+            return None;
+        }
+        let inv_det = 1.0 / det;
+        let tvec = ray.origin - v0;
+        let b1 = tvec.dot(pvec) * inv_det;
+        if !(0.0..=1.0).contains(&b1) {
+            return None;
+        }
+        let qvec = tvec.cross(e1);
+        let b2 = ray.direction.dot(qvec) * inv_det;
+        if b2 < 0.0 || b1 + b2 > 1.0 {
+            return None;
+        }
+        let t = e2.dot(qvec) * inv_det;
+        if t < t_min || t > t_max {
+            return None;
+        }
+        let b0 = 1.0 - b1 - b2;
+        let face = &self.mesh.faces[self.face];
+
+        // The geometric normal decides which side was hit; the shading normal
+        // is flipped to agree with it.
+        let geometric = e1.cross(e2).unit_vector();
+        let front_face = ray.direction.dot(geometric) < 0.0;
+        let shading = match face.normals {
+            Some(n) => (b0 * self.mesh.normals[n[0]]
+                + b1 * self.mesh.normals[n[1]]
+                + b2 * self.mesh.normals[n[2]])
+                .unit_vector(),
+            None => geometric,
+        };
+        let shading = if shading.dot(geometric) < 0.0 {
+            -shading
+        } else {
+            shading
+        };
+        let normal = if front_face { shading } else { -shading };
+
+        let (u, v) = match face.uvs {
+            Some(uv) => {
+                let (uv0, uv1, uv2) = (
+                    self.mesh.uvs[uv[0]],
+                    self.mesh.uvs[uv[1]],
+                    self.mesh.uvs[uv[2]],
+                );
+                (
+                    b0 * uv0.0 + b1 * uv1.0 + b2 * uv2.0,
+                    b0 * uv0.1 + b1 * uv1.1 + b2 * uv2.1,
+                )
+            }
+            None => (b1, b2),
+        };
+
+        Some(HitRecord {
+            t,
+            u,
+            v,
+            point: ray.at(t),
+            normal,
+            front_face,
+            mat: self.mat.clone(),
+            // The whole mesh is one object.
+            object: object_id(&*self.mesh),
+        })
+    }
+
+    fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<Aabb> {
+        let (v0, v1, v2) = self.mesh.triangle(self.face);
+        let min = vec3(
+            v0.x.min(v1.x).min(v2.x) - 0.0001,
+            v0.y.min(v1.y).min(v2.y) - 0.0001,
+            v0.z.min(v1.z).min(v2.z) - 0.0001,
+        );
+        let max = vec3(
+            v0.x.max(v1.x).max(v2.x) + 0.0001,
+            v0.y.max(v1.y).max(v2.y) + 0.0001,
+            v0.z.max(v1.z).max(v2.z) + 0.0001,
+        );
+        Some(Aabb::new(min, max))
+    }
+
+    fn pdf_value(&self, origin: Vec3, v: Vec3, rng: &mut SmallRng) -> f64 {
+        let ray = Ray {
+            origin,
+            direction: v,
+            time: 0.0, // arbitrary
+            wavelength: None,
+        };
+        match self.hit(&ray, 0.001, f64::INFINITY, rng) {
+            None => 0.0,
+            Some(hit) => {
+                let area = self.mesh.area(self.face);
+                let distance_squared = hit.t * hit.t * v.length_squared();
+                let cosine = f64::abs(v.dot(hit.normal) / v.length());
+                distance_squared / (cosine * area)
+            }
+        }
+    }
+
+    fn random(&self, origin: Vec3, sampler: &mut SamplerType) -> Vec3 {
+        self.mesh.sample_point(self.face, sampler.get_2d()) - origin
+    }
+}
+
+// An indexed triangle mesh. The triangles are kept in their own BVH so a mesh
+// can be dropped into a scene, transformed or used as a light like any other
+// primitive.
+#[derive(Debug, Clone)]
+pub struct TriangleMesh {
+    pub mesh: Arc<MeshData>,
+    pub triangles: Arc<Hittables>,
+    pub area: f64,
+    // Running sum of triangle areas, used to pick a triangle proportional to
+    // its area when sampling the mesh as a light.
+    pub area_cdf: Vec<f64>,
+}
+
+impl TriangleMesh {
+    pub fn new(mesh: Arc<MeshData>, mat: Arc<MaterialType>, time0: f64, time1: f64) -> Hittables {
+        let mut triangles = HittableList {
+            hittables: Vec::new(),
+        };
+        let mut area_cdf = Vec::with_capacity(mesh.faces.len());
+        let mut area = 0.0;
+        for face in 0..mesh.faces.len() {
+            area += mesh.area(face);
+            area_cdf.push(area);
+            triangles.add(Hittables::from(Triangle {
+                mesh: mesh.clone(),
+                face,
+                mat: mat.clone(),
+            }));
+        }
+        let triangles = if triangles.hittables.is_empty() {
+            Hittables::from(triangles)
+        } else {
+            Hittables::from(BvhNode::new(triangles, time0, time1))
+        };
+        Hittables::from(TriangleMesh {
+            mesh,
+            triangles: Arc::new(triangles),
+            area,
+            area_cdf,
+        })
+    }
+}
+
+impl Hittable for TriangleMesh {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, rng: &mut SmallRng) -> Option<HitRecord> {
+        self.triangles.hit(ray, t_min, t_max, rng)
+    }
+
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
+        self.triangles.bounding_box(time0, time1)
+    }
+
+    fn pdf_value(&self, origin: Vec3, v: Vec3, rng: &mut SmallRng) -> f64 {
+        let ray = Ray {
+            origin,
+            direction: v,
+            time: 0.0, // arbitrary
+            wavelength: None,
+        };
+        // `random` may have picked any point along the ray, not only the
+        // nearest: the back of a closed mesh, or a part hidden behind
+        // another, so every hit adds its density.
+        let length = v.length();
+        let mut pdf = 0.0;
+        let mut t_min = 0.001;
+        while let Some(hit) = self.hit(&ray, t_min, f64::INFINITY, rng) {
+            let distance_squared = hit.t * hit.t * v.length_squared();
+            let cosine = f64::abs(v.dot(hit.normal) / length);
+            pdf += distance_squared / (cosine * self.area);
+            t_min = hit.t + 0.001 / length;
+        }
+        pdf
+    }
+
+    fn random(&self, origin: Vec3, sampler: &mut SamplerType) -> Vec3 {
+        if self.area_cdf.is_empty() {
+            return Vec3::new(1.0, 0.0, 0.0);
+        }
+        let target = sampler.get_1d() * self.area;
+        let face = self
+            .area_cdf
+            .partition_point(|&a| a <= target)
+            .min(self.area_cdf.len() - 1);
+        self.mesh.sample_point(face, sampler.get_2d()) - origin
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampler::independent::IndependentSampler;
+    use crate::util::sample_unit_sphere;
+    use rand::SeedableRng;
+
+    fn quad() -> Arc<MeshData> {
+        Arc::new(MeshData {
+            vertices: vec![
+                vec3(0.0, 0.0, 0.0),
+                vec3(1.0, 0.0, 0.0),
+                vec3(1.0, 1.0, 0.0),
+                vec3(0.0, 1.0, 0.0),
+            ],
+            normals: vec![vec3(0.0, 0.0, 1.0), vec3(1.0, 0.0, 1.0)],
+            uvs: vec![(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)],
+            faces: vec![
+                Face {
+                    vertices: [0, 1, 2],
+                    normals: Some([0, 1, 1]),
+                    uvs: Some([0, 1, 2]),
+                },
+                Face {
+                    vertices: [0, 2, 3],
+                    normals: None,
+                    uvs: Some([0, 2, 3]),
+                },
+            ],
+        })
+    }
+
+    fn ray(x: f64, y: f64) -> Ray {
+        Ray {
+            origin: vec3(x, y, 1.0),
+            direction: vec3(0.0, 0.0, -1.0),
+            time: 0.0,
+            wavelength: None,
+        }
+    }
+
+    #[test]
+    fn triangle_hit_and_miss() {
+        let mut rng = SmallRng::seed_from_u64(0);
+        let tri = Triangle::new(
+            vec3(0.0, 0.0, 0.0),
+            vec3(1.0, 0.0, 0.0),
+            vec3(0.0, 1.0, 0.0),
+            Arc::new(MaterialType::default()),
+        );
+        let hit = tri.hit(&ray(0.25, 0.25), 0.001, f64::INFINITY, &mut rng);
+        let hit = hit.expect("ray should hit the triangle");
+        assert!((hit.t - 1.0).abs() < 1e-9);
+        assert!(hit.front_face);
+        assert!((hit.u - 0.25).abs() < 1e-9);
+        assert!((hit.v - 0.25).abs() < 1e-9);
+        assert!(tri
+            .hit(&ray(0.75, 0.75), 0.001, f64::INFINITY, &mut rng)
+            .is_none());
+    }
+
+    #[test]
+    fn mesh_interpolates_uvs_and_normals() {
+        let mut rng = SmallRng::seed_from_u64(0);
+        let mesh = TriangleMesh::new(quad(), Arc::new(MaterialType::default()), 0.0, 1.0);
+        let hit = mesh
+            .hit(&ray(0.25, 0.75), 0.001, f64::INFINITY, &mut rng)
+            .expect("ray should hit the mesh");
+        assert!((hit.u - 0.25).abs() < 1e-9);
+        assert!((hit.v - 0.75).abs() < 1e-9);
+        assert!((hit.normal.z - 1.0).abs() < 1e-9);
+
+        let hit = mesh
+            .hit(&ray(0.9, 0.1), 0.001, f64::INFINITY, &mut rng)
+            .expect("ray should hit the mesh");
+        assert!(hit.normal.x > 0.0);
+        assert!((hit.normal.length() - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn mesh_light_sampling() {
+        let mut rng = SmallRng::seed_from_u64(0);
+        let mut sampler = IndependentSampler::new(0);
+        let mesh = TriangleMesh::new(quad(), Arc::new(MaterialType::default()), 0.0, 1.0);
+        let origin = vec3(0.5, 0.5, 2.0);
+        for i in 0..16 {
+            sampler.start_pixel_sample(0, 0, i);
+            let dir = mesh.random(origin, &mut sampler);
+            assert!(mesh.pdf_value(origin, dir, &mut rng) > 0.0);
+        }
+        // Straight down, 2 units away from a unit square.
+        let pdf = mesh.pdf_value(origin, vec3(0.0, 0.0, -1.0), &mut rng);
+        assert!((pdf - 4.0).abs() < 1e-9);
+    }
+
+    // A closed mesh is hit twice along every ray through it, and sampling
+    // it as a light picks points on both sides, so its pdf over all
+    // directions must still integrate to one.
+    #[test]
+    fn closed_mesh_light_pdf_integrates_to_one() {
+        let mut vertices = Vec::new();
+        for i in 0..8 {
+            let bit = |b: usize| if i & b == 0 { 0.0 } else { 1.0 };
+            vertices.push(vec3(bit(1), bit(2), bit(4)));
+        }
+        let quads = [
+            [0, 1, 3, 2],
+            [4, 6, 7, 5],
+            [0, 4, 5, 1],
+            [2, 3, 7, 6],
+            [0, 2, 6, 4],
+            [1, 5, 7, 3],
+        ];
+        let face = |vertices| Face {
+            vertices,
+            normals: None,
+            uvs: None,
+        };
+        let faces = quads
+            .iter()
+            .flat_map(|q| vec![face([q[0], q[1], q[2]]), face([q[0], q[2], q[3]])])
+            .collect();
+        let cube = Arc::new(MeshData {
+            vertices,
+            faces,
+            ..MeshData::default()
+        });
+        let light = TriangleMesh::new(cube, Arc::new(MaterialType::default()), 0.0, 1.0);
+
+        let mut rng = SmallRng::seed_from_u64(0);
+        let mut sampler = IndependentSampler::new(0);
+        let origin = vec3(2.0, 1.5, 3.0);
+        let n = 200_000;
+        let mut total = 0.0;
+        for i in 0..n {
+            sampler.start_pixel_sample(0, 0, i);
+            let direction = sample_unit_sphere(sampler.get_2d());
+            total += light.pdf_value(origin, direction, &mut rng);
+        }
+        let total = total * 4.0 * std::f64::consts::PI / n as f64;
+        assert!((total - 1.0).abs() < 0.05, "{}", total);
+        for i in 0..16 {
+            sampler.start_pixel_sample(0, 1, i);
+            let direction = light.random(origin, &mut sampler);
+            assert!(light.pdf_value(origin, direction, &mut rng) > 0.0);
+        }
+    }
+}
@@ -1,11 +1,16 @@
 pub mod camera;
 pub mod color;
 pub mod hittable;
+pub mod loader;
 pub mod material;
 pub mod onb;
+pub mod output;
 pub mod pdf;
 pub mod ray;
+pub mod render;
+pub mod sampler;
 pub mod scenes;
+pub mod spectrum;
 pub mod texture;
 pub mod util;
 pub mod vec;
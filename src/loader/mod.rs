@@ -0,0 +1,87 @@
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub mod obj;
+pub mod scene;
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io {
+        path: PathBuf,
+        error: io::Error,
+    },
+    Parse {
+        path: PathBuf,
+        line: usize,
+        column: Option<usize>,
+        message: String,
+    },
+    // Well formed input that doesn't describe a valid scene; `field` is the
+    // path to the offending value, e.g. `objects[2].material`.
+    Invalid {
+        path: PathBuf,
+        field: String,
+        message: String,
+    },
+}
+
+impl LoadError {
+    pub fn io(path: &Path, error: io::Error) -> LoadError {
+        LoadError::Io {
+            path: path.to_path_buf(),
+            error,
+        }
+    }
+
+    pub fn parse(path: &Path, line: usize, message: impl Into<String>) -> LoadError {
+        LoadError::Parse {
+            path: path.to_path_buf(),
+            line,
+            column: None,
+            message: message.into(),
+        }
+    }
+
+    pub fn invalid(path: &Path, field: &str, message: impl Into<String>) -> LoadError {
+        LoadError::Invalid {
+            path: path.to_path_buf(),
+            field: field.to_string(),
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Io { path, error } => write!(f, "{}: {}", path.display(), error),
+            LoadError::Parse {
+                path,
+                line,
+                column: None,
+                message,
+            } => write!(f, "{}:{}: {}", path.display(), line, message),
+            LoadError::Parse {
+                path,
+                line,
+                column: Some(column),
+                message,
+            } => write!(f, "{}:{}:{}: {}", path.display(), line, column, message),
+            LoadError::Invalid {
+                path,
+                field,
+                message,
+            } => write!(f, "{}: {}: {}", path.display(), field, message),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LoadError::Io { error, .. } => Some(error),
+            LoadError::Parse { .. } | LoadError::Invalid { .. } => None,
+        }
+    }
+}
@@ -0,0 +1,578 @@
+use crate::color::{color, Color};
+use crate::hittable::{
+    bvh::BvhNode,
+    hittable_list::HittableList,
+    triangle::{Face, MeshData, TriangleMesh},
+    Hittables,
+};
+use crate::loader::LoadError;
+use crate::material::{
+    dielectric::Dielectric, diffuse::Diffuse, lambertian::Lambertian, metal::Metal, MaterialType,
+};
+use crate::texture::{image::ImageTexture, solidcolor::SolidColor, Texture};
+use crate::vec::{vec3, Vec3};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+// Geometry imported from a Wavefront OBJ file. Every `usemtl` group becomes
+// its own TriangleMesh; groups with an emissive material are also collected
+// in `lights` so they can be importance sampled.
+#[derive(Debug, Clone)]
+pub struct ObjModel {
+    pub hittables: HittableList,
+    pub lights: HittableList,
+}
+
+impl ObjModel {
+    pub fn to_bvh(&self, time0: f64, time1: f64) -> Hittables {
+        if self.hittables.hittables.is_empty() {
+            return Hittables::from(self.hittables.clone());
+        }
+        Hittables::from(BvhNode::new(self.hittables.clone(), time0, time1))
+    }
+}
+
+// The subset of an MTL material we understand.
+#[derive(Debug, Clone)]
+pub struct MtlMaterial {
+    pub kd: Color,
+    pub ks: Color,
+    pub ke: Color,
+    pub ns: f64,
+    pub ni: f64,
+    pub dissolve: f64,
+    pub illum: Option<u32>,
+    // Read when the MTL is, so a missing or broken image is an error there.
+    pub map_kd: Option<Texture>,
+}
+
+impl Default for MtlMaterial {
+    fn default() -> MtlMaterial {
+        MtlMaterial {
+            kd: color(0.8, 0.8, 0.8),
+            ks: color(0.0, 0.0, 0.0),
+            ke: color(0.0, 0.0, 0.0),
+            ns: 0.0,
+            ni: 1.0,
+            dissolve: 1.0,
+            illum: None,
+            map_kd: None,
+        }
+    }
+}
+
+impl MtlMaterial {
+    pub fn is_emissive(&self) -> bool {
+        max_component(self.ke) > 0.0
+    }
+
+    pub fn is_transparent(&self) -> bool {
+        self.dissolve < 1.0 || matches!(self.illum, Some(4) | Some(6) | Some(7) | Some(9))
+    }
+
+    // Only illumination model 3, reflection with ray tracing, asks for a
+    // mirror; plenty of diffuse materials have a strong specular highlight.
+    pub fn is_metal(&self) -> bool {
+        self.illum == Some(3) && self.map_kd.is_none()
+    }
+
+    // Emission wins over everything else, then transmission, then specular
+    // reflection; anything left is diffuse, textured if it has a map_Kd.
+    pub fn material(&self) -> Arc<MaterialType> {
+        if self.is_emissive() {
+            return Diffuse::new(SolidColor::new(self.ke.r, self.ke.g, self.ke.b));
+        }
+        if self.is_transparent() {
+            return Dielectric::new(self.ni);
+        }
+        if self.is_metal() {
+            // Map the Phong exponent onto a roughness-like fuzz factor.
+            let fuzz = f64::sqrt(2.0 / (self.ns.max(0.0) + 2.0));
+            return Metal::new(SolidColor::new(self.ks.r, self.ks.g, self.ks.b), fuzz);
+        }
+        match &self.map_kd {
+            Some(texture) => Lambertian::new(texture.clone()),
+            None => Lambertian::new(SolidColor::new(self.kd.r, self.kd.g, self.kd.b)),
+        }
+    }
+}
+
+fn max_component(c: Color) -> f64 {
+    c.r.max(c.g).max(c.b)
+}
+
+pub fn load_obj(path: impl AsRef<Path>, time0: f64, time1: f64) -> Result<ObjModel, LoadError> {
+    let path = path.as_ref();
+    let source = fs::read_to_string(path).map_err(|e| LoadError::io(path, e))?;
+    parse_obj(&source, path, time0, time1)
+}
+
+pub fn load_mtl(path: impl AsRef<Path>) -> Result<HashMap<String, MtlMaterial>, LoadError> {
+    let path = path.as_ref();
+    let source = fs::read_to_string(path).map_err(|e| LoadError::io(path, e))?;
+    parse_mtl(&source, path)
+}
+
+// Vertex, uv and normal indices of one face corner, already zero based.
+type Corner = (usize, Option<usize>, Option<usize>);
+
+struct Group {
+    material: Option<String>,
+    faces: Vec<[Corner; 3]>,
+}
+
+// `path` is used for error messages and to resolve `mtllib` and texture paths.
+pub fn parse_obj(source: &str, path: &Path, time0: f64, time1: f64) -> Result<ObjModel, LoadError> {
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let mut vertices: Vec<Vec3> = Vec::new();
+    let mut uvs: Vec<(f64, f64)> = Vec::new();
+    let mut normals: Vec<Vec3> = Vec::new();
+    let mut mtls: HashMap<String, MtlMaterial> = HashMap::new();
+    let mut groups: Vec<Group> = vec![Group {
+        material: None,
+        faces: Vec::new(),
+    }];
+
+    for (n, line) in source.lines().enumerate() {
+        let line_no = n + 1;
+        let mut parts = line.split_whitespace();
+        let keyword = match parts.next() {
+            Some(k) if !k.starts_with('#') => k,
+            _ => continue,
+        };
+        let args: Vec<&str> = parts.collect();
+        match keyword {
+            "v" => {
+                let v = floats(&args, 3, path, line_no)?;
+                vertices.push(vec3(v[0], v[1], v[2]));
+            }
+            "vt" => {
+                let vt = floats(&args, 1, path, line_no)?;
+                uvs.push((vt[0], *vt.get(1).unwrap_or(&0.0)));
+            }
+            "vn" => {
+                let vn = floats(&args, 3, path, line_no)?;
+                normals.push(vec3(vn[0], vn[1], vn[2]));
+            }
+            "f" => {
+                if args.len() < 3 {
+                    return Err(LoadError::parse(
+                        path,
+                        line_no,
+                        "face needs at least 3 vertices",
+                    ));
+                }
+                let mut corners = Vec::with_capacity(args.len());
+                for arg in args.iter() {
+                    corners.push(corner(
+                        arg,
+                        (vertices.len(), uvs.len(), normals.len()),
+                        path,
+                        line_no,
+                    )?);
+                }
+                // Polygons are triangulated as a fan around the first corner.
+                let group = groups.last_mut().unwrap();
+                for i in 1..corners.len() - 1 {
+                    group.faces.push([corners[0], corners[i], corners[i + 1]]);
+                }
+            }
+            "usemtl" => {
+                let name = args.join(" ");
+                groups.push(Group {
+                    material: Some(name),
+                    faces: Vec::new(),
+                });
+            }
+            "mtllib" => {
+                for file in args.iter() {
+                    mtls.extend(load_mtl(dir.join(file))?);
+                }
+            }
+            // Object names, groups and smoothing groups don't affect rendering.
+            "o" | "g" | "s" => (),
+            _ => (),
+        }
+    }
+
+    let mut model = ObjModel {
+        hittables: HittableList {
+            hittables: Vec::new(),
+        },
+        lights: HittableList {
+            hittables: Vec::new(),
+        },
+    };
+    let mut materials: HashMap<String, Arc<MaterialType>> = HashMap::new();
+    for group in groups.iter().filter(|g| !g.faces.is_empty()) {
+        let mtl = group.material.as_ref().and_then(|name| mtls.get(name));
+        let mat = match (&group.material, mtl) {
+            (Some(name), Some(mtl)) => materials
+                .entry(name.clone())
+                .or_insert_with(|| mtl.material())
+                .clone(),
+            _ => Arc::new(MaterialType::default()),
+        };
+        let mesh = build_mesh(&group.faces, &vertices, &uvs, &normals);
+        let mesh = TriangleMesh::new(Arc::new(mesh), mat, time0, time1);
+        if mtl.is_some_and(|m| m.is_emissive()) {
+            model.lights.add(mesh.clone());
+        }
+        model.hittables.add(mesh);
+    }
+    Ok(model)
+}
+
+// Copy just the vertices a group references into its own compact buffers.
+fn build_mesh(
+    faces: &[[Corner; 3]],
+    vertices: &[Vec3],
+    uvs: &[(f64, f64)],
+    normals: &[Vec3],
+) -> MeshData {
+    let mut mesh = MeshData::default();
+    let mut vertex_map: HashMap<usize, usize> = HashMap::new();
+    let mut uv_map: HashMap<usize, usize> = HashMap::new();
+    let mut normal_map: HashMap<usize, usize> = HashMap::new();
+    for corners in faces.iter() {
+        let mut face = Face {
+            vertices: [0; 3],
+            normals: Some([0; 3]),
+            uvs: Some([0; 3]),
+        };
+        for (i, (v, vt, vn)) in corners.iter().enumerate() {
+            face.vertices[i] = *vertex_map.entry(*v).or_insert_with(|| {
+                mesh.vertices.push(vertices[*v]);
+                mesh.vertices.len() - 1
+            });
+            face.uvs = match (face.uvs, vt) {
+                (Some(mut idx), Some(vt)) => {
+                    idx[i] = *uv_map.entry(*vt).or_insert_with(|| {
+                        mesh.uvs.push(uvs[*vt]);
+                        mesh.uvs.len() - 1
+                    });
+                    Some(idx)
+                }
+                _ => None,
+            };
+            face.normals = match (face.normals, vn) {
+                (Some(mut idx), Some(vn)) => {
+                    idx[i] = *normal_map.entry(*vn).or_insert_with(|| {
+                        mesh.normals.push(normals[*vn]);
+                        mesh.normals.len() - 1
+                    });
+                    Some(idx)
+                }
+                _ => None,
+            };
+        }
+        mesh.faces.push(face);
+    }
+    mesh
+}
+
+// Parse a `v`, `v/vt`, `v//vn` or `v/vt/vn` face corner. OBJ indices are one
+// based, negative indices count back from the most recent element.
+fn corner(
+    arg: &str,
+    counts: (usize, usize, usize),
+    path: &Path,
+    line: usize,
+) -> Result<Corner, LoadError> {
+    let mut fields = arg.split('/');
+    let v = match fields.next() {
+        Some(v) if !v.is_empty() => index(v, counts.0, "vertex", path, line)?,
+        _ => {
+            return Err(LoadError::parse(
+                path,
+                line,
+                format!("missing vertex index in '{}'", arg),
+            ))
+        }
+    };
+    let vt = match fields.next() {
+        Some(vt) if !vt.is_empty() => Some(index(vt, counts.1, "texture", path, line)?),
+        _ => None,
+    };
+    let vn = match fields.next() {
+        Some(vn) if !vn.is_empty() => Some(index(vn, counts.2, "normal", path, line)?),
+        _ => None,
+    };
+    Ok((v, vt, vn))
+}
+
+fn index(
+    field: &str,
+    count: usize,
+    kind: &str,
+    path: &Path,
+    line: usize,
+) -> Result<usize, LoadError> {
+    let i: i64 = field
+        .parse()
+        .map_err(|_| LoadError::parse(path, line, format!("invalid {} index '{}'", kind, field)))?;
+    let resolved = if i < 0 { count as i64 + i } else { i - 1 };
+    if i == 0 || resolved < 0 || resolved >= count as i64 {
+        return Err(LoadError::parse(
+            path,
+            line,
+            format!("{} index {} out of range ({} defined)", kind, i, count),
+        ));
+    }
+    Ok(resolved as usize)
+}
+
+fn floats(args: &[&str], min: usize, path: &Path, line: usize) -> Result<Vec<f64>, LoadError> {
+    if args.len() < min {
+        return Err(LoadError::parse(
+            path,
+            line,
+            format!("expected {} numbers, found {}", min, args.len()),
+        ));
+    }
+    args.iter()
+        .map(|a| {
+            a.parse::<f64>()
+                .map_err(|_| LoadError::parse(path, line, format!("invalid number '{}'", a)))
+        })
+        .collect()
+}
+
+fn color_arg(args: &[&str], path: &Path, line: usize) -> Result<Color, LoadError> {
+    let c = floats(args, 1, path, line)?;
+    // A single value is a grey level.
+    if c.len() < 3 {
+        return Ok(color(c[0], c[0], c[0]));
+    }
+    Ok(color(c[0], c[1], c[2]))
+}
+
+// `path` is used for error messages and to resolve texture paths.
+pub fn parse_mtl(source: &str, path: &Path) -> Result<HashMap<String, MtlMaterial>, LoadError> {
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let mut materials = HashMap::new();
+    let mut current: Option<(String, MtlMaterial)> = None;
+    for (n, line) in source.lines().enumerate() {
+        let line_no = n + 1;
+        let mut parts = line.split_whitespace();
+        let keyword = match parts.next() {
+            Some(k) if !k.starts_with('#') => k,
+            _ => continue,
+        };
+        let args: Vec<&str> = parts.collect();
+        if keyword == "newmtl" {
+            if let Some((name, mtl)) = current.take() {
+                materials.insert(name, mtl);
+            }
+            current = Some((args.join(" "), MtlMaterial::default()));
+            continue;
+        }
+        let mtl = match current.as_mut() {
+            Some((_, mtl)) => mtl,
+            None => {
+                return Err(LoadError::parse(
+                    path,
+                    line_no,
+                    format!("'{}' before any newmtl", keyword),
+                ))
+            }
+        };
+        match keyword {
+            "Kd" => mtl.kd = color_arg(&args, path, line_no)?,
+            "Ks" => mtl.ks = color_arg(&args, path, line_no)?,
+            "Ke" => mtl.ke = color_arg(&args, path, line_no)?,
+            "Ns" => mtl.ns = floats(&args, 1, path, line_no)?[0],
+            "Ni" => mtl.ni = floats(&args, 1, path, line_no)?[0],
+            "d" => mtl.dissolve = floats(&args, 1, path, line_no)?[0],
+            "Tr" => mtl.dissolve = 1.0 - floats(&args, 1, path, line_no)?[0],
+            "illum" => {
+                let model = args.first().and_then(|a| a.parse::<u32>().ok());
+                match model {
+                    Some(model) => mtl.illum = Some(model),
+                    None => {
+                        return Err(LoadError::parse(
+                            path,
+                            line_no,
+                            format!("invalid illumination model '{}'", args.join(" ")),
+                        ))
+                    }
+                }
+            }
+            // Texture options such as `-s 1 1 1` come before the file name.
+            "map_Kd" => match args.last() {
+                Some(file) => {
+                    let file = dir.join(file);
+                    let texture = ImageTexture::open(&file.to_string_lossy()).map_err(|e| {
+                        LoadError::parse(
+                            path,
+                            line_no,
+                            format!("can't read texture '{}': {}", file.display(), e),
+                        )
+                    })?;
+                    mtl.map_kd = Some(texture);
+                }
+                None => return Err(LoadError::parse(path, line_no, "map_Kd needs a file name")),
+            },
+            _ => (),
+        }
+    }
+    if let Some((name, mtl)) = current.take() {
+        materials.insert(name, mtl);
+    }
+    Ok(materials)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittable::Hittable;
+    use crate::ray::Ray;
+    use rand::rngs::SmallRng;
+    use rand::SeedableRng;
+
+    const QUAD: &str = "
+# unit quad in the xy plane, written as a single polygon
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+vt 0 0
+vt 1 0
+vt 1 1
+vt 0 1
+vn 0 0 1
+f 1/1/1 2/2/1 3/3/1 -1/-1/-1
+";
+
+    #[test]
+    fn obj_quad_is_triangulated() {
+        let model = parse_obj(QUAD, Path::new("quad.obj"), 0.0, 1.0).unwrap();
+        assert_eq!(model.hittables.hittables.len(), 1);
+        assert!(model.lights.hittables.is_empty());
+
+        let mut rng = SmallRng::seed_from_u64(0);
+        let ray = Ray {
+            origin: vec3(0.25, 0.75, 1.0),
+            direction: vec3(0.0, 0.0, -1.0),
+            time: 0.0,
+            wavelength: None,
+        };
+        let hit = model
+            .to_bvh(0.0, 1.0)
+            .hit(&ray, 0.001, f64::INFINITY, &mut rng)
+            .expect("ray should hit the second triangle of the quad");
+        assert!((hit.u - 0.25).abs() < 1e-9);
+        assert!((hit.v - 0.75).abs() < 1e-9);
+    }
+
+    #[test]
+    fn obj_errors_have_line_numbers() {
+        let err = parse_obj(
+            "v 0 0 0\nv 1 0 0\nf 1 2 3\n",
+            Path::new("bad.obj"),
+            0.0,
+            1.0,
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "bad.obj:3: vertex index 3 out of range (2 defined)"
+        );
+        let err = parse_obj("v 0 zero 0\n", Path::new("bad.obj"), 0.0, 1.0).unwrap_err();
+        assert_eq!(err.to_string(), "bad.obj:1: invalid number 'zero'");
+    }
+
+    #[test]
+    fn mtl_maps_onto_materials() {
+        let mtls = parse_mtl(
+            "
+newmtl light
+Kd 0 0 0
+Ke 15 15 15
+
+newmtl glass
+Ni 1.5
+d 0.1
+
+newmtl gold
+Kd 0.1 0.1 0.1
+Ks 1.0 0.8 0.3
+Ns 200
+illum 3
+
+newmtl plastic
+Kd 0.05 0.05 0.05
+Ks 0.5 0.5 0.5
+illum 2
+
+newmtl wall
+Kd 0.73 0.73 0.73
+",
+            Path::new("test.mtl"),
+        )
+        .unwrap();
+        assert!(matches!(
+            *mtls["light"].material(),
+            MaterialType::Diffuse(_)
+        ));
+        assert!(matches!(
+            *mtls["glass"].material(),
+            MaterialType::Dielectric(_)
+        ));
+        assert!(matches!(*mtls["gold"].material(), MaterialType::Metal(_)));
+        assert!(matches!(
+            *mtls["plastic"].material(),
+            MaterialType::Lambertian(_)
+        ));
+        assert!(matches!(
+            *mtls["wall"].material(),
+            MaterialType::Lambertian(_)
+        ));
+    }
+
+    #[test]
+    fn mtl_textures_must_be_readable() {
+        let err = parse_mtl("newmtl a\nmap_Kd missing.png\n", Path::new("test.mtl")).unwrap_err();
+        let message = err.to_string();
+        assert!(
+            message.starts_with("test.mtl:2: can't read texture 'missing.png'"),
+            "{}",
+            message
+        );
+        // Not an image.
+        let err = parse_mtl("newmtl a\nmap_Kd Cargo.toml\n", Path::new("test.mtl")).unwrap_err();
+        assert!(err.to_string().starts_with("test.mtl:2: "), "{}", err);
+    }
+
+    #[test]
+    fn mtl_illumination_models_are_integers() {
+        for model in ["-1", "2.7", ""].iter() {
+            let source = format!("newmtl a\nillum {}\n", model);
+            let err = parse_mtl(&source, Path::new("test.mtl")).unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                format!("test.mtl:2: invalid illumination model '{}'", model)
+            );
+        }
+    }
+
+    #[test]
+    fn textured_materials_stay_diffuse() {
+        let dir = std::env::temp_dir().join(format!("obj-texture-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        image::RgbImage::new(2, 2)
+            .save(dir.join("texture.png"))
+            .unwrap();
+        let mtls = parse_mtl(
+            "newmtl a\nKd 0 0 0\nKs 1 1 1\nillum 3\nmap_Kd texture.png\n",
+            &dir.join("test.mtl"),
+        );
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(matches!(
+            *mtls.unwrap()["a"].material(),
+            MaterialType::Lambertian(_)
+        ));
+    }
+}
@@ -0,0 +1,941 @@
+use crate::camera::{Camera, CameraConfig};
+use crate::color::color;
+use crate::hittable::{
+    box3d::Box3D,
+    bvh::BvhNode,
+    constant_medium::ConstantMedium,
+    flip_face::FlipFace,
+    hittable_list::HittableList,
+    rect::{XyRect, XzRect, YzRect},
+    rotate::{RotateX, RotateY, RotateZ},
+    sphere::{MovingSphere, Sphere},
+    translate::Translate,
+    triangle::Triangle,
+    Hittables,
+};
+use crate::loader::{obj::load_obj, LoadError};
+use crate::material::{
+    dielectric::{Absorption, Dielectric},
+    diffuse::Diffuse,
+    dispersion::Dispersion,
+    isotropic::Isotropic,
+    lambertian::Lambertian,
+    metal::Metal,
+    principled::Principled,
+    rough_conductor::{Conductor, RoughConductor},
+    rough_dielectric::RoughDielectric,
+    MaterialType,
+};
+use crate::scenes::Scene;
+use crate::texture::{
+    checker::CheckerTexture, image::ImageTexture, marble::MarbleTexture, noise::NoiseTexture,
+    solidcolor::SolidColor, turbulence::TurbulenceTexture, Texture,
+};
+use crate::vec::{vec3, Vec3};
+use serde::de::{self, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+// A JSON scene description, see assets/scenes/ for examples.
+//
+// Textures, materials and objects are written as `{"kind": {fields...}}`.
+// Textures and materials can be declared once under a name and referenced by
+// that name, or written inline wherever they're used. A texture can also be
+// given as a plain `[r, g, b]` color. Transforms wrap the object they apply
+// to, just like the corresponding hittables do.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SceneFile {
+    camera: CameraDesc,
+    #[serde(default)]
+    textures: BTreeMap<String, TextureDesc>,
+    #[serde(default)]
+    materials: BTreeMap<String, MaterialDesc>,
+    objects: Vec<ObjectDesc>,
+    #[serde(default)]
+    lights: Vec<ObjectDesc>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CameraDesc {
+    lookfrom: [f64; 3],
+    lookat: [f64; 3],
+    #[serde(default = "default_vup")]
+    vup: [f64; 3],
+    vfov: f64,
+    #[serde(default)]
+    aperture: f64,
+    // Defaults to the distance between lookfrom and lookat.
+    focus_dist: Option<f64>,
+    #[serde(default)]
+    background: [f64; 3],
+}
+
+fn default_vup() -> [f64; 3] {
+    [0.0, 1.0, 0.0]
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+enum TextureDesc {
+    Solid {
+        color: [f64; 3],
+    },
+    Checker {
+        odd: TextureRef,
+        even: TextureRef,
+    },
+    Noise {
+        #[serde(default)]
+        seed: u64,
+        scale: f64,
+    },
+    Turbulence {
+        #[serde(default)]
+        seed: u64,
+        scale: f64,
+    },
+    Marble {
+        #[serde(default)]
+        seed: u64,
+        scale: f64,
+    },
+    Image {
+        file: String,
+    },
+}
+
+#[derive(Debug)]
+enum TextureRef {
+    Color([f64; 3]),
+    Named(String),
+    Inline(Box<TextureDesc>),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+enum MaterialDesc {
+    Lambertian {
+        albedo: TextureRef,
+    },
+    Metal {
+        albedo: TextureRef,
+        #[serde(default)]
+        fuzz: f64,
+    },
+    // Either ir or dispersion, for both.
+    Dielectric {
+        ir: Option<f64>,
+        dispersion: Option<Dispersion>,
+        absorption: Option<AbsorptionDesc>,
+    },
+    RoughDielectric {
+        ir: Option<f64>,
+        dispersion: Option<Dispersion>,
+        roughness: f64,
+        absorption: Option<AbsorptionDesc>,
+    },
+    // Either a preset or both eta and k.
+    RoughConductor {
+        preset: Option<Conductor>,
+        eta: Option<[f64; 3]>,
+        k: Option<[f64; 3]>,
+        roughness: f64,
+    },
+    Diffuse {
+        emit: TextureRef,
+    },
+    Isotropic {
+        albedo: TextureRef,
+    },
+    Principled(Box<PrincipledDesc>),
+}
+
+// Parameters left out take Disney's defaults.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PrincipledDesc {
+    base_color: Option<TextureRef>,
+    metallic: Option<TextureRef>,
+    roughness: Option<TextureRef>,
+    specular: Option<TextureRef>,
+    specular_tint: Option<TextureRef>,
+    sheen: Option<TextureRef>,
+    sheen_tint: Option<TextureRef>,
+    clearcoat: Option<TextureRef>,
+    clearcoat_gloss: Option<TextureRef>,
+    transmission: Option<TextureRef>,
+    subsurface: Option<TextureRef>,
+}
+
+// The color light inside is left with after travelling `distance`.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AbsorptionDesc {
+    color: [f64; 3],
+    #[serde(default = "default_absorption_distance")]
+    distance: f64,
+}
+
+fn default_absorption_distance() -> f64 {
+    1.0
+}
+
+#[derive(Debug)]
+enum MaterialRef {
+    Named(String),
+    Inline(Box<MaterialDesc>),
+}
+
+// Objects in the `lights` list are only used for sampling, so their material
+// may be left out.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+enum ObjectDesc {
+    Sphere {
+        center: [f64; 3],
+        radius: f64,
+        material: Option<MaterialRef>,
+    },
+    MovingSphere {
+        center0: [f64; 3],
+        center1: [f64; 3],
+        time0: f64,
+        time1: f64,
+        radius: f64,
+        material: Option<MaterialRef>,
+    },
+    XyRect {
+        x0: f64,
+        x1: f64,
+        y0: f64,
+        y1: f64,
+        k: f64,
+        material: Option<MaterialRef>,
+    },
+    XzRect {
+        x0: f64,
+        x1: f64,
+        z0: f64,
+        z1: f64,
+        k: f64,
+        material: Option<MaterialRef>,
+    },
+    YzRect {
+        y0: f64,
+        y1: f64,
+        z0: f64,
+        z1: f64,
+        k: f64,
+        material: Option<MaterialRef>,
+    },
+    Box {
+        p0: [f64; 3],
+        p1: [f64; 3],
+        material: Option<MaterialRef>,
+    },
+    Triangle {
+        v0: [f64; 3],
+        v1: [f64; 3],
+        v2: [f64; 3],
+        material: Option<MaterialRef>,
+    },
+    // A Wavefront OBJ file, with materials from its MTL library.
+    Obj {
+        file: String,
+    },
+    ConstantMedium {
+        boundary: Box<ObjectDesc>,
+        density: f64,
+        texture: TextureRef,
+    },
+    // A group of objects, kept in their own BVH.
+    List {
+        objects: Vec<ObjectDesc>,
+    },
+    Translate {
+        offset: [f64; 3],
+        object: Box<ObjectDesc>,
+    },
+    RotateX {
+        angle: f64,
+        object: Box<ObjectDesc>,
+    },
+    RotateY {
+        angle: f64,
+        object: Box<ObjectDesc>,
+    },
+    RotateZ {
+        angle: f64,
+        object: Box<ObjectDesc>,
+    },
+    FlipFace {
+        object: Box<ObjectDesc>,
+    },
+}
+
+impl<'de> Deserialize<'de> for TextureRef {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<TextureRef, D::Error> {
+        struct TextureRefVisitor;
+
+        impl<'de> Visitor<'de> for TextureRefVisitor {
+            type Value = TextureRef;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("an [r, g, b] color, a grey level, a texture name or a texture")
+            }
+
+            fn visit_f64<E: de::Error>(self, x: f64) -> Result<TextureRef, E> {
+                Ok(TextureRef::Color([x, x, x]))
+            }
+
+            fn visit_u64<E: de::Error>(self, x: u64) -> Result<TextureRef, E> {
+                self.visit_f64(x as f64)
+            }
+
+            fn visit_i64<E: de::Error>(self, x: i64) -> Result<TextureRef, E> {
+                self.visit_f64(x as f64)
+            }
+
+            fn visit_str<E: de::Error>(self, name: &str) -> Result<TextureRef, E> {
+                Ok(TextureRef::Named(name.to_string()))
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<TextureRef, A::Error> {
+                let rgb = <[f64; 3]>::deserialize(de::value::SeqAccessDeserializer::new(seq))?;
+                Ok(TextureRef::Color(rgb))
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<TextureRef, A::Error> {
+                let desc = TextureDesc::deserialize(de::value::MapAccessDeserializer::new(map))?;
+                Ok(TextureRef::Inline(Box::new(desc)))
+            }
+        }
+
+        deserializer.deserialize_any(TextureRefVisitor)
+    }
+}
+
+impl<'de> Deserialize<'de> for MaterialRef {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<MaterialRef, D::Error> {
+        struct MaterialRefVisitor;
+
+        impl<'de> Visitor<'de> for MaterialRefVisitor {
+            type Value = MaterialRef;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a material name or a material")
+            }
+
+            fn visit_str<E: de::Error>(self, name: &str) -> Result<MaterialRef, E> {
+                Ok(MaterialRef::Named(name.to_string()))
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<MaterialRef, A::Error> {
+                let desc = MaterialDesc::deserialize(de::value::MapAccessDeserializer::new(map))?;
+                Ok(MaterialRef::Inline(Box::new(desc)))
+            }
+        }
+
+        deserializer.deserialize_any(MaterialRefVisitor)
+    }
+}
+
+fn v3(a: [f64; 3]) -> Vec3 {
+    vec3(a[0], a[1], a[2])
+}
+
+pub fn load_scene(
+    path: impl AsRef<Path>,
+    time0: f64,
+    time1: f64,
+    aspect_ratio: f64,
+) -> Result<Scene, LoadError> {
+    let path = path.as_ref();
+    let source = fs::read_to_string(path).map_err(|e| LoadError::io(path, e))?;
+    parse_scene(&source, path, time0, time1, aspect_ratio)
+}
+
+// `path` is used for error messages and to resolve relative file names.
+pub fn parse_scene(
+    source: &str,
+    path: &Path,
+    time0: f64,
+    time1: f64,
+    aspect_ratio: f64,
+) -> Result<Scene, LoadError> {
+    let file: SceneFile = serde_json::from_str(source).map_err(|e| {
+        // serde_json appends the position to its message, we report it separately.
+        let message = e.to_string();
+        let message = match message.rfind(" at line ") {
+            Some(i) => message[..i].to_string(),
+            None => message,
+        };
+        LoadError::Parse {
+            path: path.to_path_buf(),
+            line: e.line(),
+            column: Some(e.column()),
+            message,
+        }
+    })?;
+
+    let mut builder = Builder {
+        path,
+        dir: path.parent().unwrap_or_else(|| Path::new("")).to_path_buf(),
+        file: &file,
+        textures: HashMap::new(),
+        materials: HashMap::new(),
+        time0,
+        time1,
+    };
+    for name in file.textures.keys() {
+        builder.named_texture(name, &mut Vec::new())?;
+    }
+    for (name, desc) in file.materials.iter() {
+        let mat = builder.material(desc, &format!("materials.{}", name))?;
+        builder.materials.insert(name.clone(), mat);
+    }
+
+    let mut world = HittableList {
+        hittables: Vec::new(),
+    };
+    for (i, desc) in file.objects.iter().enumerate() {
+        world.add(builder.object(desc, &format!("objects[{}]", i), true)?);
+    }
+    if world.hittables.is_empty() {
+        return Err(LoadError::invalid(path, "objects", "scene has no objects"));
+    }
+    let mut lights = HittableList {
+        hittables: Vec::new(),
+    };
+    for (i, desc) in file.lights.iter().enumerate() {
+        lights.add(builder.object(desc, &format!("lights[{}]", i), false)?);
+    }
+
+    let cam = &file.camera;
+    let camera = Camera::new(CameraConfig {
+        lookfrom: v3(cam.lookfrom),
+        lookat: v3(cam.lookat),
+        vup: v3(cam.vup),
+        vfov: cam.vfov,
+        aspect_ratio,
+        aperture: cam.aperture,
+        focus_dist: cam
+            .focus_dist
+            .unwrap_or_else(|| (v3(cam.lookfrom) - v3(cam.lookat)).length()),
+        time0,
+        time1,
+        background: color(cam.background[0], cam.background[1], cam.background[2]),
+    });
+
+    Ok(Scene {
+        camera,
+        hittables: Hittables::from(BvhNode::new(world, time0, time1)),
+        lights: Hittables::from(lights),
+    })
+}
+
+struct Builder<'a> {
+    path: &'a Path,
+    dir: PathBuf,
+    file: &'a SceneFile,
+    textures: HashMap<String, Texture>,
+    materials: HashMap<String, Arc<MaterialType>>,
+    time0: f64,
+    time1: f64,
+}
+
+impl<'a> Builder<'a> {
+    // Named textures may refer to each other, `visiting` catches cycles.
+    fn named_texture(
+        &mut self,
+        name: &str,
+        visiting: &mut Vec<String>,
+    ) -> Result<Texture, LoadError> {
+        if let Some(texture) = self.textures.get(name) {
+            return Ok(texture.clone());
+        }
+        let field = format!("textures.{}", name);
+        let desc = match self.file.textures.get(name) {
+            Some(desc) => desc,
+            None => {
+                return Err(LoadError::invalid(
+                    self.path,
+                    visiting.last().map_or("textures", |s| s.as_str()),
+                    format!("unknown texture '{}'", name),
+                ))
+            }
+        };
+        if visiting.contains(&field) {
+            return Err(LoadError::invalid(
+                self.path,
+                &field,
+                "texture refers to itself",
+            ));
+        }
+        visiting.push(field.clone());
+        let texture = self.texture_desc(desc, &field, visiting)?;
+        visiting.pop();
+        self.textures.insert(name.to_string(), texture.clone());
+        Ok(texture)
+    }
+
+    fn texture(&mut self, r: &TextureRef, field: &str) -> Result<Texture, LoadError> {
+        self.texture_ref(r, field, &mut Vec::new())
+    }
+
+    fn texture_ref(
+        &mut self,
+        r: &TextureRef,
+        field: &str,
+        visiting: &mut Vec<String>,
+    ) -> Result<Texture, LoadError> {
+        match r {
+            TextureRef::Color(c) => Ok(SolidColor::new(c[0], c[1], c[2])),
+            TextureRef::Named(name) => {
+                visiting.push(field.to_string());
+                let texture = self.named_texture(name, visiting);
+                visiting.pop();
+                texture
+            }
+            TextureRef::Inline(desc) => self.texture_desc(desc, field, visiting),
+        }
+    }
+
+    fn texture_desc(
+        &mut self,
+        desc: &TextureDesc,
+        field: &str,
+        visiting: &mut Vec<String>,
+    ) -> Result<Texture, LoadError> {
+        Ok(match desc {
+            TextureDesc::Solid { color: c } => SolidColor::new(c[0], c[1], c[2]),
+            TextureDesc::Checker { odd, even } => Texture::from(CheckerTexture {
+                odd: Box::new(self.texture_ref(odd, &format!("{}.odd", field), visiting)?),
+                even: Box::new(self.texture_ref(even, &format!("{}.even", field), visiting)?),
+            }),
+            TextureDesc::Noise { seed, scale } => NoiseTexture::new(*seed, *scale),
+            TextureDesc::Turbulence { seed, scale } => TurbulenceTexture::new(*seed, *scale),
+            TextureDesc::Marble { seed, scale } => MarbleTexture::new(*seed, *scale),
+            TextureDesc::Image { file } => {
+                let file = self.dir.join(file);
+                if !file.is_file() {
+                    return Err(LoadError::invalid(
+                        self.path,
+                        &format!("{}.file", field),
+                        format!("no such image '{}'", file.display()),
+                    ));
+                }
+                ImageTexture::new(&file.to_string_lossy())
+            }
+        })
+    }
+
+    fn material(
+        &mut self,
+        desc: &MaterialDesc,
+        field: &str,
+    ) -> Result<Arc<MaterialType>, LoadError> {
+        Ok(match desc {
+            MaterialDesc::Lambertian { albedo } => {
+                Lambertian::new(self.texture(albedo, &format!("{}.albedo", field))?)
+            }
+            MaterialDesc::Metal { albedo, fuzz } => {
+                Metal::new(self.texture(albedo, &format!("{}.albedo", field))?, *fuzz)
+            }
+            MaterialDesc::Dielectric {
+                ir,
+                dispersion,
+                absorption,
+            } => {
+                let absorption = self.absorption(absorption, field)?;
+                match (ir, dispersion) {
+                    (Some(ir), None) => Dielectric::absorbing(*ir, absorption),
+                    (None, Some(dispersion)) => Dielectric::dispersing(*dispersion, absorption),
+                    _ => return Err(self.ir_or_dispersion(field)),
+                }
+            }
+            MaterialDesc::RoughDielectric {
+                ir,
+                dispersion,
+                roughness,
+                absorption,
+            } => {
+                let absorption = self.absorption(absorption, field)?;
+                match (ir, dispersion) {
+                    (Some(ir), None) => RoughDielectric::absorbing(*ir, *roughness, absorption),
+                    (None, Some(dispersion)) => {
+                        RoughDielectric::dispersing(*dispersion, *roughness, absorption)
+                    }
+                    _ => return Err(self.ir_or_dispersion(field)),
+                }
+            }
+            MaterialDesc::RoughConductor {
+                preset,
+                eta,
+                k,
+                roughness,
+            } => match (preset, eta, k) {
+                (Some(preset), None, None) => RoughConductor::preset(*preset, *roughness),
+                (None, Some(eta), Some(k)) => RoughConductor::new(
+                    color(eta[0], eta[1], eta[2]),
+                    color(k[0], k[1], k[2]),
+                    *roughness,
+                ),
+                _ => {
+                    return Err(LoadError::invalid(
+                        self.path,
+                        field,
+                        "expected either a preset or both eta and k",
+                    ))
+                }
+            },
+            MaterialDesc::Diffuse { emit } => {
+                Diffuse::new(self.texture(emit, &format!("{}.emit", field))?)
+            }
+            MaterialDesc::Isotropic { albedo } => {
+                Isotropic::new(self.texture(albedo, &format!("{}.albedo", field))?)
+            }
+            MaterialDesc::Principled(desc) => {
+                let PrincipledDesc {
+                    base_color,
+                    metallic,
+                    roughness,
+                    specular,
+                    specular_tint,
+                    sheen,
+                    sheen_tint,
+                    clearcoat,
+                    clearcoat_gloss,
+                    transmission,
+                    subsurface,
+                } = desc.as_ref();
+                let defaults = Principled::default();
+                let mut param = |r: &Option<TextureRef>, name: &str, default| match r {
+                    Some(r) => Ok(Box::new(self.texture(r, &format!("{}.{}", field, name))?)),
+                    None => Ok(default),
+                };
+                Principled {
+                    base_color: param(base_color, "base_color", defaults.base_color)?,
+                    metallic: param(metallic, "metallic", defaults.metallic)?,
+                    roughness: param(roughness, "roughness", defaults.roughness)?,
+                    specular: param(specular, "specular", defaults.specular)?,
+                    specular_tint: param(specular_tint, "specular_tint", defaults.specular_tint)?,
+                    sheen: param(sheen, "sheen", defaults.sheen)?,
+                    sheen_tint: param(sheen_tint, "sheen_tint", defaults.sheen_tint)?,
+                    clearcoat: param(clearcoat, "clearcoat", defaults.clearcoat)?,
+                    clearcoat_gloss: param(
+                        clearcoat_gloss,
+                        "clearcoat_gloss",
+                        defaults.clearcoat_gloss,
+                    )?,
+                    transmission: param(transmission, "transmission", defaults.transmission)?,
+                    subsurface: param(subsurface, "subsurface", defaults.subsurface)?,
+                }
+                .material()
+            }
+        })
+    }
+
+    fn ir_or_dispersion(&self, field: &str) -> LoadError {
+        LoadError::invalid(self.path, field, "expected either ir or dispersion")
+    }
+
+    fn absorption(
+        &self,
+        desc: &Option<AbsorptionDesc>,
+        field: &str,
+    ) -> Result<Absorption, LoadError> {
+        let desc = match desc {
+            Some(desc) => desc,
+            None => return Ok(Absorption::default()),
+        };
+        if desc.distance <= 0.0 {
+            return Err(LoadError::invalid(
+                self.path,
+                &format!("{}.absorption.distance", field),
+                "must be positive",
+            ));
+        }
+        let c = desc.color;
+        Ok(Absorption::new(color(c[0], c[1], c[2]), desc.distance))
+    }
+
+    fn material_ref(
+        &mut self,
+        r: &Option<MaterialRef>,
+        field: &str,
+        required: bool,
+    ) -> Result<Arc<MaterialType>, LoadError> {
+        let field = format!("{}.material", field);
+        match r {
+            Some(MaterialRef::Named(name)) => match self.materials.get(name) {
+                Some(mat) => Ok(mat.clone()),
+                None => Err(LoadError::invalid(
+                    self.path,
+                    &field,
+                    format!("unknown material '{}'", name),
+                )),
+            },
+            Some(MaterialRef::Inline(desc)) => self.material(desc, &field),
+            None if required => Err(LoadError::invalid(self.path, &field, "missing material")),
+            None => Ok(Arc::new(MaterialType::default())),
+        }
+    }
+
+    fn object(
+        &mut self,
+        desc: &ObjectDesc,
+        field: &str,
+        required: bool,
+    ) -> Result<Hittables, LoadError> {
+        let (time0, time1) = (self.time0, self.time1);
+        Ok(match desc {
+            ObjectDesc::Sphere {
+                center,
+                radius,
+                material,
+            } => Sphere::new(
+                v3(*center),
+                *radius,
+                self.material_ref(material, field, required)?,
+            ),
+            ObjectDesc::MovingSphere {
+                center0,
+                center1,
+                time0,
+                time1,
+                radius,
+                material,
+            } => MovingSphere::new(
+                v3(*center0),
+                v3(*center1),
+                *time0,
+                *time1,
+                *radius,
+                self.material_ref(material, field, required)?,
+            ),
+            ObjectDesc::XyRect {
+                x0,
+                x1,
+                y0,
+                y1,
+                k,
+                material,
+            } => XyRect::new(
+                *x0,
+                *x1,
+                *y0,
+                *y1,
+                *k,
+                self.material_ref(material, field, required)?,
+            ),
+            ObjectDesc::XzRect {
+                x0,
+                x1,
+                z0,
+                z1,
+                k,
+                material,
+            } => XzRect::new(
+                *x0,
+                *x1,
+                *z0,
+                *z1,
+                *k,
+                self.material_ref(material, field, required)?,
+            ),
+            ObjectDesc::YzRect {
+                y0,
+                y1,
+                z0,
+                z1,
+                k,
+                material,
+            } => YzRect::new(
+                *y0,
+                *y1,
+                *z0,
+                *z1,
+                *k,
+                self.material_ref(material, field, required)?,
+            ),
+            ObjectDesc::Box { p0, p1, material } => Box3D::new(
+                v3(*p0),
+                v3(*p1),
+                self.material_ref(material, field, required)?,
+            ),
+            ObjectDesc::Triangle {
+                v0,
+                v1,
+                v2,
+                material,
+            } => Triangle::new(
+                v3(*v0),
+                v3(*v1),
+                v3(*v2),
+                self.material_ref(material, field, required)?,
+            ),
+            ObjectDesc::Obj { file } => {
+                let model = load_obj(self.dir.join(file), time0, time1)?;
+                if model.hittables.hittables.is_empty() {
+                    return Err(LoadError::invalid(
+                        self.path,
+                        &format!("{}.file", field),
+                        format!("'{}' has no faces", file),
+                    ));
+                }
+                model.to_bvh(time0, time1)
+            }
+            ObjectDesc::ConstantMedium {
+                boundary,
+                density,
+                texture,
+            } => {
+                let boundary = self.object(boundary, &format!("{}.boundary", field), false)?;
+                let texture = self.texture(texture, &format!("{}.texture", field))?;
+                ConstantMedium::new(Arc::new(boundary), *density, texture)
+            }
+            ObjectDesc::List { objects } => {
+                let mut list = HittableList {
+                    hittables: Vec::new(),
+                };
+                for (i, object) in objects.iter().enumerate() {
+                    list.add(self.object(
+                        object,
+                        &format!("{}.objects[{}]", field, i),
+                        required,
+                    )?);
+                }
+                if list.hittables.is_empty() {
+                    return Err(LoadError::invalid(
+                        self.path,
+                        &format!("{}.objects", field),
+                        "list is empty",
+                    ));
+                }
+                Hittables::from(BvhNode::new(list, time0, time1))
+            }
+            ObjectDesc::Translate { offset, object } => Translate::new(
+                Arc::new(self.object(object, &format!("{}.object", field), required)?),
+                v3(*offset),
+            ),
+            ObjectDesc::RotateX { angle, object } => RotateX::new(
+                Arc::new(self.object(object, &format!("{}.object", field), required)?),
+                *angle,
+            ),
+            ObjectDesc::RotateY { angle, object } => RotateY::new(
+                Arc::new(self.object(object, &format!("{}.object", field), required)?),
+                *angle,
+            ),
+            ObjectDesc::RotateZ { angle, object } => RotateZ::new(
+                Arc::new(self.object(object, &format!("{}.object", field), required)?),
+                *angle,
+            ),
+            ObjectDesc::FlipFace { object } => {
+                FlipFace::new(self.object(object, &format!("{}.object", field), required)?)
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittable::Hittable;
+
+    fn parse(source: &str) -> Result<Scene, LoadError> {
+        parse_scene(source, Path::new("test.json"), 0.0, 1.0, 1.0)
+    }
+
+    #[test]
+    fn example_scenes_load() {
+        for entry in fs::read_dir("assets/scenes").unwrap() {
+            let path = entry.unwrap().path();
+            if let Err(e) = load_scene(&path, 0.0, 1.0, 1.0) {
+                panic!("{}", e);
+            }
+        }
+    }
+
+    #[test]
+    fn scene_with_named_and_inline_references() {
+        let scene = parse(
+            r#"{
+                "camera": {"lookfrom": [0, 0, 5], "lookat": [0, 0, 0], "vfov": 40},
+                "textures": {
+                    "check": {"checker": {"odd": "white", "even": [0, 0, 0]}},
+                    "white": {"solid": {"color": [1, 1, 1]}}
+                },
+                "materials": {"floor": {"lambertian": {"albedo": "check"}}},
+                "objects": [
+                    {"sphere": {"center": [0, 0, 0], "radius": 1, "material": "floor"}},
+                    {"translate": {"offset": [0, 3, 0], "object":
+                        {"flip_face": {"object":
+                            {"xz_rect": {"x0": -1, "x1": 1, "z0": -1, "z1": 1, "k": 0,
+                             "material": {"diffuse": {"emit": [4, 4, 4]}}}}}}}}
+                ],
+                "lights": [{"xz_rect": {"x0": -1, "x1": 1, "z0": -1, "z1": 1, "k": 3}}]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(scene.lights.length(), 1);
+        assert!(scene.hittables.bounding_box(0.0, 1.0).is_some());
+    }
+
+    #[test]
+    fn syntax_errors_have_line_and_column() {
+        let err = parse(
+            r#"{
+  "camera": {"lookfrom": [0, 0, 5], "lookat": [0, 0, 0], "vfov": 40},
+  "objects": [
+    {"sphere": {"center": [0, 0, 0], "radius": "big"}}
+  ]
+}"#,
+        )
+        .unwrap_err();
+        match err {
+            LoadError::Parse { line, message, .. } => {
+                assert_eq!(line, 4);
+                assert!(message.contains("invalid type"), "{}", message);
+            }
+            e => panic!("unexpected error {}", e),
+        }
+    }
+
+    #[test]
+    fn semantic_errors_name_the_field() {
+        let err = parse(
+            r#"{
+                "camera": {"lookfrom": [0, 0, 5], "lookat": [0, 0, 0], "vfov": 40},
+                "objects": [
+                    {"sphere": {"center": [0, 0, 0], "radius": 1, "material": "nope"}}
+                ]
+            }"#,
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "test.json: objects[0].material: unknown material 'nope'"
+        );
+
+        let err = parse(
+            r#"{
+                "camera": {"lookfrom": [0, 0, 5], "lookat": [0, 0, 0], "vfov": 40},
+                "textures": {"a": {"checker": {"odd": "a", "even": [0, 0, 0]}}},
+                "objects": [{"sphere": {"center": [0, 0, 0], "radius": 1,
+                             "material": {"lambertian": {"albedo": "a"}}}}]
+            }"#,
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "test.json: textures.a: texture refers to itself"
+        );
+    }
+}
@@ -1,20 +1,84 @@
-use crate::color::color;
+use crate::color::{color, Color};
 use crate::hittable::HitRecord;
+use crate::material::dispersion::Dispersion;
 use crate::material::{Material, MaterialType, Scatter};
 use crate::ray::Ray;
+use crate::sampler::{Sampler, SamplerType};
+use crate::spectrum::D_LINE;
 use crate::vec::Vec3;
-use rand::prelude::*;
-use rand::rngs::SmallRng;
 use std::sync::Arc;
 
+// Beer-Lambert absorption inside a dielectric, per RGB channel. Colored glass
+// is darker where it's thicker, which tinting the surface can't do.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Absorption {
+    pub sigma: Color,
+}
+
+// The transmittance that counts as black, absorbing everything would need
+// an infinite coefficient.
+const MIN_TRANSMITTANCE: f64 = 1e-6;
+
+impl Absorption {
+    // Light that travelled `distance` inside is left with `color`.
+    pub fn new(color: Color, distance: f64) -> Absorption {
+        let sigma = |c: f64| -c.clamp(MIN_TRANSMITTANCE, 1.0).ln() / distance;
+        Absorption {
+            sigma: Color {
+                r: sigma(color.r),
+                g: sigma(color.g),
+                b: sigma(color.b),
+            },
+        }
+    }
+
+    // What's left of the light along the ray up to the hit. Only a ray that
+    // hits the surface from the back travelled inside, the boundary it
+    // entered through was the last one it crossed.
+    pub fn transmittance(&self, ray: &Ray, hit: &HitRecord) -> Color {
+        if hit.front_face {
+            return color(1.0, 1.0, 1.0);
+        }
+        let distance = hit.t * ray.direction.length();
+        let t = |sigma: f64| (-sigma * distance).exp();
+        color(t(self.sigma.r), t(self.sigma.g), t(self.sigma.b))
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Dielectric {
     pub ir: f64,
+    pub absorption: Absorption,
+    pub dispersion: Option<Dispersion>,
 }
 
 impl Dielectric {
     pub fn new(ir: f64) -> Arc<MaterialType> {
-        Arc::new(MaterialType::from(Dielectric { ir: ir }))
+        Dielectric::absorbing(ir, Absorption::default())
+    }
+
+    pub fn absorbing(ir: f64, absorption: Absorption) -> Arc<MaterialType> {
+        Arc::new(MaterialType::from(Dielectric {
+            ir: ir,
+            absorption: absorption,
+            dispersion: None,
+        }))
+    }
+
+    pub fn dispersing(dispersion: Dispersion, absorption: Absorption) -> Arc<MaterialType> {
+        Arc::new(MaterialType::from(Dielectric {
+            ir: dispersion.ior(D_LINE),
+            absorption: absorption,
+            dispersion: Some(dispersion),
+        }))
+    }
+
+    // The index of refraction for the wavelength the ray carries, if any.
+    fn ir(&self, ray: &Ray) -> f64 {
+        match (self.dispersion, ray.wavelength) {
+            (Some(dispersion), Some(wavelength)) => dispersion.ior(wavelength),
+            _ => self.ir,
+        }
     }
 
     fn reflectance(cosine: f64, ref_idx: f64) -> f64 {
@@ -24,17 +88,14 @@ impl Dielectric {
 }
 
 impl Material for Dielectric {
-    fn scatter(&self, rayin: &Ray, hit: &HitRecord, rng: &mut SmallRng) -> Option<Scatter> {
-        let refraction_ratio = if hit.front_face {
-            1.0 / self.ir
-        } else {
-            self.ir
-        };
+    fn scatter(&self, rayin: &Ray, hit: &HitRecord, sampler: &mut SamplerType) -> Option<Scatter> {
+        let ir = self.ir(rayin);
+        let refraction_ratio = if hit.front_face { 1.0 / ir } else { ir };
         let unit_direction = rayin.direction.unit_vector();
         let cos_theta = f64::min(-unit_direction.dot(hit.normal), 1.0);
         let sin_theta = f64::sqrt(1.0 - cos_theta * cos_theta);
         let cannot_refract = refraction_ratio * sin_theta > 1.0
-            || Dielectric::reflectance(cos_theta, refraction_ratio) > rng.gen();
+            || Dielectric::reflectance(cos_theta, refraction_ratio) > sampler.get_1d();
         let direction = if cannot_refract {
             Vec3::reflect(unit_direction, hit.normal)
         } else {
@@ -45,9 +106,75 @@ impl Material for Dielectric {
                 origin: hit.point,
                 direction: direction,
                 time: rayin.time,
+                wavelength: rayin.wavelength,
             },
-            attenuation: color(1.0, 1.0, 1.0),
+            attenuation: self.absorption.transmittance(rayin, hit),
             pdf: None,
         })
     }
+
+    // Clear glass lets everything through, and how much colored glass
+    // absorbs depends on how thick it is.
+    fn albedo(&self, _hit: &HitRecord) -> Color {
+        color(1.0, 1.0, 1.0)
+    }
+
+    fn dispersive(&self) -> bool {
+        self.dispersion.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vec::vec3;
+
+    #[test]
+    fn absorption_depends_on_distance_inside() {
+        let absorption = Absorption::new(color(0.8, 0.5, 0.0), 1.0);
+        let ray = Ray {
+            origin: Vec3::zero(),
+            direction: vec3(0.0, 0.0, 2.0),
+            time: 0.0,
+            wavelength: None,
+        };
+        let mut hit = HitRecord {
+            point: vec3(0.0, 0.0, 2.0),
+            normal: vec3(0.0, 0.0, -1.0),
+            t: 1.0,
+            u: 0.0,
+            v: 0.0,
+            front_face: true,
+            mat: Arc::new(MaterialType::default()),
+            object: 0,
+        };
+        let entering = absorption.transmittance(&ray, &hit);
+        assert_eq!((entering.r, entering.g, entering.b), (1.0, 1.0, 1.0));
+
+        // Two units inside leave the color squared.
+        hit.front_face = false;
+        let leaving = absorption.transmittance(&ray, &hit);
+        assert!((leaving.r - 0.64).abs() < 1e-9);
+        assert!((leaving.g - 0.25).abs() < 1e-9);
+        assert!(leaving.b < 1e-9);
+    }
+
+    #[test]
+    fn dispersion_follows_the_wavelength() {
+        let glass = Dielectric {
+            ir: 1.5,
+            absorption: Absorption::default(),
+            dispersion: Some(Dispersion::BK7),
+        };
+        let ray = |wavelength| Ray {
+            origin: Vec3::zero(),
+            direction: vec3(0.0, 0.0, -1.0),
+            time: 0.0,
+            wavelength,
+        };
+        assert!(glass.ir(&ray(Some(450.0))) > glass.ir(&ray(Some(650.0))));
+        // Paths still in RGB see the index it was given.
+        assert_eq!(glass.ir(&ray(None)), 1.5);
+        assert!(glass.dispersive());
+    }
 }
@@ -0,0 +1,67 @@
+use serde::Deserialize;
+
+// How a dielectric's index of refraction changes with the wavelength, which
+// splits white light into its colors. Both models take wavelengths in
+// micrometres, like the coefficients published for glasses.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum Dispersion {
+    // n = a + b / l^2, good enough over the visible range for most glasses.
+    Cauchy { a: f64, b: f64 },
+    // n^2 = 1 + sum of b l^2 / (l^2 - c), which also fits strongly
+    // dispersive materials.
+    Sellmeier { b: [f64; 3], c: [f64; 3] },
+}
+
+impl Dispersion {
+    // Schott N-BK7, the common crown glass.
+    pub const BK7: Dispersion = Dispersion::Sellmeier {
+        b: [1.03961212, 0.231792344, 1.01046945],
+        c: [0.00600069867, 0.0200179144, 103.560653],
+    };
+
+    // Diamond, whose fire is the dispersion.
+    pub const DIAMOND: Dispersion = Dispersion::Sellmeier {
+        b: [0.3306, 4.3356, 0.0],
+        c: [0.030625, 0.011236, 0.0],
+    };
+
+    // Index of refraction at a wavelength in nanometres.
+    pub fn ior(&self, wavelength: f64) -> f64 {
+        let l = wavelength * 1e-3;
+        let l2 = l * l;
+        match self {
+            Dispersion::Cauchy { a, b } => a + b / l2,
+            Dispersion::Sellmeier { b, c } => {
+                let n2 = 1.0
+                    + b.iter()
+                        .zip(c.iter())
+                        .map(|(b, c)| b * l2 / (l2 - c))
+                        .sum::<f64>();
+                n2.max(1.0).sqrt()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spectrum::D_LINE;
+
+    #[test]
+    fn glasses_have_their_published_indices() {
+        assert!((Dispersion::BK7.ior(D_LINE) - 1.5168).abs() < 1e-3);
+        assert!((Dispersion::DIAMOND.ior(D_LINE) - 2.417).abs() < 5e-3);
+        // Blue bends more than red.
+        for dispersion in [
+            Dispersion::BK7,
+            Dispersion::DIAMOND,
+            Dispersion::Cauchy { a: 1.5, b: 0.004 },
+        ]
+        .iter()
+        {
+            assert!(dispersion.ior(450.0) > dispersion.ior(650.0));
+        }
+    }
+}
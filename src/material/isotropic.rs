@@ -1,9 +1,10 @@
+use crate::color::Color;
 use crate::hittable::HitRecord;
 use crate::material::{Material, MaterialType, Scatter};
 use crate::ray::Ray;
+use crate::sampler::{Sampler, SamplerType};
 use crate::texture::{Texture, TextureColor};
-use crate::vec::Vec3;
-use rand::rngs::SmallRng;
+use crate::util::sample_unit_sphere;
 use std::sync::Arc;
 
 // Isotropic
@@ -19,11 +20,12 @@ impl Isotropic {
 }
 
 impl Material for Isotropic {
-    fn scatter(&self, rayin: &Ray, hit: &HitRecord, rng: &mut SmallRng) -> Option<Scatter> {
+    fn scatter(&self, rayin: &Ray, hit: &HitRecord, sampler: &mut SamplerType) -> Option<Scatter> {
         let ray = Ray {
             origin: rayin.origin,
-            direction: Vec3::random_in_unit_sphere(rng),
+            direction: sample_unit_sphere(sampler.get_2d()),
             time: rayin.time,
+            wavelength: rayin.wavelength,
         };
 
         let attenuation = self.albedo.value(hit.u, hit.v, hit.point);
@@ -33,4 +35,8 @@ impl Material for Isotropic {
             pdf: None,
         })
     }
+
+    fn albedo(&self, hit: &HitRecord) -> Color {
+        self.albedo.value(hit.u, hit.v, hit.point)
+    }
 }
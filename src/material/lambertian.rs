@@ -1,11 +1,12 @@
+use crate::color::Color;
 use crate::hittable::HitRecord;
 use crate::material::{Material, MaterialType, Scatter};
 use crate::onb::Onb;
 use crate::pdf::CosinePdf;
 use crate::ray::Ray;
+use crate::sampler::{Sampler, SamplerType};
 use crate::texture::{Texture, TextureColor};
-use crate::vec::Vec3;
-use rand::rngs::SmallRng;
+use crate::util::sample_cosine_direction;
 use std::sync::Arc;
 
 // Lambertian
@@ -21,13 +22,14 @@ impl Lambertian {
 }
 
 impl Material for Lambertian {
-    fn scatter(&self, rayin: &Ray, hit: &HitRecord, rng: &mut SmallRng) -> Option<Scatter> {
+    fn scatter(&self, rayin: &Ray, hit: &HitRecord, sampler: &mut SamplerType) -> Option<Scatter> {
         let uvw = Onb::new(&hit.normal);
-        let scatter_direction = uvw.local(&Vec3::random_cosine_direction(rng));
+        let scatter_direction = uvw.local(&sample_cosine_direction(sampler.get_2d()));
         let scattered = Ray {
             origin: hit.point,
             direction: scatter_direction.unit_vector(),
             time: rayin.time,
+            wavelength: rayin.wavelength,
         };
         let attenuation = self.albedo.value(hit.u, hit.v, hit.point);
         Some(Scatter {
@@ -45,4 +47,8 @@ impl Material for Lambertian {
             cosine / std::f64::consts::PI
         }
     }
+
+    fn albedo(&self, hit: &HitRecord) -> Color {
+        self.albedo.value(hit.u, hit.v, hit.point)
+    }
 }
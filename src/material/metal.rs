@@ -1,9 +1,11 @@
+use crate::color::Color;
 use crate::hittable::HitRecord;
 use crate::material::{Material, MaterialType, Scatter};
 use crate::ray::Ray;
+use crate::sampler::{Sampler, SamplerType};
 use crate::texture::{Texture, TextureColor};
+use crate::util::sample_unit_sphere;
 use crate::vec::Vec3;
-use rand::rngs::SmallRng;
 use std::sync::Arc;
 
 // Metal
@@ -23,12 +25,15 @@ impl Metal {
 }
 
 impl Material for Metal {
-    fn scatter(&self, ray: &Ray, hit: &HitRecord, rng: &mut SmallRng) -> Option<Scatter> {
+    fn scatter(&self, ray: &Ray, hit: &HitRecord, sampler: &mut SamplerType) -> Option<Scatter> {
         let reflected = Vec3::reflect(ray.direction.unit_vector(), hit.normal);
+        // A point uniformly inside the ball of radius fuzz.
+        let radius = self.fuzz * sampler.get_1d().cbrt();
         let scattered = Ray {
             origin: hit.point,
-            direction: reflected + self.fuzz * Vec3::random_in_unit_sphere(rng),
+            direction: reflected + radius * sample_unit_sphere(sampler.get_2d()),
             time: ray.time,
+            wavelength: ray.wavelength,
         };
         let attenuation = self.albedo.value(hit.u, hit.v, hit.point);
         if scattered.direction.dot(hit.normal) > 0.0 {
@@ -40,4 +45,8 @@ impl Material for Metal {
         }
         return None;
     }
+
+    fn albedo(&self, hit: &HitRecord) -> Color {
+        self.albedo.value(hit.u, hit.v, hit.point)
+    }
 }
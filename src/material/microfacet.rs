@@ -0,0 +1,238 @@
+use crate::vec::{vec3, Vec3};
+use std::f64::consts::PI;
+
+// The GGX (Trowbridge-Reitz) distribution of microfacet normals with Smith's
+// height-correlated masking-shadowing. Directions are in the shading frame,
+// where the macro surface normal is +z.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ggx {
+    pub alpha: f64,
+}
+
+// Below this alpha the lobe is sharper than doubles can evaluate well, it's
+// a mirror for all intents anyway.
+const MIN_ALPHA: f64 = 1e-4;
+
+impl Ggx {
+    // Roughness is perceptually linear, alpha its square.
+    pub fn from_roughness(roughness: f64) -> Ggx {
+        let roughness = roughness.clamp(0.0, 1.0);
+        Ggx {
+            alpha: (roughness * roughness).max(MIN_ALPHA),
+        }
+    }
+
+    // Density of microfacet normal `h`, per solid angle projected onto the
+    // macro surface.
+    pub fn d(&self, h: Vec3) -> f64 {
+        if h.z <= 0.0 {
+            return 0.0;
+        }
+        let a2 = self.alpha * self.alpha;
+        let t = h.z * h.z * (a2 - 1.0) + 1.0;
+        a2 / (PI * t * t)
+    }
+
+    fn lambda(&self, w: Vec3) -> f64 {
+        let cos2 = w.z * w.z;
+        if cos2 == 0.0 {
+            return f64::INFINITY;
+        }
+        let tan2 = (1.0 - cos2).max(0.0) / cos2;
+        0.5 * ((1.0 + self.alpha * self.alpha * tan2).sqrt() - 1.0)
+    }
+
+    // Fraction of the microfacets facing `w` that are visible from it.
+    pub fn g1(&self, w: Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    // Fraction visible from both `wo` and `wi`.
+    pub fn g(&self, wo: Vec3, wi: Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    // Density of the normals visible from `wo`, which `sample_visible`
+    // draws from.
+    pub fn visible_pdf(&self, wo: Vec3, h: Vec3) -> f64 {
+        let cos = wo.dot(h);
+        if wo.z <= 0.0 || cos <= 0.0 {
+            return 0.0;
+        }
+        self.g1(wo) * cos * self.d(h) / wo.z
+    }
+
+    // A microfacet normal visible from `wo`, from two uniform numbers (Heitz,
+    // "Sampling the GGX Distribution of Visible Normals", 2018). Sampling
+    // only what can be seen wastes no samples on facets facing away.
+    pub fn sample_visible(&self, wo: Vec3, (u1, u2): (f64, f64)) -> Vec3 {
+        let a = self.alpha;
+        // Stretch to the hemisphere configuration.
+        let v = vec3(a * wo.x, a * wo.y, wo.z).unit_vector();
+        let len2 = v.x * v.x + v.y * v.y;
+        let t1 = if len2 > 0.0 {
+            vec3(-v.y, v.x, 0.0) * (1.0 / len2.sqrt())
+        } else {
+            vec3(1.0, 0.0, 0.0)
+        };
+        let t2 = v.cross(t1);
+        // A point on the disk, squashed onto the part of the projected
+        // hemisphere facing v.
+        let r = u1.sqrt();
+        let phi = 2.0 * PI * u2;
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1.0 + v.z);
+        let p2 = (1.0 - s) * (1.0 - p1 * p1).sqrt() + s * r * phi.sin();
+        let n = p1 * t1 + p2 * t2 + (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt() * v;
+        // Back to the ellipsoid.
+        vec3(a * n.x, a * n.y, n.z.max(0.0)).unit_vector()
+    }
+}
+
+// The GTR1 (Berry) distribution, whose long tails give clearcoats their
+// haze. Only clearcoats use it, with masking from a fixed GGX.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Gtr1 {
+    pub alpha: f64,
+}
+
+impl Gtr1 {
+    pub fn d(&self, h: Vec3) -> f64 {
+        if h.z <= 0.0 {
+            return 0.0;
+        }
+        let a2 = self.alpha * self.alpha;
+        if a2 >= 1.0 {
+            return 1.0 / PI;
+        }
+        let t = 1.0 + (a2 - 1.0) * h.z * h.z;
+        (a2 - 1.0) / (PI * a2.ln() * t)
+    }
+
+    // A normal with density d(h) cos(h).
+    pub fn sample(&self, (u1, u2): (f64, f64)) -> Vec3 {
+        let a2 = self.alpha * self.alpha;
+        let cos2 = if a2 >= 1.0 {
+            1.0 - u1
+        } else {
+            (1.0 - a2.powf(1.0 - u1)) / (1.0 - a2)
+        };
+        let cos = cos2.clamp(0.0, 1.0).sqrt();
+        let sin = (1.0 - cos * cos).max(0.0).sqrt();
+        let phi = 2.0 * PI * u2;
+        vec3(sin * phi.cos(), sin * phi.sin(), cos)
+    }
+}
+
+// The microfacet normal that takes `wo` to `wi`, facing +z, at a boundary
+// into a medium with relative index of refraction `eta` on the side away
+// from `wo`. It reflects when they're on the same side and refracts
+// otherwise. None when no facet can, because each would have to face the
+// directions from the wrong side.
+pub fn half_vector(wo: Vec3, wi: Vec3, eta: f64) -> Option<Vec3> {
+    let h = if wo.z * wi.z > 0.0 {
+        wo + wi
+    } else {
+        wo + eta * wi
+    };
+    if h.length_squared() == 0.0 {
+        return None;
+    }
+    let h = h.unit_vector();
+    let h = if h.z < 0.0 { -h } else { h };
+    if wo.dot(h) * wo.z <= 0.0 || wi.dot(h) * wi.z <= 0.0 {
+        return None;
+    }
+    Some(h)
+}
+
+// Unpolarized Fresnel reflectance of a dielectric boundary, for light
+// arriving at angle acos(cos) to the normal on the side it's on, with
+// `eta` the relative index of refraction of the other side. All light is
+// reflected past the critical angle.
+pub fn fresnel_dielectric(cos: f64, eta: f64) -> f64 {
+    let cos = cos.clamp(0.0, 1.0);
+    let sin2_t = (1.0 - cos * cos) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let rs = (cos - eta * cos_t) / (cos + eta * cos_t);
+    let rp = (eta * cos - cos_t) / (eta * cos + cos_t);
+    0.5 * (rs * rs + rp * rp)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampler::mix;
+
+    fn uniform(i: u64, stream: u64) -> f64 {
+        (mix(i, stream) >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    #[test]
+    fn projected_normals_integrate_to_one() {
+        // The integral of D(h) cos(h) over the hemisphere, by sampling it
+        // uniformly.
+        for &roughness in [0.3, 0.6, 1.0].iter() {
+            let ggx = Ggx::from_roughness(roughness);
+            let n = 200_000;
+            let mut sum = 0.0;
+            for i in 0..n {
+                let z = uniform(i, 1);
+                let phi = 2.0 * PI * uniform(i, 2);
+                let r = (1.0 - z * z).sqrt();
+                let h = vec3(r * phi.cos(), r * phi.sin(), z);
+                sum += ggx.d(h) * h.z * 2.0 * PI;
+            }
+            let integral = sum / n as f64;
+            assert!((integral - 1.0).abs() < 0.03, "{}: {}", roughness, integral);
+        }
+    }
+
+    #[test]
+    fn gtr1_sampling_matches_its_density() {
+        // The projected density integrates to one, and the mean height of the
+        // sampled normals agrees with integrating it.
+        for &alpha in [0.1, 0.3, 0.6].iter() {
+            let gtr1 = Gtr1 { alpha };
+            let n = 200_000;
+            let (mut integral, mut uniform_z, mut sampled_z) = (0.0, 0.0, 0.0);
+            for i in 0..n {
+                let z = uniform(i, 1);
+                let phi = 2.0 * PI * uniform(i, 2);
+                let r = (1.0 - z * z).sqrt();
+                let h = vec3(r * phi.cos(), r * phi.sin(), z);
+                integral += gtr1.d(h) * h.z * 2.0 * PI;
+                uniform_z += gtr1.d(h) * h.z * h.z * 2.0 * PI;
+                sampled_z += gtr1.sample((uniform(i, 3), uniform(i, 4))).z;
+            }
+            let (integral, uniform_z, sampled_z) = (
+                integral / n as f64,
+                uniform_z / n as f64,
+                sampled_z / n as f64,
+            );
+            assert!((integral - 1.0).abs() < 0.03, "{}: {}", alpha, integral);
+            assert!(
+                (uniform_z - sampled_z).abs() < 0.02,
+                "{}: {} vs {}",
+                alpha,
+                uniform_z,
+                sampled_z
+            );
+        }
+    }
+
+    #[test]
+    fn visible_normals_face_the_viewer() {
+        let ggx = Ggx::from_roughness(0.5);
+        let wo = vec3(0.6, 0.0, 0.8);
+        for i in 0..1000 {
+            let h = ggx.sample_visible(wo, (uniform(i, 3), uniform(i, 4)));
+            assert!((h.length() - 1.0).abs() < 1e-9);
+            assert!(h.z >= 0.0 && wo.dot(h) >= 0.0);
+            assert!(ggx.visible_pdf(wo, h) > 0.0);
+        }
+    }
+}
@@ -2,20 +2,26 @@ use crate::color::{color, Color};
 use crate::hittable::HitRecord;
 use crate::material::{
     dielectric::Dielectric, diffuse::Diffuse, isotropic::Isotropic, lambertian::Lambertian,
-    metal::Metal,
+    metal::Metal, principled::Principled, rough_conductor::RoughConductor,
+    rough_dielectric::RoughDielectric,
 };
 use crate::pdf::PdfType;
 use crate::ray::Ray;
+use crate::sampler::SamplerType;
 use crate::texture::{solidcolor::SolidColor, Texture};
 use crate::vec::Vec3;
 use enum_dispatch::enum_dispatch;
-use rand::rngs::SmallRng;
 
 pub mod dielectric;
 pub mod diffuse;
+pub mod dispersion;
 pub mod isotropic;
 pub mod lambertian;
 pub mod metal;
+pub mod microfacet;
+pub mod principled;
+pub mod rough_conductor;
+pub mod rough_dielectric;
 
 pub struct Scatter {
     pub ray: Ray,
@@ -25,15 +31,42 @@ pub struct Scatter {
 
 #[enum_dispatch]
 pub trait Material: Clone {
-    fn scatter(&self, _rayin: &Ray, _hit: &HitRecord, _rng: &mut SmallRng) -> Option<Scatter> {
+    fn scatter(
+        &self,
+        _rayin: &Ray,
+        _hit: &HitRecord,
+        _sampler: &mut SamplerType,
+    ) -> Option<Scatter> {
         None
     }
     fn scattering_pdf(&self, _rayin: &Ray, _hit: &HitRecord, _scattered: &Ray) -> f64 {
         1.0
     }
+    // The BSDF times the cosine for light arriving along `scattered`, which
+    // paths are weighted by; `scatter` is what `scatter` gave for the hit.
+    // Usually its attenuation times `scattering_pdf`; materials whose color
+    // changes with the direction work it out themselves.
+    fn scattering(
+        &self,
+        rayin: &Ray,
+        hit: &HitRecord,
+        scatter: &Scatter,
+        scattered: &Ray,
+    ) -> Color {
+        scatter.attenuation * self.scattering_pdf(rayin, hit, scattered)
+    }
     fn emitted(&self, _rayin: &Ray, _hit: &HitRecord, _u: f64, _v: f64, _p: Vec3) -> Color {
         color(0.0, 0.0, 0.0)
     }
+    // Surface color at the hit, for the albedo AOV and the denoiser.
+    fn albedo(&self, _hit: &HitRecord) -> Color {
+        color(0.0, 0.0, 0.0)
+    }
+    // Whether the material bends each wavelength differently, so paths that
+    // hit it have to carry a single one.
+    fn dispersive(&self) -> bool {
+        false
+    }
 }
 
 #[enum_dispatch(Material)]
@@ -44,6 +77,25 @@ pub enum MaterialType {
     Metal,
     Dielectric,
     Diffuse,
+    RoughConductor,
+    RoughDielectric,
+    Principled,
+}
+
+impl MaterialType {
+    // For diagnostics.
+    pub fn name(&self) -> &'static str {
+        match self {
+            MaterialType::Isotropic(_) => "isotropic",
+            MaterialType::Lambertian(_) => "lambertian",
+            MaterialType::Metal(_) => "metal",
+            MaterialType::Dielectric(_) => "dielectric",
+            MaterialType::Diffuse(_) => "diffuse light",
+            MaterialType::RoughConductor(_) => "rough conductor",
+            MaterialType::RoughDielectric(_) => "rough dielectric",
+            MaterialType::Principled(_) => "principled",
+        }
+    }
 }
 
 impl Default for MaterialType {
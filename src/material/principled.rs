@@ -0,0 +1,449 @@
+use crate::color::{color, Color};
+use crate::hittable::HitRecord;
+use crate::material::microfacet::{Ggx, Gtr1};
+use crate::material::rough_dielectric;
+use crate::material::{Material, MaterialType, Scatter};
+use crate::pdf::{PdfType, PrincipledPdf};
+use crate::ray::Ray;
+use crate::sampler::SamplerType;
+use crate::texture::{solidcolor::SolidColor, Texture, TextureColor};
+use crate::util::sample_cosine_direction;
+use crate::vec::Vec3;
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+// The Disney principled BSDF (Burley, "Physically Based Shading at Disney",
+// 2012, and "Extending the Disney BRDF to a BSDF with Integrated Subsurface
+// Scattering", 2015): a diffuse base with a fake subsurface look and sheen,
+// a GGX specular layer that turns into a metal with `metallic`, a GTR1
+// clearcoat on top, and rough glass with `transmission`. Every parameter but
+// the color is in [0, 1] and may come from a texture, whose channels are
+// averaged. They are boxed, textures are large and there are eleven.
+#[derive(Debug, Clone)]
+pub struct Principled {
+    pub base_color: Box<Texture>,
+    pub metallic: Box<Texture>,
+    pub roughness: Box<Texture>,
+    // Specular reflectance head on, 0.5 is 4% like most dielectrics. It also
+    // sets the index of refraction for transmission, 0.5 is 1.5.
+    pub specular: Box<Texture>,
+    // Tints dielectric specular towards the base color.
+    pub specular_tint: Box<Texture>,
+    // A soft glow at grazing angles, for cloth.
+    pub sheen: Box<Texture>,
+    pub sheen_tint: Box<Texture>,
+    pub clearcoat: Box<Texture>,
+    pub clearcoat_gloss: Box<Texture>,
+    pub transmission: Box<Texture>,
+    pub subsurface: Box<Texture>,
+}
+
+fn constant(x: f64) -> Box<Texture> {
+    Box::new(SolidColor::new(x, x, x))
+}
+
+// Disney's defaults, a grey plastic.
+impl Default for Principled {
+    fn default() -> Principled {
+        Principled {
+            base_color: constant(0.8),
+            metallic: constant(0.0),
+            roughness: constant(0.5),
+            specular: constant(0.5),
+            specular_tint: constant(0.0),
+            sheen: constant(0.0),
+            sheen_tint: constant(0.5),
+            clearcoat: constant(0.0),
+            clearcoat_gloss: constant(1.0),
+            transmission: constant(0.0),
+            subsurface: constant(0.0),
+        }
+    }
+}
+
+impl Principled {
+    pub fn material(self) -> Arc<MaterialType> {
+        Arc::new(MaterialType::from(self))
+    }
+
+    // The parameters at the hit, worked out into the lobes.
+    fn lobes(&self, hit: &HitRecord) -> Lobes {
+        let value = |texture: &Texture| texture.value(hit.u, hit.v, hit.point);
+        let scalar = |texture: &Texture| {
+            let c = value(texture);
+            ((c.r + c.g + c.b) / 3.0).clamp(0.0, 1.0)
+        };
+        let base = value(&self.base_color);
+        let metallic = scalar(&self.metallic);
+        let roughness = scalar(&self.roughness);
+        let specular = scalar(&self.specular);
+        let transmission = (1.0 - metallic) * scalar(&self.transmission);
+        let sqrt_f0 = (0.08 * specular).max(MIN_F0).sqrt();
+        let ior = (1.0 + sqrt_f0) / (1.0 - sqrt_f0);
+        let ggx = Ggx::from_roughness(roughness);
+        let white = color(1.0, 1.0, 1.0);
+        if !hit.front_face && transmission > 0.0 {
+            // Inside a transmissive material only the glass is left to get
+            // back out through.
+            return Lobes {
+                base,
+                roughness,
+                subsurface: 0.0,
+                sheen: Color::default(),
+                specular0: Color::default(),
+                ggx,
+                gtr1: Gtr1 { alpha: 1.0 },
+                eta: 1.0 / ior,
+                weights: [0.0, 0.0, 0.0, 1.0],
+            };
+        }
+
+        let luminance = base.luminance();
+        let tint = if luminance > 0.0 {
+            base * (1.0 / luminance)
+        } else {
+            white
+        };
+        let specular_tint = scalar(&self.specular_tint);
+        let specular0 = lerp(
+            lerp(white, tint, specular_tint) * (0.08 * specular),
+            base,
+            metallic,
+        );
+        let sheen_tint = scalar(&self.sheen_tint);
+        let diffuse = (1.0 - metallic) * (1.0 - scalar(&self.transmission));
+        Lobes {
+            base,
+            roughness,
+            subsurface: scalar(&self.subsurface),
+            sheen: lerp(white, tint, sheen_tint) * scalar(&self.sheen),
+            specular0,
+            ggx,
+            gtr1: Gtr1 {
+                alpha: 0.1 + (0.001 - 0.1) * scalar(&self.clearcoat_gloss),
+            },
+            eta: ior,
+            weights: [
+                diffuse,
+                1.0 - transmission,
+                0.25 * scalar(&self.clearcoat),
+                transmission,
+            ],
+        }
+    }
+}
+
+// Below this the index of refraction is too close to 1 to refract anything.
+const MIN_F0: f64 = 1e-4;
+
+fn lerp(a: Color, b: Color, t: f64) -> Color {
+    a * (1.0 - t) + b * t
+}
+
+fn mix(a: f64, b: f64, t: f64) -> f64 {
+    a * (1.0 - t) + b * t
+}
+
+fn schlick_weight(cos: f64) -> f64 {
+    (1.0 - cos).clamp(0.0, 1.0).powi(5)
+}
+
+const DIFFUSE: usize = 0;
+const SPECULAR: usize = 1;
+const CLEARCOAT: usize = 2;
+const TRANSMISSION: usize = 3;
+
+// The principled BSDF at one point, in the shading frame with `wo` above the
+// surface. The weights of the diffuse, specular, clearcoat and transmission
+// lobes also decide how often each is sampled.
+#[derive(Debug, Clone, Copy)]
+pub struct Lobes {
+    base: Color,
+    roughness: f64,
+    subsurface: f64,
+    sheen: Color,
+    specular0: Color,
+    ggx: Ggx,
+    gtr1: Gtr1,
+    // Relative index of refraction of the side transmitted light goes into.
+    eta: f64,
+    weights: [f64; 4],
+}
+
+// Clearcoats are masked like GGX with this alpha.
+const CLEARCOAT_ALPHA: f64 = 0.25;
+
+impl Lobes {
+    // The BSDF times |cos(wi)|.
+    pub fn eval(&self, wo: Vec3, wi: Vec3) -> Color {
+        let mut f = Color::default();
+        if wo.z <= 0.0 || wi.z == 0.0 {
+            return f;
+        }
+        let transmission = self.weights[TRANSMISSION];
+        if transmission > 0.0 {
+            let glass = transmission * rough_dielectric::bsdf(&self.ggx, self.eta, wo, wi);
+            // Light going in and coming back out is tinted by the base color
+            // once in all.
+            f += if wi.z < 0.0 {
+                color(self.base.r.sqrt(), self.base.g.sqrt(), self.base.b.sqrt()) * glass
+            } else {
+                color(glass, glass, glass)
+            };
+        }
+        if wi.z < 0.0 {
+            return f;
+        }
+
+        let h = (wo + wi).unit_vector();
+        let cos_d = wi.dot(h);
+        let (fl, fv) = (schlick_weight(wi.z), schlick_weight(wo.z));
+        let diffuse = self.weights[DIFFUSE];
+        if diffuse > 0.0 {
+            // Retroreflection at grazing angles on rough surfaces, and a
+            // flatter look that stands in for subsurface scattering.
+            let fd90 = 0.5 + 2.0 * self.roughness * cos_d * cos_d;
+            let fd = mix(1.0, fd90, fl) * mix(1.0, fd90, fv);
+            let fss90 = self.roughness * cos_d * cos_d;
+            let fss = mix(1.0, fss90, fl) * mix(1.0, fss90, fv);
+            let ss = 1.25 * (fss * (1.0 / (wi.z + wo.z) - 0.5) + 0.5);
+            let lambert = mix(fd, ss, self.subsurface) / PI;
+            f += (self.base * lambert + self.sheen * schlick_weight(cos_d)) * (diffuse * wi.z);
+        }
+        let specular = self.weights[SPECULAR];
+        if specular > 0.0 {
+            let fresnel = lerp(self.specular0, color(1.0, 1.0, 1.0), schlick_weight(cos_d));
+            let dg = self.ggx.d(h) * self.ggx.g(wo, wi);
+            f += fresnel * (specular * dg / (4.0 * wo.z));
+        }
+        let clearcoat = self.weights[CLEARCOAT];
+        if clearcoat > 0.0 {
+            let masking = Ggx {
+                alpha: CLEARCOAT_ALPHA,
+            };
+            let fresnel = mix(0.04, 1.0, schlick_weight(cos_d));
+            let g = masking.g1(wo) * masking.g1(wi);
+            let c = clearcoat * fresnel * self.gtr1.d(h) * g / (4.0 * wo.z);
+            f += color(c, c, c);
+        }
+        f
+    }
+
+    // How often each lobe is sampled.
+    fn probabilities(&self) -> [f64; 4] {
+        let total: f64 = self.weights.iter().sum();
+        if total <= 0.0 {
+            return [1.0, 0.0, 0.0, 0.0];
+        }
+        let mut p = self.weights;
+        for x in p.iter_mut() {
+            *x /= total;
+        }
+        p
+    }
+
+    // Density of `sample` picking `wi`, over all the lobes.
+    pub fn pdf(&self, wo: Vec3, wi: Vec3) -> f64 {
+        if wo.z <= 0.0 || wi.z == 0.0 {
+            return 0.0;
+        }
+        let p = self.probabilities();
+        let mut pdf = 0.0;
+        if p[TRANSMISSION] > 0.0 {
+            pdf += p[TRANSMISSION] * rough_dielectric::pdf(&self.ggx, self.eta, wo, wi);
+        }
+        if wi.z < 0.0 {
+            return pdf;
+        }
+        let h = (wo + wi).unit_vector();
+        let cos_o = wo.dot(h);
+        if cos_o <= 0.0 {
+            return pdf;
+        }
+        pdf += p[DIFFUSE] * wi.z / PI;
+        pdf += p[SPECULAR] * self.ggx.visible_pdf(wo, h) / (4.0 * cos_o);
+        pdf += p[CLEARCOAT] * self.gtr1.d(h) * h.z / (4.0 * cos_o);
+        pdf
+    }
+
+    // A lobe by `choice`, then a direction from it.
+    pub fn sample(&self, wo: Vec3, choice: f64, u: (f64, f64), fresnel_choice: f64) -> Vec3 {
+        let p = self.probabilities();
+        let mut choice = choice;
+        let mut lobe = DIFFUSE;
+        for (i, &pi) in p.iter().enumerate() {
+            if pi > 0.0 {
+                lobe = i;
+                if choice < pi {
+                    break;
+                }
+                choice -= pi;
+            }
+        }
+        match lobe {
+            DIFFUSE => sample_cosine_direction(u),
+            SPECULAR => Vec3::reflect(-wo, self.ggx.sample_visible(wo, u)),
+            CLEARCOAT => Vec3::reflect(-wo, self.gtr1.sample(u)),
+            _ => rough_dielectric::sample(&self.ggx, self.eta, wo, u, fresnel_choice),
+        }
+    }
+}
+
+impl Material for Principled {
+    fn scatter(&self, ray: &Ray, hit: &HitRecord, _sampler: &mut SamplerType) -> Option<Scatter> {
+        // The direction comes from the pdf, the mirror direction is only a
+        // placeholder.
+        Some(Scatter {
+            ray: Ray {
+                origin: hit.point,
+                direction: Vec3::reflect(ray.direction.unit_vector(), hit.normal),
+                time: ray.time,
+                wavelength: ray.wavelength,
+            },
+            attenuation: color(1.0, 1.0, 1.0),
+            pdf: Some(PrincipledPdf::new(
+                hit.normal,
+                ray.direction,
+                self.lobes(hit),
+            )),
+        })
+    }
+
+    // The lobes at the hit were worked out for the pdf already.
+    fn scattering(
+        &self,
+        _ray: &Ray,
+        _hit: &HitRecord,
+        scatter: &Scatter,
+        scattered: &Ray,
+    ) -> Color {
+        match &scatter.pdf {
+            Some(PdfType::PrincipledPdf(pdf)) => pdf.eval(scattered.direction),
+            _ => Color::default(),
+        }
+    }
+
+    fn albedo(&self, hit: &HitRecord) -> Color {
+        self.base_color.value(hit.u, hit.v, hit.point)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampler::mix as hash;
+    use crate::vec::vec3;
+
+    fn uniform(i: u64, stream: u64) -> f64 {
+        (hash(i, stream) >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn hit(front_face: bool) -> HitRecord {
+        HitRecord {
+            point: Vec3::zero(),
+            normal: vec3(0.0, 0.0, 1.0),
+            t: 1.0,
+            u: 0.0,
+            v: 0.0,
+            front_face,
+            mat: Arc::new(MaterialType::default()),
+            object: 0,
+        }
+    }
+
+    // Importance sampling must agree with brute force integration over the
+    // sphere on how much light each kind of material scatters, which is at
+    // most all of it for a white one.
+    #[test]
+    fn sampling_matches_the_bsdf() {
+        let materials = [
+            ("plastic", Principled::default()),
+            (
+                "rough metal",
+                Principled {
+                    base_color: constant(1.0),
+                    metallic: constant(1.0),
+                    roughness: constant(0.6),
+                    ..Principled::default()
+                },
+            ),
+            (
+                "coated paint",
+                Principled {
+                    base_color: Box::new(SolidColor::new(0.7, 0.1, 0.1)),
+                    roughness: constant(0.4),
+                    clearcoat: constant(1.0),
+                    clearcoat_gloss: constant(0.3),
+                    ..Principled::default()
+                },
+            ),
+            (
+                "velvet",
+                Principled {
+                    roughness: constant(1.0),
+                    sheen: constant(1.0),
+                    subsurface: constant(0.8),
+                    ..Principled::default()
+                },
+            ),
+            (
+                "frosted glass",
+                Principled {
+                    base_color: constant(1.0),
+                    roughness: constant(0.6),
+                    transmission: constant(1.0),
+                    ..Principled::default()
+                },
+            ),
+        ];
+        let wo = vec3(0.6, 0.0, 0.8);
+        for (name, material) in materials.iter() {
+            let lobes = material.lobes(&hit(true));
+            let n = 400_000;
+            let (mut total, mut uniform_f, mut sampled_f) = (0.0, 0.0, 0.0);
+            for i in 0..n {
+                let z = 2.0 * uniform(i, 1) - 1.0;
+                let phi = 2.0 * PI * uniform(i, 2);
+                let r = (1.0 - z * z).sqrt();
+                let wi = vec3(r * phi.cos(), r * phi.sin(), z);
+                total += lobes.pdf(wo, wi) * 4.0 * PI;
+                uniform_f += lobes.eval(wo, wi).luminance() * 4.0 * PI;
+                let wi = lobes.sample(
+                    wo,
+                    uniform(i, 3),
+                    (uniform(i, 4), uniform(i, 5)),
+                    uniform(i, 6),
+                );
+                let pdf = lobes.pdf(wo, wi);
+                if pdf > 0.0 {
+                    sampled_f += lobes.eval(wo, wi).luminance() / pdf;
+                }
+            }
+            let (total, uniform_f, sampled_f) =
+                (total / n as f64, uniform_f / n as f64, sampled_f / n as f64);
+            assert!(total > 0.7 && total < 1.05, "{}: {}", name, total);
+            assert!(sampled_f < 1.05, "{}: {}", name, sampled_f);
+            assert!(
+                (uniform_f - sampled_f).abs() < 0.05 * sampled_f,
+                "{}: {} vs {}",
+                name,
+                uniform_f,
+                sampled_f
+            );
+        }
+    }
+
+    #[test]
+    fn only_glass_is_left_inside() {
+        let glass = Principled {
+            transmission: constant(1.0),
+            clearcoat: constant(1.0),
+            ..Principled::default()
+        };
+        let inside = glass.lobes(&hit(false));
+        assert_eq!(inside.weights, [0.0, 0.0, 0.0, 1.0]);
+        assert!((inside.eta - 1.0 / 1.5).abs() < 1e-9);
+        let opaque = Principled::default().lobes(&hit(false));
+        assert!(opaque.weights[DIFFUSE] > 0.0);
+    }
+}
@@ -0,0 +1,268 @@
+use crate::color::{color, Color};
+use crate::hittable::HitRecord;
+use crate::material::microfacet::Ggx;
+use crate::material::{Material, MaterialType, Scatter};
+use crate::onb::Onb;
+use crate::pdf::GgxPdf;
+use crate::ray::Ray;
+use crate::sampler::SamplerType;
+use crate::vec::Vec3;
+use serde::Deserialize;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+// Metals with measured optical constants, per RGB channel.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Conductor {
+    Gold,
+    Copper,
+    Aluminium,
+    Silver,
+}
+
+impl Conductor {
+    // Complex index of refraction, eta + ik.
+    pub fn ior(&self) -> (Color, Color) {
+        match self {
+            Conductor::Gold => (
+                color(0.143119, 0.374957, 1.44248),
+                color(3.98316, 2.38572, 1.60322),
+            ),
+            Conductor::Copper => (
+                color(0.200438, 0.924033, 1.10221),
+                color(3.91295, 2.45285, 2.14219),
+            ),
+            Conductor::Aluminium => (
+                color(1.65746, 0.880369, 0.521229),
+                color(9.22387, 6.26952, 4.837),
+            ),
+            Conductor::Silver => (
+                color(0.155265, 0.116723, 0.138342),
+                color(4.82835, 3.12225, 2.14696),
+            ),
+        }
+    }
+}
+
+impl FromStr for Conductor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Conductor, String> {
+        match s {
+            "gold" => Ok(Conductor::Gold),
+            "copper" => Ok(Conductor::Copper),
+            "aluminium" => Ok(Conductor::Aluminium),
+            "silver" => Ok(Conductor::Silver),
+            _ => Err(format!(
+                "unknown conductor {}, expected gold, copper, aluminium or silver",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Conductor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Conductor::Gold => "gold",
+            Conductor::Copper => "copper",
+            Conductor::Aluminium => "aluminium",
+            Conductor::Silver => "silver",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// A metal made of GGX microfacets, each a perfect mirror whose reflectance is
+// the Fresnel term for the complex index of refraction `eta` + i`k`. Unlike
+// `Metal` it has a pdf, so it takes part in light sampling.
+#[derive(Debug, Clone)]
+pub struct RoughConductor {
+    pub eta: Color,
+    pub k: Color,
+    pub ggx: Ggx,
+}
+
+impl RoughConductor {
+    pub fn new(eta: Color, k: Color, roughness: f64) -> Arc<MaterialType> {
+        Arc::new(MaterialType::from(RoughConductor {
+            eta,
+            k,
+            ggx: Ggx::from_roughness(roughness),
+        }))
+    }
+
+    pub fn preset(conductor: Conductor, roughness: f64) -> Arc<MaterialType> {
+        let (eta, k) = conductor.ior();
+        RoughConductor::new(eta, k, roughness)
+    }
+
+    fn fresnel(&self, cos: f64) -> Color {
+        let (eta, k) = (self.eta, self.k);
+        color(
+            fresnel_conductor(cos, eta.r, k.r),
+            fresnel_conductor(cos, eta.g, k.g),
+            fresnel_conductor(cos, eta.b, k.b),
+        )
+    }
+
+    // The outgoing and incoming directions in the shading frame.
+    fn frame(ray: &Ray, hit: &HitRecord, scattered: &Ray) -> (Vec3, Vec3) {
+        let uvw = Onb::new(&hit.normal);
+        let wo = uvw.to_local(&-ray.direction.unit_vector());
+        let wi = uvw.to_local(&scattered.direction.unit_vector());
+        (wo, wi)
+    }
+}
+
+impl Material for RoughConductor {
+    fn scatter(&self, ray: &Ray, hit: &HitRecord, _sampler: &mut SamplerType) -> Option<Scatter> {
+        // The direction comes from the pdf, the mirror direction is only a
+        // placeholder.
+        Some(Scatter {
+            ray: Ray {
+                origin: hit.point,
+                direction: Vec3::reflect(ray.direction.unit_vector(), hit.normal),
+                time: ray.time,
+                wavelength: ray.wavelength,
+            },
+            attenuation: color(1.0, 1.0, 1.0),
+            pdf: Some(GgxPdf::new(hit.normal, ray.direction, self.ggx)),
+        })
+    }
+
+    // The microfacet BSDF times the cosine, D G / (4 cos(wo)), without the
+    // Fresnel term, which `scattering` adds per channel.
+    fn scattering_pdf(&self, ray: &Ray, hit: &HitRecord, scattered: &Ray) -> f64 {
+        let (wo, wi) = RoughConductor::frame(ray, hit, scattered);
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return 0.0;
+        }
+        let h = (wo + wi).unit_vector();
+        self.ggx.d(h) * self.ggx.g(wo, wi) / (4.0 * wo.z)
+    }
+
+    fn scattering(&self, ray: &Ray, hit: &HitRecord, scatter: &Scatter, scattered: &Ray) -> Color {
+        let f = self.scattering_pdf(ray, hit, scattered);
+        if f == 0.0 {
+            return Color::default();
+        }
+        let (wo, wi) = RoughConductor::frame(ray, hit, scattered);
+        let h = (wo + wi).unit_vector();
+        scatter.attenuation * self.fresnel(wi.dot(h)) * f
+    }
+
+    // Reflectance head on.
+    fn albedo(&self, _hit: &HitRecord) -> Color {
+        self.fresnel(1.0)
+    }
+}
+
+// Unpolarized Fresnel reflectance of a conductor, from outside at angle
+// acos(cos) to the normal.
+fn fresnel_conductor(cos: f64, eta: f64, k: f64) -> f64 {
+    let cos = cos.clamp(0.0, 1.0);
+    let cos2 = cos * cos;
+    let sin2 = 1.0 - cos2;
+    let (eta2, k2) = (eta * eta, k * k);
+    let t0 = eta2 - k2 - sin2;
+    let a2_plus_b2 = (t0 * t0 + 4.0 * eta2 * k2).sqrt();
+    let t1 = a2_plus_b2 + cos2;
+    let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+    let t2 = 2.0 * cos * a;
+    let rs = (t1 - t2) / (t1 + t2);
+    let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+    let t4 = t2 * sin2;
+    let rp = rs * (t3 - t4) / (t3 + t4);
+    0.5 * (rp + rs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pdf::Pdf;
+    use crate::sampler::{Sampler, SamplerKind};
+    use crate::vec::vec3;
+
+    #[test]
+    fn presets_are_colored_and_brighter_at_grazing_angles() {
+        let (eta, k) = Conductor::Gold.ior();
+        let gold = |cos: f64| fresnel_conductor(cos, eta.r, k.r);
+        assert!(gold(1.0) > 0.9 && gold(0.01) > gold(0.5));
+        assert!(fresnel_conductor(1.0, eta.b, k.b) < 0.5);
+        for &conductor in [Conductor::Copper, Conductor::Aluminium, Conductor::Silver].iter() {
+            let (eta, k) = conductor.ior();
+            let r = fresnel_conductor(1.0, eta.r, k.r);
+            assert!((0.5..1.0).contains(&r), "{}", conductor);
+        }
+    }
+
+    // Importance sampling and brute force integration over the hemisphere
+    // must agree on how much light a mirror-like conductor reflects, and
+    // that's all of it for a smooth one.
+    #[test]
+    fn sampling_matches_the_bsdf() {
+        for &roughness in [0.2, 0.5, 0.9].iter() {
+            let material = RoughConductor {
+                eta: color(0.0, 0.0, 0.0),
+                k: color(1e9, 1e9, 1e9),
+                ggx: Ggx::from_roughness(roughness),
+            };
+            let normal = vec3(0.0, 1.0, 0.0);
+            let hit = HitRecord {
+                point: Vec3::zero(),
+                normal,
+                t: 1.0,
+                u: 0.0,
+                v: 0.0,
+                front_face: true,
+                mat: Arc::new(MaterialType::default()),
+                object: 0,
+            };
+            let ray = Ray {
+                origin: vec3(0.0, 1.0, -1.0),
+                direction: vec3(0.0, -1.0, 1.0),
+                time: 0.0,
+                wavelength: None,
+            };
+            let mut sampler = SamplerKind::Independent.sampler(1, 1);
+            let scatter = material.scatter(&ray, &hit, &mut sampler).unwrap();
+            let reflected = |direction: Vec3| {
+                let scattered = Ray {
+                    origin: Vec3::zero(),
+                    direction,
+                    time: 0.0,
+                    wavelength: None,
+                };
+                material.scattering(&ray, &hit, &scatter, &scattered).g
+            };
+            let pdf = GgxPdf::new(normal, ray.direction, material.ggx);
+            let n = 50_000;
+            let (mut sampled, mut uniform) = (0.0, 0.0);
+            for i in 0..n {
+                sampler.start_pixel_sample(0, 0, i);
+                let direction = pdf.generate(&mut sampler);
+                sampled += reflected(direction) / pdf.value(direction, sampler.rng());
+                let (u1, u2) = sampler.get_2d();
+                let r = (1.0 - u1 * u1).sqrt();
+                let phi = 2.0 * std::f64::consts::PI * u2;
+                let direction = vec3(r * phi.cos(), u1, r * phi.sin());
+                uniform += reflected(direction) * 2.0 * std::f64::consts::PI;
+            }
+            let (sampled, uniform) = (sampled / n as f64, uniform / n as f64);
+            assert!(sampled <= 1.0 + 1e-9, "{}: {}", roughness, sampled);
+            assert!(
+                (sampled - uniform).abs() < 0.03,
+                "{}: {} vs {}",
+                roughness,
+                sampled,
+                uniform
+            );
+            if roughness < 0.3 {
+                assert!(sampled > 0.95, "{}: {}", roughness, sampled);
+            }
+        }
+    }
+}
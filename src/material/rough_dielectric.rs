@@ -0,0 +1,216 @@
+use crate::color::{color, Color};
+use crate::hittable::HitRecord;
+use crate::material::dielectric::Absorption;
+use crate::material::dispersion::Dispersion;
+use crate::material::microfacet::{fresnel_dielectric, half_vector, Ggx};
+use crate::material::{Material, MaterialType, Scatter};
+use crate::onb::Onb;
+use crate::pdf::RoughDielectricPdf;
+use crate::ray::Ray;
+use crate::sampler::SamplerType;
+use crate::spectrum::D_LINE;
+use crate::vec::Vec3;
+use std::sync::Arc;
+
+// Frosted glass: GGX microfacets that each reflect or refract like a smooth
+// dielectric with index of refraction `ir` (Walter et al., "Microfacet
+// Models for Refraction through Rough Surfaces", 2007). Light passing
+// through is spread out by the roughness, and unlike `Dielectric` it has a
+// pdf, so it takes part in light sampling.
+#[derive(Debug, Clone)]
+pub struct RoughDielectric {
+    pub ir: f64,
+    pub ggx: Ggx,
+    pub absorption: Absorption,
+    pub dispersion: Option<Dispersion>,
+}
+
+impl RoughDielectric {
+    pub fn new(ir: f64, roughness: f64) -> Arc<MaterialType> {
+        RoughDielectric::absorbing(ir, roughness, Absorption::default())
+    }
+
+    pub fn absorbing(ir: f64, roughness: f64, absorption: Absorption) -> Arc<MaterialType> {
+        Arc::new(MaterialType::from(RoughDielectric {
+            ir,
+            ggx: Ggx::from_roughness(roughness),
+            absorption,
+            dispersion: None,
+        }))
+    }
+
+    pub fn dispersing(
+        dispersion: Dispersion,
+        roughness: f64,
+        absorption: Absorption,
+    ) -> Arc<MaterialType> {
+        Arc::new(MaterialType::from(RoughDielectric {
+            ir: dispersion.ior(D_LINE),
+            ggx: Ggx::from_roughness(roughness),
+            absorption,
+            dispersion: Some(dispersion),
+        }))
+    }
+
+    // Relative index of refraction of the side the ray goes into, for the
+    // wavelength it carries, if any.
+    fn eta(&self, ray: &Ray, hit: &HitRecord) -> f64 {
+        let ir = match (self.dispersion, ray.wavelength) {
+            (Some(dispersion), Some(wavelength)) => dispersion.ior(wavelength),
+            _ => self.ir,
+        };
+        if hit.front_face {
+            ir
+        } else {
+            1.0 / ir
+        }
+    }
+}
+
+impl Material for RoughDielectric {
+    fn scatter(&self, ray: &Ray, hit: &HitRecord, _sampler: &mut SamplerType) -> Option<Scatter> {
+        // The direction comes from the pdf, the mirror direction is only a
+        // placeholder.
+        Some(Scatter {
+            ray: Ray {
+                origin: hit.point,
+                direction: Vec3::reflect(ray.direction.unit_vector(), hit.normal),
+                time: ray.time,
+                wavelength: ray.wavelength,
+            },
+            attenuation: self.absorption.transmittance(ray, hit),
+            pdf: Some(RoughDielectricPdf::new(
+                hit.normal,
+                ray.direction,
+                self.ggx,
+                self.eta(ray, hit),
+            )),
+        })
+    }
+
+    fn scattering_pdf(&self, ray: &Ray, hit: &HitRecord, scattered: &Ray) -> f64 {
+        let uvw = Onb::new(&hit.normal);
+        let wo = uvw.to_local(&-ray.direction.unit_vector());
+        let wi = uvw.to_local(&scattered.direction.unit_vector());
+        bsdf(&self.ggx, self.eta(ray, hit), wo, wi)
+    }
+
+    // Clear glass lets everything through, and how much colored glass
+    // absorbs depends on how thick it is.
+    fn albedo(&self, _hit: &HitRecord) -> Color {
+        color(1.0, 1.0, 1.0)
+    }
+
+    fn dispersive(&self) -> bool {
+        self.dispersion.is_some()
+    }
+}
+
+// The BSDF times |cos(wi)| in the shading frame, for `wo` above the surface
+// and a boundary into relative index `eta` below it. Transmission leaves out
+// the 1 / eta^2 that radiance picks up going through, it comes back out when
+// the path leaves on the other side.
+pub fn bsdf(ggx: &Ggx, eta: f64, wo: Vec3, wi: Vec3) -> f64 {
+    if wo.z <= 0.0 || wi.z == 0.0 {
+        return 0.0;
+    }
+    let h = match half_vector(wo, wi, eta) {
+        Some(h) => h,
+        None => return 0.0,
+    };
+    let f = fresnel_dielectric(wo.dot(h), eta);
+    let dg = ggx.d(h) * ggx.g(wo, wi);
+    if wi.z > 0.0 {
+        f * dg / (4.0 * wo.z)
+    } else {
+        let denom = wo.dot(h) + eta * wi.dot(h);
+        (1.0 - f) * dg * (wi.dot(h) * wo.dot(h)).abs() / (wo.z * denom * denom) * (eta * eta)
+    }
+}
+
+// Density of `sample` picking `wi`.
+pub fn pdf(ggx: &Ggx, eta: f64, wo: Vec3, wi: Vec3) -> f64 {
+    if wo.z <= 0.0 || wi.z == 0.0 {
+        return 0.0;
+    }
+    let h = match half_vector(wo, wi, eta) {
+        Some(h) => h,
+        None => return 0.0,
+    };
+    let f = fresnel_dielectric(wo.dot(h), eta);
+    let visible = ggx.visible_pdf(wo, h);
+    if wi.z > 0.0 {
+        f * visible / (4.0 * wo.dot(h))
+    } else {
+        let denom = wo.dot(h) + eta * wi.dot(h);
+        (1.0 - f) * visible * eta * eta * wi.dot(h).abs() / (denom * denom)
+    }
+}
+
+// A visible microfacet, then reflection off it or refraction through it in
+// proportion to its Fresnel reflectance.
+pub fn sample(ggx: &Ggx, eta: f64, wo: Vec3, u: (f64, f64), choice: f64) -> Vec3 {
+    let h = ggx.sample_visible(wo, u);
+    let cos = wo.dot(h);
+    if choice < fresnel_dielectric(cos, eta) {
+        Vec3::reflect(-wo, h)
+    } else {
+        Vec3::refract(-wo, h, 1.0 / eta)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampler::mix;
+    use crate::vec::vec3;
+    use std::f64::consts::PI;
+
+    fn uniform(i: u64, stream: u64) -> f64 {
+        (mix(i, stream) >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn uniform_sphere(i: u64) -> Vec3 {
+        let z = 2.0 * uniform(i, 1) - 1.0;
+        let phi = 2.0 * PI * uniform(i, 2);
+        let r = (1.0 - z * z).sqrt();
+        vec3(r * phi.cos(), r * phi.sin(), z)
+    }
+
+    // The pdf must integrate to one over the sphere, less the samples that
+    // end up on the wrong side of the surface, and weighting samples by it
+    // must agree with brute force integration of the BSDF.
+    #[test]
+    fn sampling_matches_the_bsdf() {
+        let wo = vec3(0.5, 0.0, 0.75f64.sqrt());
+        for &(eta, roughness) in [(1.5, 0.5), (1.5, 0.8), (1.0 / 1.5, 0.5)].iter() {
+            let ggx = Ggx::from_roughness(roughness);
+            let n = 1_000_000;
+            let (mut total, mut uniform_bsdf, mut sampled_bsdf) = (0.0, 0.0, 0.0);
+            for i in 0..n {
+                let wi = uniform_sphere(i);
+                total += pdf(&ggx, eta, wo, wi) * 4.0 * PI;
+                uniform_bsdf += bsdf(&ggx, eta, wo, wi) * 4.0 * PI;
+                let wi = sample(&ggx, eta, wo, (uniform(i, 3), uniform(i, 4)), uniform(i, 5));
+                let p = pdf(&ggx, eta, wo, wi);
+                if p > 0.0 {
+                    sampled_bsdf += bsdf(&ggx, eta, wo, wi) / p;
+                }
+            }
+            let (total, uniform_bsdf, sampled_bsdf) = (
+                total / n as f64,
+                uniform_bsdf / n as f64,
+                sampled_bsdf / n as f64,
+            );
+            let case = format!("eta {} roughness {}", eta, roughness);
+            assert!(total > 0.9 && total < 1.03, "{}: {}", case, total);
+            assert!(
+                (uniform_bsdf - sampled_bsdf).abs() < 0.05 * sampled_bsdf,
+                "{}: {} vs {}",
+                case,
+                uniform_bsdf,
+                sampled_bsdf
+            );
+        }
+    }
+}
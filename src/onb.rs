@@ -33,4 +33,10 @@ impl Onb {
     pub fn local(&self, a: &Vec3) -> Vec3 {
         return a.x * self.u() + a.y * self.v() + a.z * self.w();
     }
+
+    // The inverse of `local`: the coordinates of world vector `a` in this
+    // basis.
+    pub fn to_local(&self, a: &Vec3) -> Vec3 {
+        vec3(a.dot(self.u()), a.dot(self.v()), a.dot(self.w()))
+    }
 }
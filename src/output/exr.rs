@@ -0,0 +1,159 @@
+use crate::color::Color;
+use crate::output::finite;
+use std::io::{self, Write};
+
+// A minimal OpenEXR writer: single part, scanline, uncompressed 32-bit float
+// channels. That's all we need to hand linear renders to a grading tool.
+
+const MAGIC: u32 = 20_000_630;
+const VERSION: u32 = 2;
+const PIXEL_TYPE_FLOAT: u32 = 2;
+
+pub struct Channel<'a> {
+    pub name: &'a str,
+    pub data: Vec<f32>,
+}
+
+pub fn rgb_channels(pixels: &[Color]) -> Vec<Channel<'static>> {
+    vec![
+        Channel {
+            name: "R",
+            data: pixels.iter().map(|c| finite(c.r)).collect(),
+        },
+        Channel {
+            name: "G",
+            data: pixels.iter().map(|c| finite(c.g)).collect(),
+        },
+        Channel {
+            name: "B",
+            data: pixels.iter().map(|c| finite(c.b)).collect(),
+        },
+    ]
+}
+
+fn attribute(header: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    header.extend_from_slice(name.as_bytes());
+    header.push(0);
+    header.extend_from_slice(kind.as_bytes());
+    header.push(0);
+    header.extend_from_slice(&(value.len() as u32).to_le_bytes());
+    header.extend_from_slice(value);
+}
+
+fn box2i(width: usize, height: usize) -> Vec<u8> {
+    let mut v = Vec::with_capacity(16);
+    for x in [0, 0, width as i32 - 1, height as i32 - 1].iter() {
+        v.extend_from_slice(&x.to_le_bytes());
+    }
+    v
+}
+
+// Each channel holds width * height values, top row first. EXR wants channels
+// sorted by name, which we take care of here.
+pub fn write_exr(
+    out: &mut impl Write,
+    width: usize,
+    height: usize,
+    channels: &[Channel],
+) -> io::Result<()> {
+    let mut channels: Vec<&Channel> = channels.iter().collect();
+    channels.sort_by(|a, b| a.name.cmp(b.name));
+    for channel in channels.iter() {
+        assert_eq!(channel.data.len(), width * height);
+    }
+
+    let mut chlist = Vec::new();
+    for channel in channels.iter() {
+        chlist.extend_from_slice(channel.name.as_bytes());
+        chlist.push(0);
+        chlist.extend_from_slice(&PIXEL_TYPE_FLOAT.to_le_bytes());
+        chlist.extend_from_slice(&[0, 0, 0, 0]); // pLinear + reserved
+        chlist.extend_from_slice(&1i32.to_le_bytes()); // xSampling
+        chlist.extend_from_slice(&1i32.to_le_bytes()); // ySampling
+    }
+    chlist.push(0);
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&MAGIC.to_le_bytes());
+    header.extend_from_slice(&VERSION.to_le_bytes());
+    attribute(&mut header, "channels", "chlist", &chlist);
+    attribute(&mut header, "compression", "compression", &[0]);
+    attribute(&mut header, "dataWindow", "box2i", &box2i(width, height));
+    attribute(&mut header, "displayWindow", "box2i", &box2i(width, height));
+    attribute(&mut header, "lineOrder", "lineOrder", &[0]);
+    attribute(
+        &mut header,
+        "pixelAspectRatio",
+        "float",
+        &1f32.to_le_bytes(),
+    );
+    attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8]);
+    attribute(
+        &mut header,
+        "screenWindowWidth",
+        "float",
+        &1f32.to_le_bytes(),
+    );
+    header.push(0);
+
+    // One offset table entry per scanline, pointing at its chunk.
+    let line_bytes = channels.len() * width * 4;
+    let chunk_size = 8 + line_bytes;
+    let table_end = header.len() + 8 * height;
+    for y in 0..height {
+        let offset = (table_end + y * chunk_size) as u64;
+        header.extend_from_slice(&offset.to_le_bytes());
+    }
+    out.write_all(&header)?;
+
+    let mut chunk = Vec::with_capacity(chunk_size);
+    for y in 0..height {
+        chunk.clear();
+        chunk.extend_from_slice(&(y as i32).to_le_bytes());
+        chunk.extend_from_slice(&(line_bytes as u32).to_le_bytes());
+        for channel in channels.iter() {
+            for value in channel.data[y * width..(y + 1) * width].iter() {
+                chunk.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        out.write_all(&chunk)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::color;
+
+    #[test]
+    fn exr_layout() {
+        let pixels = vec![
+            color(1.0, 2.0, 3.0),
+            color(4.0, 5.0, 6.0),
+            color(7.0, 8.0, 9.0),
+            color(10.0, 11.0, 12.0),
+        ];
+        let mut out = Vec::new();
+        write_exr(&mut out, 2, 2, &rgb_channels(&pixels)).unwrap();
+        assert_eq!(&out[0..4], &MAGIC.to_le_bytes());
+
+        // The last scanline chunk ends the file: y, size, then B, G and R.
+        let chunk = &out[out.len() - (8 + 3 * 2 * 4)..];
+        let floats: Vec<f32> = chunk[8..]
+            .chunks(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+        assert_eq!(&chunk[0..4], &1i32.to_le_bytes());
+        assert_eq!(floats, vec![9.0, 12.0, 8.0, 11.0, 7.0, 10.0]);
+
+        // Both offset table entries point at a chunk with the right y.
+        let table = out.len() - 2 * (8 + 3 * 2 * 4) - 16;
+        for y in 0..2 {
+            let mut entry = [0u8; 8];
+            entry.copy_from_slice(&out[table + 8 * y..table + 8 * y + 8]);
+            let offset = u64::from_le_bytes(entry) as usize;
+            assert_eq!(&out[offset..offset + 4], &(y as i32).to_le_bytes());
+        }
+    }
+}
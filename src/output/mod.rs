@@ -0,0 +1,124 @@
+use crate::color::Color;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+pub mod exr;
+pub mod tonemap;
+
+use tonemap::ToneMap;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    Ppm,
+    Png,
+    Hdr,
+    Exr,
+}
+
+impl OutputFormat {
+    pub fn from_path(path: &Path) -> Option<OutputFormat> {
+        let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+        match ext.as_ref() {
+            "ppm" => Some(OutputFormat::Ppm),
+            "png" => Some(OutputFormat::Png),
+            "hdr" => Some(OutputFormat::Hdr),
+            "exr" => Some(OutputFormat::Exr),
+            _ => None,
+        }
+    }
+
+    // HDR formats keep the linear float values, LDR formats are tone mapped
+    // and sRGB encoded to 8 bits.
+    pub fn is_hdr(&self) -> bool {
+        matches!(self, OutputFormat::Hdr | OutputFormat::Exr)
+    }
+}
+
+// Write a top-to-bottom buffer of averaged, linear pixel colors. The format is
+// picked from the file extension; `tone_map` only applies to LDR formats.
+pub fn write_image(
+    path: &Path,
+    width: usize,
+    height: usize,
+    pixels: &[Color],
+    tone_map: &ToneMap,
+) -> io::Result<()> {
+    let format = match OutputFormat::from_path(path) {
+        Some(format) => format,
+        None => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{}: unknown image format, expected .ppm, .png, .hdr or .exr",
+                    path.display()
+                ),
+            ))
+        }
+    };
+    assert_eq!(pixels.len(), width * height);
+    let mut out = BufWriter::new(File::create(path)?);
+    match format {
+        OutputFormat::Ppm => write_ppm(&mut out, width, height, pixels, tone_map)?,
+        OutputFormat::Png => write_png(&mut out, width, height, pixels, tone_map)?,
+        OutputFormat::Hdr => write_hdr(&mut out, width, height, pixels)?,
+        OutputFormat::Exr => exr::write_exr(&mut out, width, height, &exr::rgb_channels(pixels))?,
+    }
+    out.flush()
+}
+
+pub fn write_ppm(
+    out: &mut impl Write,
+    width: usize,
+    height: usize,
+    pixels: &[Color],
+    tone_map: &ToneMap,
+) -> io::Result<()> {
+    writeln!(out, "P3\n{} {}\n255", width, height)?;
+    for [r, g, b] in tone_map.to_rgb8(pixels) {
+        writeln!(out, "{} {} {}", r, g, b)?;
+    }
+    Ok(())
+}
+
+pub fn write_png(
+    out: &mut impl Write,
+    width: usize,
+    height: usize,
+    pixels: &[Color],
+    tone_map: &ToneMap,
+) -> io::Result<()> {
+    let bytes: Vec<u8> = tone_map.to_rgb8(pixels).concat();
+    image::codecs::png::PngEncoder::new(out)
+        .encode(&bytes, width as u32, height as u32, image::ColorType::Rgb8)
+        .map_err(image_error)
+}
+
+// Radiance RGBE
+pub fn write_hdr(
+    out: &mut impl Write,
+    width: usize,
+    height: usize,
+    pixels: &[Color],
+) -> io::Result<()> {
+    let data: Vec<image::Rgb<f32>> = pixels
+        .iter()
+        .map(|c| image::Rgb([finite(c.r), finite(c.g), finite(c.b)]))
+        .collect();
+    image::codecs::hdr::HdrEncoder::new(out)
+        .encode(&data, width, height)
+        .map_err(image_error)
+}
+
+// NaNs and infinities can't be represented by RGBE and poison EXR viewers.
+pub fn finite(x: f64) -> f32 {
+    if x.is_finite() {
+        x as f32
+    } else {
+        0.0
+    }
+}
+
+fn image_error(e: image::ImageError) -> io::Error {
+    io::Error::other(e)
+}
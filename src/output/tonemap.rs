@@ -0,0 +1,194 @@
+use crate::color::{to_rgb8, Color};
+use std::fmt;
+use std::str::FromStr;
+
+// Tone mapping turns linear radiance into display values in [0, 1], which
+// LDR formats then encode with the sRGB transfer function. HDR formats skip
+// it and keep the radiance as rendered.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Operator {
+    // Anything brighter than white is cut off.
+    #[default]
+    Clamp,
+    // L / (1 + L) on luminance, which keeps the hue but never reaches white.
+    Reinhard,
+    // Reinhard with a luminance that maps to white.
+    ReinhardExtended,
+    // John Hable's filmic curve from Uncharted 2.
+    Hable,
+    // Krzysztof Narkowicz's fit of the ACES reference rendering transform.
+    Aces,
+}
+
+impl FromStr for Operator {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Operator, String> {
+        match s {
+            "clamp" => Ok(Operator::Clamp),
+            "reinhard" => Ok(Operator::Reinhard),
+            "reinhard-extended" => Ok(Operator::ReinhardExtended),
+            "hable" => Ok(Operator::Hable),
+            "aces" => Ok(Operator::Aces),
+            _ => Err(format!(
+                "unknown tone mapping operator {}, expected clamp, reinhard, reinhard-extended, hable or aces",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Operator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Operator::Clamp => "clamp",
+            Operator::Reinhard => "reinhard",
+            Operator::ReinhardExtended => "reinhard-extended",
+            Operator::Hable => "hable",
+            Operator::Aces => "aces",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// `exposure` is in stops, each one doubling the brightness. `white` is the
+// luminance, after exposure, that extended Reinhard maps to white; None
+// takes the brightest pixel's.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ToneMap {
+    pub exposure: f64,
+    pub operator: Operator,
+    pub white: Option<f64>,
+}
+
+impl ToneMap {
+    // Display values of linear pixels, before the sRGB transfer. Saturated
+    // colors can still leave a channel out of range, which is clamped.
+    pub fn apply(&self, pixels: &[Color]) -> Vec<Color> {
+        let scale = self.exposure.exp2();
+        let exposed = pixels.iter().map(|&c| finite(c) * scale);
+        let mapped: Vec<Color> = match self.operator {
+            Operator::Clamp => exposed.collect(),
+            Operator::Reinhard => exposed
+                .map(|c| map_luminance(c, |l| l / (1.0 + l)))
+                .collect(),
+            Operator::ReinhardExtended => {
+                let exposed: Vec<Color> = exposed.collect();
+                let white = self
+                    .white
+                    .unwrap_or_else(|| exposed.iter().map(|c| c.luminance()).fold(0.0, f64::max));
+                let w2 = (white * white).max(f64::MIN_POSITIVE);
+                exposed
+                    .into_iter()
+                    .map(|c| map_luminance(c, |l| l * (1.0 + l / w2) / (1.0 + l)))
+                    .collect()
+            }
+            Operator::Hable => {
+                // Exposure bias and linear white point from the talk.
+                let white = hable(11.2);
+                exposed
+                    .map(|c| map_channels(c, |x| hable(2.0 * x) / white))
+                    .collect()
+            }
+            Operator::Aces => exposed.map(|c| map_channels(c, aces)).collect(),
+        };
+        mapped
+            .into_iter()
+            .map(|c| map_channels(c, |x| x.clamp(0.0, 1.0)))
+            .collect()
+    }
+
+    pub fn to_rgb8(&self, pixels: &[Color]) -> Vec<[u8; 3]> {
+        self.apply(pixels).into_iter().map(to_rgb8).collect()
+    }
+}
+
+// NaNs and infinities would spread to the whole image through the white
+// point.
+fn finite(c: Color) -> Color {
+    let f = |x: f64| if x.is_finite() { x } else { 0.0 };
+    Color {
+        r: f(c.r),
+        g: f(c.g),
+        b: f(c.b),
+    }
+}
+
+fn map_luminance(c: Color, f: impl Fn(f64) -> f64) -> Color {
+    let l = c.luminance();
+    if l <= 0.0 {
+        c
+    } else {
+        c * (f(l) / l)
+    }
+}
+
+fn map_channels(c: Color, f: impl Fn(f64) -> f64) -> Color {
+    Color {
+        r: f(c.r),
+        g: f(c.g),
+        b: f(c.b),
+    }
+}
+
+fn hable(x: f64) -> f64 {
+    let (a, b, c, d, e, f) = (0.15, 0.50, 0.10, 0.20, 0.02, 0.30);
+    (x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f) - e / f
+}
+
+fn aces(x: f64) -> f64 {
+    let x = 0.6 * x;
+    x * (2.51 * x + 0.03) / (x * (2.43 * x + 0.59) + 0.14)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::color;
+
+    #[test]
+    fn operators_are_monotonic_and_stay_displayable() {
+        let pixels: Vec<Color> = (0..200)
+            .map(|i| {
+                let x = i as f64 * 0.1;
+                color(x, x, x)
+            })
+            .collect();
+        for &operator in [
+            Operator::Reinhard,
+            Operator::ReinhardExtended,
+            Operator::Hable,
+            Operator::Aces,
+        ]
+        .iter()
+        {
+            let mapped = ToneMap {
+                operator,
+                ..ToneMap::default()
+            }
+            .apply(&pixels);
+            for pair in mapped.windows(2) {
+                assert!(pair[1].r >= pair[0].r, "{}", operator);
+            }
+            assert!(
+                mapped.iter().all(|c| (0.0..=1.0).contains(&c.r)),
+                "{}",
+                operator
+            );
+            assert!(mapped[199].r > 0.9, "{}", operator);
+        }
+    }
+
+    #[test]
+    fn extended_reinhard_maps_the_white_point_to_white() {
+        let pixels = vec![color(0.25, 0.25, 0.25), color(4.0, 4.0, 4.0)];
+        let tone_map = ToneMap {
+            exposure: 1.0,
+            operator: Operator::ReinhardExtended,
+            white: None,
+        };
+        let mapped = tone_map.apply(&pixels);
+        assert!((mapped[1].r - 1.0).abs() < 1e-12);
+        assert!(mapped[0].r < 0.5);
+    }
+}
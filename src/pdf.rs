@@ -1,6 +1,11 @@
+use crate::color::Color;
 use crate::hittable::{Hittable, Hittables};
+use crate::material::microfacet::Ggx;
+use crate::material::principled::Lobes;
+use crate::material::rough_dielectric;
 use crate::onb::Onb;
-use crate::util::random_double;
+use crate::sampler::{Sampler, SamplerType};
+use crate::util::sample_cosine_direction;
 use crate::vec::Vec3;
 
 use enum_dispatch::enum_dispatch;
@@ -12,7 +17,7 @@ pub trait Pdf {
     fn value(&self, _direction: Vec3, _rng: &mut SmallRng) -> f64 {
         0.0
     }
-    fn generate(&self, _rng: &mut SmallRng) -> Vec3 {
+    fn generate(&self, _sampler: &mut SamplerType) -> Vec3 {
         Vec3::zero()
     }
 }
@@ -20,8 +25,11 @@ pub trait Pdf {
 #[enum_dispatch(Pdf)]
 pub enum PdfType {
     CosinePdf,
+    GgxPdf,
     HittablePdf,
     MixturePdf,
+    PrincipledPdf,
+    RoughDielectricPdf,
 }
 
 pub struct CosinePdf {
@@ -44,8 +52,114 @@ impl Pdf for CosinePdf {
         }
     }
 
-    fn generate(&self, rng: &mut SmallRng) -> Vec3 {
-        self.uvw.local(&random_cosine_direction(rng))
+    fn generate(&self, sampler: &mut SamplerType) -> Vec3 {
+        self.uvw.local(&sample_cosine_direction(sampler.get_2d()))
+    }
+}
+
+// Reflection off GGX microfacets visible from `wo`, the direction back along
+// the incoming ray in the `uvw` frame.
+pub struct GgxPdf {
+    pub uvw: Onb,
+    pub wo: Vec3,
+    pub ggx: Ggx,
+}
+
+impl GgxPdf {
+    pub fn new(normal: Vec3, incoming: Vec3, ggx: Ggx) -> PdfType {
+        let uvw = Onb::new(&normal);
+        let wo = uvw.to_local(&-incoming.unit_vector());
+        PdfType::from(GgxPdf { uvw, wo, ggx })
+    }
+}
+
+impl Pdf for GgxPdf {
+    fn value(&self, direction: Vec3, _rng: &mut SmallRng) -> f64 {
+        let wi = self.uvw.to_local(&direction.unit_vector());
+        let half = self.wo + wi;
+        if half.length_squared() == 0.0 {
+            return 0.0;
+        }
+        let h = half.unit_vector();
+        let cos = self.wo.dot(h);
+        if cos <= 0.0 {
+            return 0.0;
+        }
+        // The Jacobian of reflecting about h.
+        self.ggx.visible_pdf(self.wo, h) / (4.0 * cos)
+    }
+
+    fn generate(&self, sampler: &mut SamplerType) -> Vec3 {
+        let h = self.ggx.sample_visible(self.wo, sampler.get_2d());
+        self.uvw.local(&Vec3::reflect(-self.wo, h))
+    }
+}
+
+// Reflection off or refraction through GGX microfacets of a boundary into
+// relative index of refraction `eta`, see `rough_dielectric`.
+pub struct RoughDielectricPdf {
+    pub uvw: Onb,
+    pub wo: Vec3,
+    pub ggx: Ggx,
+    pub eta: f64,
+}
+
+impl RoughDielectricPdf {
+    pub fn new(normal: Vec3, incoming: Vec3, ggx: Ggx, eta: f64) -> PdfType {
+        let uvw = Onb::new(&normal);
+        let wo = uvw.to_local(&-incoming.unit_vector());
+        PdfType::from(RoughDielectricPdf { uvw, wo, ggx, eta })
+    }
+}
+
+impl Pdf for RoughDielectricPdf {
+    fn value(&self, direction: Vec3, _rng: &mut SmallRng) -> f64 {
+        let wi = self.uvw.to_local(&direction.unit_vector());
+        rough_dielectric::pdf(&self.ggx, self.eta, self.wo, wi)
+    }
+
+    fn generate(&self, sampler: &mut SamplerType) -> Vec3 {
+        let u = sampler.get_2d();
+        let choice = sampler.get_1d();
+        let wi = rough_dielectric::sample(&self.ggx, self.eta, self.wo, u, choice);
+        self.uvw.local(&wi)
+    }
+}
+
+// A mixture of the lobes of the principled BSDF at a point, see
+// `principled`.
+pub struct PrincipledPdf {
+    pub uvw: Onb,
+    pub wo: Vec3,
+    pub lobes: Lobes,
+}
+
+impl PrincipledPdf {
+    pub fn new(normal: Vec3, incoming: Vec3, lobes: Lobes) -> PdfType {
+        let uvw = Onb::new(&normal);
+        let wo = uvw.to_local(&-incoming.unit_vector());
+        PdfType::from(PrincipledPdf { uvw, wo, lobes })
+    }
+
+    // The BSDF times the cosine for light arriving from `direction`.
+    pub fn eval(&self, direction: Vec3) -> Color {
+        let wi = self.uvw.to_local(&direction.unit_vector());
+        self.lobes.eval(self.wo, wi)
+    }
+}
+
+impl Pdf for PrincipledPdf {
+    fn value(&self, direction: Vec3, _rng: &mut SmallRng) -> f64 {
+        let wi = self.uvw.to_local(&direction.unit_vector());
+        self.lobes.pdf(self.wo, wi)
+    }
+
+    fn generate(&self, sampler: &mut SamplerType) -> Vec3 {
+        let choice = sampler.get_1d();
+        let u = sampler.get_2d();
+        let fresnel_choice = sampler.get_1d();
+        let wi = self.lobes.sample(self.wo, choice, u, fresnel_choice);
+        self.uvw.local(&wi)
     }
 }
 
@@ -68,8 +182,8 @@ impl Pdf for HittablePdf {
         self.object.pdf_value(self.origin, direction, rng)
     }
 
-    fn generate(&self, rng: &mut SmallRng) -> Vec3 {
-        self.object.random(self.origin, rng)
+    fn generate(&self, sampler: &mut SamplerType) -> Vec3 {
+        self.object.random(self.origin, sampler)
     }
 }
 
@@ -92,21 +206,23 @@ impl Pdf for MixturePdf {
         0.5 * self.pdf1.value(direction, rng) + 0.5 * self.pdf2.value(direction, rng)
     }
 
-    fn generate(&self, rng: &mut SmallRng) -> Vec3 {
-        if random_double(rng) < 0.5 {
-            self.pdf1.generate(rng)
+    fn generate(&self, sampler: &mut SamplerType) -> Vec3 {
+        if sampler.get_1d() < 0.5 {
+            self.pdf1.generate(sampler)
         } else {
-            self.pdf2.generate(rng)
+            self.pdf2.generate(sampler)
         }
     }
 }
 
-fn random_cosine_direction(rng: &mut SmallRng) -> Vec3 {
-    let r1 = random_double(rng);
-    let r2 = random_double(rng);
-    let phi = 2.0 * std::f64::consts::PI * r1;
-    let x = f64::cos(phi) * f64::sqrt(r2);
-    let y = f64::sin(phi) * f64::sqrt(r2);
-    let z = f64::sqrt(1.0 - r2);
-    Vec3::new(x, y, z)
+// Power heuristic (beta = 2) weight for a sample drawn from the strategy with
+// density `f` when the other strategy has density `g` in the same direction.
+pub fn power_heuristic(f: f64, g: f64) -> f64 {
+    let f2 = f * f;
+    let g2 = g * g;
+    if f2 + g2 == 0.0 {
+        0.0
+    } else {
+        f2 / (f2 + g2)
+    }
 }
@@ -5,6 +5,9 @@ pub struct Ray {
     pub origin: Vec3,
     pub direction: Vec3,
     pub time: f64,
+    // The single wavelength a path carries in nanometres, once it has hit a
+    // dispersive material. Until then it carries all of them, as RGB.
+    pub wavelength: Option<f64>,
 }
 
 impl Ray {
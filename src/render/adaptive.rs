@@ -0,0 +1,139 @@
+use crate::color::{color, srgb_to_linear, Color};
+use serde::{Deserialize, Serialize};
+
+// Adaptive sampling: once a pixel has `min_samples`, it is checked again every
+// `min_samples` samples and stops when the standard error of its mean
+// luminance is below `threshold` times the mean. `samples_per_pixel` is then
+// only the upper limit. Checking in batches rather than after every sample
+// keeps low discrepancy samplers on whole, well distributed sets of points.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Adaptive {
+    pub threshold: f64,
+    pub min_samples: u64,
+}
+
+impl Default for Adaptive {
+    fn default() -> Adaptive {
+        Adaptive {
+            threshold: 0.01,
+            min_samples: 16,
+        }
+    }
+}
+
+impl Adaptive {
+    pub fn converged(&self, stats: &PixelStats) -> bool {
+        let min_samples = self.min_samples.max(2);
+        stats.n >= min_samples
+            && stats.n.is_multiple_of(min_samples)
+            && stats.relative_error() < self.threshold
+    }
+}
+
+// Running mean and variance of a pixel's sample luminance, using Welford's
+// update so long runs don't lose precision.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PixelStats {
+    pub n: u64,
+    pub mean: f64,
+    pub m2: f64,
+}
+
+impl PixelStats {
+    pub fn add(&mut self, x: f64) {
+        self.n += 1;
+        let delta = x - self.mean;
+        self.mean += delta / self.n as f64;
+        self.m2 += delta * (x - self.mean);
+    }
+
+    // Combine with the statistics of another set of samples of the same
+    // pixel (Chan et al.'s parallel update).
+    pub fn merge(&mut self, other: &PixelStats) {
+        let n = self.n + other.n;
+        if n == 0 {
+            return;
+        }
+        let delta = other.mean - self.mean;
+        let (na, nb) = (self.n as f64, other.n as f64);
+        self.mean += delta * nb / n as f64;
+        self.m2 += other.m2 + delta * delta * na * nb / n as f64;
+        self.n = n;
+    }
+
+    // Unbiased sample variance.
+    pub fn variance(&self) -> f64 {
+        if self.n < 2 {
+            0.0
+        } else {
+            self.m2 / (self.n - 1) as f64
+        }
+    }
+
+    // Standard error of the mean relative to the mean. Pixels that are black
+    // in every sample have converged, while a single bright sample among
+    // black ones keeps the error large.
+    pub fn relative_error(&self) -> f64 {
+        if self.n < 2 {
+            return f64::INFINITY;
+        }
+        let std_error = (self.variance() / self.n as f64).sqrt();
+        if std_error == 0.0 {
+            0.0
+        } else if self.mean <= 0.0 {
+            f64::INFINITY
+        } else {
+            std_error / self.mean
+        }
+    }
+}
+
+// Cold to hot ramp for `t` in [0, 1]: blue, cyan, green, yellow, red. The
+// stops are display values, decoded so they survive the sRGB encoding of LDR
+// output.
+pub fn heat(t: f64) -> Color {
+    const STOPS: [(f64, f64, f64); 5] = [
+        (0.0, 0.0, 1.0),
+        (0.0, 1.0, 1.0),
+        (0.0, 1.0, 0.0),
+        (1.0, 1.0, 0.0),
+        (1.0, 0.0, 0.0),
+    ];
+    let x = t.clamp(0.0, 1.0) * (STOPS.len() - 1) as f64;
+    let i = (x as usize).min(STOPS.len() - 2);
+    let f = x - i as f64;
+    let (a, b) = (STOPS[i], STOPS[i + 1]);
+    let lerp = |a: f64, b: f64| srgb_to_linear(a + f * (b - a));
+    color(lerp(a.0, b.0), lerp(a.1, b.1), lerp(a.2, b.2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn welford_matches_two_pass_variance() {
+        let xs = [0.5, 1.5, 2.0, 0.25, 3.0, 1.0];
+        let mut stats = PixelStats::default();
+        for x in xs.iter() {
+            stats.add(*x);
+        }
+        let mean = xs.iter().sum::<f64>() / xs.len() as f64;
+        let variance =
+            xs.iter().map(|x| (x - mean) * (x - mean)).sum::<f64>() / (xs.len() - 1) as f64;
+        assert!((stats.mean - mean).abs() < 1e-12);
+        assert!((stats.variance() - variance).abs() < 1e-12);
+    }
+
+    #[test]
+    fn flat_pixels_converge_at_the_first_check() {
+        let adaptive = Adaptive::default();
+        let mut stats = PixelStats::default();
+        for _ in 0..adaptive.min_samples - 1 {
+            stats.add(0.0);
+            assert!(!adaptive.converged(&stats));
+        }
+        stats.add(0.0);
+        assert!(adaptive.converged(&stats));
+    }
+}
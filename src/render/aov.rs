@@ -0,0 +1,399 @@
+use crate::color::{color, srgb_to_linear, Color};
+use crate::hittable::Hittable;
+use crate::material::Material;
+use crate::output::exr::{self, Channel};
+use crate::output::tonemap::ToneMap;
+use crate::output::{finite, write_image, OutputFormat};
+use crate::render::{camera_sample, RenderSettings};
+use crate::sampler::{mix, Sampler};
+use crate::scenes::Scene;
+use crate::vec::Vec3;
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+
+// Arbitrary output variables: what the camera rays hit first, for
+// compositing and to guide the denoiser. Each pixel averages its first few
+// samples, taken where the render takes them, so edges line up with the
+// image. Albedo and normal average over all of them with misses as zero,
+// depth, position and UV only over the ones that hit something. Object and
+// material IDs come from the first sample and are numbered from 1 in the
+// order they first appear in the image, top to bottom; 0 is the background.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aov {
+    Albedo,
+    Normal,
+    Depth,
+    Position,
+    Uv,
+    Object,
+    Material,
+}
+
+impl Aov {
+    // Channel names in an EXR, layer first.
+    pub fn channels(&self) -> &'static [&'static str] {
+        match self {
+            Aov::Albedo => &["albedo.R", "albedo.G", "albedo.B"],
+            Aov::Normal => &["normal.X", "normal.Y", "normal.Z"],
+            Aov::Depth => &["depth.Z"],
+            Aov::Position => &["position.X", "position.Y", "position.Z"],
+            Aov::Uv => &["uv.U", "uv.V"],
+            Aov::Object => &["object.id"],
+            Aov::Material => &["material.id"],
+        }
+    }
+}
+
+impl FromStr for Aov {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Aov, String> {
+        match s {
+            "albedo" => Ok(Aov::Albedo),
+            "normal" => Ok(Aov::Normal),
+            "depth" => Ok(Aov::Depth),
+            "position" => Ok(Aov::Position),
+            "uv" => Ok(Aov::Uv),
+            "object" => Ok(Aov::Object),
+            "material" => Ok(Aov::Material),
+            _ => Err(format!(
+                "unknown AOV {}, expected albedo, normal, depth, position, uv, object or material",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Aov {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Aov::Albedo => "albedo",
+            Aov::Normal => "normal",
+            Aov::Depth => "depth",
+            Aov::Position => "position",
+            Aov::Uv => "uv",
+            Aov::Object => "object",
+            Aov::Material => "material",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// Rows are stored top to bottom like the framebuffer's.
+#[derive(Debug, Clone)]
+pub struct Aovs {
+    pub width: usize,
+    pub height: usize,
+    pub albedo: Vec<Color>,
+    // Shading normals, facing the camera. Averaging leaves them shorter
+    // than unit length along edges.
+    pub normal: Vec<Vec3>,
+    // Distance along the camera ray, infinite where nothing was hit.
+    pub depth: Vec<f64>,
+    pub position: Vec<Vec3>,
+    pub uv: Vec<(f64, f64)>,
+    pub object: Vec<u32>,
+    pub material: Vec<u32>,
+}
+
+impl Aovs {
+    // The values of `aov` in the first channels of a color, for HDR output.
+    pub fn values(&self, aov: Aov) -> Vec<Color> {
+        match aov {
+            Aov::Albedo => self.albedo.clone(),
+            Aov::Normal => self.normal.iter().map(|&n| vec_color(n)).collect(),
+            Aov::Depth => self.depth.iter().map(|&d| color(d, d, d)).collect(),
+            Aov::Position => self.position.iter().map(|&p| vec_color(p)).collect(),
+            Aov::Uv => self.uv.iter().map(|&(u, v)| color(u, v, 0.0)).collect(),
+            Aov::Object => id_values(&self.object),
+            Aov::Material => id_values(&self.material),
+        }
+    }
+
+    // `aovs` as EXR layers.
+    pub fn channels(&self, aovs: &[Aov]) -> Vec<Channel<'static>> {
+        let mut channels = Vec::new();
+        for &aov in aovs {
+            let values = self.values(aov);
+            for (i, &name) in aov.channels().iter().enumerate() {
+                let component = |c: &Color| [c.r, c.g, c.b][i];
+                channels.push(Channel {
+                    name,
+                    data: values.iter().map(|c| finite(component(c))).collect(),
+                });
+            }
+        }
+        channels
+    }
+
+    // `aov` as an image to look at, for LDR output. Normals and UVs are
+    // mapped to colors the usual way, positions to the box around them and
+    // depth to gray from white up close to black at the background. IDs get
+    // random colors.
+    pub fn image(&self, aov: Aov) -> Vec<Color> {
+        let display =
+            |r: f64, g: f64, b: f64| color(srgb_to_linear(r), srgb_to_linear(g), srgb_to_linear(b));
+        match aov {
+            Aov::Albedo => self.albedo.clone(),
+            Aov::Normal => self
+                .normal
+                .iter()
+                .map(|n| display(0.5 * n.x + 0.5, 0.5 * n.y + 0.5, 0.5 * n.z + 0.5))
+                .collect(),
+            Aov::Depth => {
+                let hits = self.depth.iter().filter(|d| d.is_finite());
+                let far = 1.05 * hits.fold(0.0, |far: f64, &d| far.max(d));
+                self.depth
+                    .iter()
+                    .map(|&d| {
+                        let t = if d.is_finite() { 1.0 - d / far } else { 0.0 };
+                        display(t, t, t)
+                    })
+                    .collect()
+            }
+            Aov::Position => {
+                let hits: Vec<Vec3> = self
+                    .position
+                    .iter()
+                    .zip(self.depth.iter())
+                    .filter(|(_, d)| d.is_finite())
+                    .map(|(&p, _)| p)
+                    .collect();
+                let min = hits
+                    .iter()
+                    .fold(Vec3::new(f64::MAX, f64::MAX, f64::MAX), |a, p| {
+                        Vec3::new(a.x.min(p.x), a.y.min(p.y), a.z.min(p.z))
+                    });
+                let max = hits
+                    .iter()
+                    .fold(Vec3::new(f64::MIN, f64::MIN, f64::MIN), |a, p| {
+                        Vec3::new(a.x.max(p.x), a.y.max(p.y), a.z.max(p.z))
+                    });
+                let scale = |x: f64, min: f64, max: f64| {
+                    if max > min {
+                        (x - min) / (max - min)
+                    } else {
+                        0.0
+                    }
+                };
+                self.position
+                    .iter()
+                    .zip(self.depth.iter())
+                    .map(|(p, d)| {
+                        if d.is_finite() {
+                            display(
+                                scale(p.x, min.x, max.x),
+                                scale(p.y, min.y, max.y),
+                                scale(p.z, min.z, max.z),
+                            )
+                        } else {
+                            Color::default()
+                        }
+                    })
+                    .collect()
+            }
+            Aov::Uv => self.uv.iter().map(|&(u, v)| display(u, v, 0.0)).collect(),
+            Aov::Object => self.object.iter().map(|&id| id_color(id)).collect(),
+            Aov::Material => self.material.iter().map(|&id| id_color(id)).collect(),
+        }
+    }
+
+    // Write the image in `pixels` to `path` along with `aovs`. An EXR gets
+    // them as extra layers, other formats a file each, named after the AOV:
+    // beauty.png gets beauty.normal.png and so on.
+    pub fn write(
+        &self,
+        path: &Path,
+        pixels: &[Color],
+        aovs: &[Aov],
+        tone_map: &ToneMap,
+    ) -> io::Result<()> {
+        let (width, height) = (self.width, self.height);
+        if OutputFormat::from_path(path) == Some(OutputFormat::Exr) {
+            let mut channels = exr::rgb_channels(pixels);
+            channels.extend(self.channels(aovs));
+            let mut out = BufWriter::new(File::create(path)?);
+            exr::write_exr(&mut out, width, height, &channels)?;
+            return out.flush();
+        }
+        write_image(path, width, height, pixels, tone_map)?;
+        let hdr = OutputFormat::from_path(path).is_some_and(|f| f.is_hdr());
+        for &aov in aovs {
+            let pixels = if hdr {
+                self.values(aov)
+            } else {
+                self.image(aov)
+            };
+            write_image(
+                &aov_path(path, aov),
+                width,
+                height,
+                &pixels,
+                &ToneMap::default(),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn aov_path(path: &Path, aov: Aov) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let ext = path.extension().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!("{}.{}.{}", stem, aov, ext))
+}
+
+fn vec_color(v: Vec3) -> Color {
+    color(v.x, v.y, v.z)
+}
+
+fn id_values(ids: &[u32]) -> Vec<Color> {
+    ids.iter()
+        .map(|&id| {
+            let id = id as f64;
+            color(id, id, id)
+        })
+        .collect()
+}
+
+fn id_color(id: u32) -> Color {
+    if id == 0 {
+        return Color::default();
+    }
+    let bits = mix(id as u64, 0);
+    let channel = |shift: u32| srgb_to_linear(0.2 + 0.8 * ((bits >> shift) & 0xff) as f64 / 255.0);
+    color(channel(0), channel(8), channel(16))
+}
+
+#[derive(Default)]
+struct FirstHits {
+    albedo: Color,
+    normal: Vec3,
+    depth: f64,
+    position: Vec3,
+    uv: (f64, f64),
+    hits: u64,
+    // Identities of the first sample's object and material, 0 for a miss.
+    object: usize,
+    material: usize,
+}
+
+pub(super) fn render(scene: &Scene, settings: &RenderSettings, seed: u64, samples: u64) -> Aovs {
+    let (width, height) = (settings.width, settings.height);
+    let samples = samples.clamp(1, settings.samples_per_pixel.max(1));
+    let pixels: Vec<FirstHits> = (0..height)
+        .into_par_iter()
+        .flat_map_iter(|y| {
+            let mut sampler = settings.sampler.sampler(settings.samples_per_pixel, seed);
+            (0..width)
+                .map(|x| {
+                    let mut pixel = FirstHits::default();
+                    for i in 0..samples {
+                        let (ray, _, _) =
+                            camera_sample(&scene.camera, settings, &mut sampler, x, y, i);
+                        let hit = match scene.hittables.hit(&ray, 0.0001, f64::MAX, sampler.rng()) {
+                            Some(hit) => hit,
+                            None => continue,
+                        };
+                        pixel.albedo += hit.mat.albedo(&hit);
+                        pixel.normal = pixel.normal + hit.normal;
+                        pixel.depth += hit.t * ray.direction.length();
+                        pixel.position = pixel.position + hit.point;
+                        pixel.uv = (pixel.uv.0 + hit.u, pixel.uv.1 + hit.v);
+                        pixel.hits += 1;
+                        if i == 0 {
+                            pixel.object = hit.object;
+                            pixel.material = Arc::as_ptr(&hit.mat) as usize;
+                        }
+                    }
+                    pixel
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect();
+
+    let mut aovs = Aovs {
+        width,
+        height,
+        albedo: Vec::with_capacity(pixels.len()),
+        normal: Vec::with_capacity(pixels.len()),
+        depth: Vec::with_capacity(pixels.len()),
+        position: Vec::with_capacity(pixels.len()),
+        uv: Vec::with_capacity(pixels.len()),
+        object: Vec::with_capacity(pixels.len()),
+        material: Vec::with_capacity(pixels.len()),
+    };
+    let mut objects = HashMap::new();
+    let mut materials = HashMap::new();
+    for pixel in pixels {
+        let all = 1.0 / samples as f64;
+        aovs.albedo.push(pixel.albedo * all);
+        aovs.normal.push(pixel.normal * all);
+        if pixel.hits == 0 {
+            aovs.depth.push(f64::INFINITY);
+            aovs.position.push(Vec3::zero());
+            aovs.uv.push((0.0, 0.0));
+        } else {
+            let hits = 1.0 / pixel.hits as f64;
+            aovs.depth.push(pixel.depth * hits);
+            aovs.position.push(pixel.position * hits);
+            aovs.uv.push((pixel.uv.0 * hits, pixel.uv.1 * hits));
+        }
+        aovs.object.push(number(&mut objects, pixel.object));
+        aovs.material.push(number(&mut materials, pixel.material));
+    }
+    aovs
+}
+
+// Dense IDs for identities in the order they come, 0 stays 0.
+fn number(ids: &mut HashMap<usize, u32>, identity: usize) -> u32 {
+    if identity == 0 {
+        return 0;
+    }
+    let next = ids.len() as u32 + 1;
+    *ids.entry(identity).or_insert(next)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::Renderer;
+    use crate::scenes::cornell_box::cornell_box;
+
+    #[test]
+    fn first_hits_of_the_cornell_box() {
+        let settings = RenderSettings {
+            width: 20,
+            height: 20,
+            samples_per_pixel: 4,
+            seed: Some(1),
+            ..RenderSettings::default()
+        };
+        let aovs = Renderer::new(settings).render_aovs(&cornell_box(0.0, 1.0, 1.0), 1, 4);
+        assert_eq!(aovs.albedo.len(), 400);
+        // The box is open towards the camera, which sees past its edges but
+        // not further in.
+        for y in 2..18 {
+            for x in 2..18 {
+                let i = y * 20 + x;
+                assert!(aovs.depth[i].is_finite() && aovs.depth[i] > 0.0);
+                assert!(aovs.object[i] > 0 && aovs.material[i] > 0);
+            }
+        }
+        assert_eq!(aovs.object[0], 0);
+        // Green wall on the left, red on the right.
+        let left = aovs.albedo[10 * 20 + 2];
+        let right = aovs.albedo[10 * 20 + 17];
+        assert!(left.g > left.r && right.r > right.g);
+        assert!(aovs.material.iter().copied().max().unwrap() >= 4);
+        assert!(aovs
+            .normal
+            .iter()
+            .all(|n| (n.length() - 1.0).abs() < 1e-9 || n.length() < 1.0));
+    }
+}
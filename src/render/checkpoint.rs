@@ -0,0 +1,184 @@
+use crate::color::{color, Color};
+use crate::render::{adaptive::PixelStats, Accumulator, RenderSettings};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+// A checkpoint is a magic number and version, a length prefixed JSON header
+// with the settings, then every pixel's weighted sum and filter weight as
+// little endian f64 r, g, b, weight, followed by every pixel's statistics as
+// u64 n, f64 mean, f64 m2, both in framebuffer order.
+//
+// Version 1 stored each pixel's sum and statistics together, without filter
+// weights; those checkpoints can't be resumed.
+
+const MAGIC: &[u8; 8] = b"RTCKPT\0\0";
+const VERSION: u32 = 2;
+
+#[derive(Debug, Clone)]
+pub struct Checkpoint {
+    // Whatever the caller needs to build the same scene again, the renderer
+    // doesn't look at it.
+    pub scene: String,
+    pub settings: RenderSettings,
+    pub accumulator: Accumulator,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Header {
+    scene: String,
+    settings: RenderSettings,
+    width: usize,
+    height: usize,
+    seed: u64,
+}
+
+impl Checkpoint {
+    // Written to a temporary file first and renamed over `path`, so an
+    // interrupted write never destroys the previous checkpoint.
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+        {
+            let mut out = BufWriter::new(File::create(&tmp)?);
+            self.write_to(&mut out)?;
+            out.flush()?;
+        }
+        fs::rename(&tmp, path)
+    }
+
+    pub fn read(path: &Path) -> io::Result<Checkpoint> {
+        let mut input = BufReader::new(File::open(path)?);
+        Checkpoint::read_from(&mut input)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))
+    }
+
+    pub fn write_to(&self, out: &mut impl Write) -> io::Result<()> {
+        let accumulator = &self.accumulator;
+        let header = Header {
+            scene: self.scene.clone(),
+            settings: self.settings.clone(),
+            width: accumulator.width,
+            height: accumulator.height,
+            seed: accumulator.seed,
+        };
+        let header = serde_json::to_vec(&header)?;
+        out.write_all(MAGIC)?;
+        out.write_all(&VERSION.to_le_bytes())?;
+        out.write_all(&(header.len() as u32).to_le_bytes())?;
+        out.write_all(&header)?;
+        write_sums(out, &accumulator.sums, &accumulator.weights)?;
+        write_stats(out, &accumulator.stats)
+    }
+
+    pub fn read_from(input: &mut impl Read) -> io::Result<Checkpoint> {
+        let mut magic = [0u8; 8];
+        input.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("not a render checkpoint".to_string()));
+        }
+        let version = read_u32(input)?;
+        if version == 1 {
+            return Err(invalid(
+                "checkpoint version 1 is from before reconstruction filters, render again"
+                    .to_string(),
+            ));
+        }
+        if version != VERSION {
+            return Err(invalid(format!(
+                "checkpoint version {}, expected {}",
+                version, VERSION
+            )));
+        }
+        let mut header = vec![0u8; read_u32(input)? as usize];
+        input.read_exact(&mut header)?;
+        let header: Header = serde_json::from_slice(&header)?;
+        let mut accumulator = Accumulator::new(header.width, header.height, header.seed);
+        read_sums(input, &mut accumulator.sums, &mut accumulator.weights)?;
+        read_stats(input, &mut accumulator.stats)?;
+        Ok(Checkpoint {
+            scene: header.scene,
+            settings: header.settings,
+            accumulator,
+        })
+    }
+}
+
+// Pixel sums, weights and statistics in the checkpoint's encoding, which
+// keeps every bit of them. Also used to send tiles between render processes.
+pub fn write_sums(out: &mut impl Write, sums: &[Color], weights: &[f64]) -> io::Result<()> {
+    for (sum, weight) in sums.iter().zip(weights.iter()) {
+        for v in [sum.r, sum.g, sum.b, *weight].iter() {
+            out.write_all(&v.to_le_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+pub fn read_sums(input: &mut impl Read, sums: &mut [Color], weights: &mut [f64]) -> io::Result<()> {
+    for (sum, weight) in sums.iter_mut().zip(weights.iter_mut()) {
+        *sum = color(read_f64(input)?, read_f64(input)?, read_f64(input)?);
+        *weight = read_f64(input)?;
+    }
+    Ok(())
+}
+
+pub fn write_stats(out: &mut impl Write, stats: &[PixelStats]) -> io::Result<()> {
+    for stats in stats {
+        out.write_all(&stats.n.to_le_bytes())?;
+        out.write_all(&stats.mean.to_le_bytes())?;
+        out.write_all(&stats.m2.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+pub fn read_stats(input: &mut impl Read, stats: &mut [PixelStats]) -> io::Result<()> {
+    for stats in stats.iter_mut() {
+        *stats = read_pixel_stats(input)?;
+    }
+    Ok(())
+}
+
+fn read_pixel_stats(input: &mut impl Read) -> io::Result<PixelStats> {
+    Ok(PixelStats {
+        n: read_u64(input)?,
+        mean: read_f64(input)?,
+        m2: read_f64(input)?,
+    })
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn read_u32(input: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0u8; 4];
+    input.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64(input: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0u8; 8];
+    input.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn read_f64(input: &mut impl Read) -> io::Result<f64> {
+    Ok(f64::from_bits(read_u64(input)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn old_versions_are_rejected() {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&1u32.to_le_bytes());
+        let err = Checkpoint::read_from(&mut &bytes[..]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("version 1"), "{}", err);
+    }
+}
@@ -0,0 +1,229 @@
+use crate::color::{color, Color};
+use crate::render::aov::Aovs;
+use rayon::prelude::*;
+
+// A cross bilateral filter guided by the AOVs. Each pixel becomes a weighted
+// average of its neighbours, with weights that fall off with distance and
+// with how different their albedo, normal and depth are, so edges between
+// objects stay sharp while noise within a surface is smoothed out. Texture
+// detail is kept by filtering the light arriving at the surface, the color
+// divided by its albedo, and multiplying the albedo back in afterwards.
+//
+// Given the variance of each pixel's mean luminance, neighbours are also
+// weighted by how far apart their colors are relative to that noise, which
+// keeps shadows and highlights within a surface from being blurred away.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Denoiser {
+    // Half the width of the window, in pixels.
+    pub radius: usize,
+    // Standard deviations of the weights.
+    pub sigma_space: f64,
+    pub sigma_albedo: f64,
+    pub sigma_normal: f64,
+    // Relative to the pixel's own depth.
+    pub sigma_depth: f64,
+    // In standard errors of the difference between two pixels' luminance.
+    pub sigma_color: f64,
+}
+
+impl Default for Denoiser {
+    fn default() -> Denoiser {
+        Denoiser {
+            radius: 6,
+            sigma_space: 3.0,
+            sigma_albedo: 0.1,
+            sigma_normal: 0.2,
+            sigma_depth: 0.05,
+            sigma_color: 2.0,
+        }
+    }
+}
+
+// Below this albedo the color is filtered as it is, lights and the
+// background have none to divide by.
+const MIN_ALBEDO: f64 = 0.01;
+
+impl Denoiser {
+    // `pixels` and `variance` are top to bottom like the AOVs.
+    pub fn denoise(&self, pixels: &[Color], aovs: &Aovs, variance: Option<&[f64]>) -> Vec<Color> {
+        let (width, height) = (aovs.width, aovs.height);
+        assert_eq!(pixels.len(), width * height);
+        let albedo: Vec<Color> = aovs.albedo.iter().map(|&a| demodulation(a)).collect();
+        let irradiance: Vec<Color> = pixels
+            .iter()
+            .zip(albedo.iter())
+            .map(|(&c, &a)| color(c.r / a.r, c.g / a.g, c.b / a.b))
+            .collect();
+        // Pixels whose color or variance isn't finite would spread to their
+        // whole window, so they're left out and come out black themselves.
+        let finite: Vec<bool> = pixels
+            .iter()
+            .enumerate()
+            .map(|(p, c)| {
+                (c.r + c.g + c.b).is_finite() && variance.is_none_or(|v| v[p].is_finite())
+            })
+            .collect();
+        let r = self.radius as i64;
+
+        (0..height)
+            .into_par_iter()
+            .flat_map_iter(|y| {
+                let irradiance = &irradiance;
+                let albedo = &albedo;
+                let finite = &finite;
+                (0..width).map(move |x| {
+                    let p = y * width + x;
+                    if !finite[p] {
+                        return Color::default();
+                    }
+                    let mut sum = Color::default();
+                    let mut total = 0.0;
+                    for dy in -r..=r {
+                        for dx in -r..=r {
+                            let (qx, qy) = (x as i64 + dx, y as i64 + dy);
+                            if qx < 0 || qy < 0 || qx >= width as i64 || qy >= height as i64 {
+                                continue;
+                            }
+                            let q = qy as usize * width + qx as usize;
+                            if !finite[q] {
+                                continue;
+                            }
+                            let w = self.spatial(dx, dy)
+                                * self.guide(aovs, p, q)
+                                * self.range(pixels, variance, p, q);
+                            sum += irradiance[q] * w;
+                            total += w;
+                        }
+                    }
+                    // The pixel's own weight is never 0, so neither is the
+                    // total.
+                    let a = albedo[p];
+                    let e = sum * (1.0 / total);
+                    color(e.r * a.r, e.g * a.g, e.b * a.b)
+                })
+            })
+            .collect()
+    }
+
+    fn spatial(&self, dx: i64, dy: i64) -> f64 {
+        gaussian((dx * dx + dy * dy) as f64, self.sigma_space)
+    }
+
+    fn guide(&self, aovs: &Aovs, p: usize, q: usize) -> f64 {
+        let (zp, zq) = (aovs.depth[p], aovs.depth[q]);
+        if zp.is_finite() != zq.is_finite() {
+            return 0.0;
+        }
+        let depth = if zp.is_finite() {
+            gaussian(sq((zp - zq) / zp.max(f64::MIN_POSITIVE)), self.sigma_depth)
+        } else {
+            1.0
+        };
+        let (ap, aq) = (aovs.albedo[p], aovs.albedo[q]);
+        let albedo = sq(ap.r - aq.r) + sq(ap.g - aq.g) + sq(ap.b - aq.b);
+        let normal = (aovs.normal[p] - aovs.normal[q]).length_squared();
+        depth * gaussian(albedo, self.sigma_albedo) * gaussian(normal, self.sigma_normal)
+    }
+
+    fn range(&self, pixels: &[Color], variance: Option<&[f64]>, p: usize, q: usize) -> f64 {
+        let variance = match variance {
+            Some(variance) => variance[p] + variance[q],
+            None => return 1.0,
+        };
+        let distance = sq(pixels[p].luminance() - pixels[q].luminance());
+        if distance == 0.0 {
+            1.0
+        } else {
+            gaussian(distance / variance, self.sigma_color)
+        }
+    }
+}
+
+fn demodulation(albedo: Color) -> Color {
+    let f = |a: f64| if a < MIN_ALBEDO { 1.0 } else { a };
+    color(f(albedo.r), f(albedo.g), f(albedo.b))
+}
+
+// Weight for a squared distance.
+fn gaussian(distance_squared: f64, sigma: f64) -> f64 {
+    (-distance_squared / (2.0 * sigma * sigma)).exp()
+}
+
+fn sq(x: f64) -> f64 {
+    x * x
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampler::mix;
+    use crate::vec::Vec3;
+
+    fn flat(width: usize, height: usize) -> Aovs {
+        let n = width * height;
+        Aovs {
+            width,
+            height,
+            albedo: vec![color(0.5, 0.5, 0.5); n],
+            normal: vec![Vec3::new(0.0, 0.0, 1.0); n],
+            depth: vec![10.0; n],
+            position: vec![Vec3::zero(); n],
+            uv: vec![(0.0, 0.0); n],
+            object: vec![1; n],
+            material: vec![1; n],
+        }
+    }
+
+    #[test]
+    fn smooths_noise_but_not_across_edges() {
+        let (width, height) = (32, 32);
+        let mut aovs = flat(width, height);
+        // A wall of different albedo on the right half.
+        let noise = |i: usize| (mix(i as u64, 7) >> 11) as f64 / (1u64 << 53) as f64 - 0.5;
+        let mut pixels = Vec::new();
+        for i in 0..width * height {
+            let right = i % width >= width / 2;
+            if right {
+                aovs.albedo[i] = color(0.9, 0.1, 0.1);
+            }
+            let base = if right { 0.9 } else { 0.5 };
+            let v = base * (1.0 + 0.5 * noise(i));
+            pixels.push(color(v, v, v));
+        }
+        let denoised = Denoiser::default().denoise(&pixels, &aovs, None);
+
+        let spread = |pixels: &[Color]| {
+            let left: Vec<f64> = (0..width * height)
+                .filter(|i| i % width < width / 2)
+                .map(|i| pixels[i].r)
+                .collect();
+            let mean = left.iter().sum::<f64>() / left.len() as f64;
+            left.iter().map(|v| sq(v - mean)).sum::<f64>() / left.len() as f64
+        };
+        assert!(spread(&denoised) < 0.1 * spread(&pixels));
+        // The pixels next to the edge keep their own side's brightness.
+        let row = 16 * width;
+        assert!((denoised[row + width / 2 - 1].r - 0.5).abs() < 0.05);
+        assert!((denoised[row + width / 2].r - 0.9).abs() < 0.1);
+    }
+
+    #[test]
+    fn pixels_that_arent_finite_dont_spread() {
+        let (width, height) = (16, 16);
+        let aovs = flat(width, height);
+        let mut pixels = vec![color(0.5, 0.5, 0.5); width * height];
+        pixels[5 * width + 5] = color(f64::NAN, 0.5, 0.5);
+        pixels[10 * width + 10] = color(f64::INFINITY, 0.5, 0.5);
+        let mut variance = vec![0.01; width * height];
+        variance[3] = f64::NAN;
+        for variance in [None, Some(&variance[..])].iter() {
+            let denoised = Denoiser::default().denoise(&pixels, &aovs, *variance);
+            for (i, c) in denoised.iter().enumerate() {
+                let bad =
+                    i == 5 * width + 5 || i == 10 * width + 10 || (i == 3 && variance.is_some());
+                let expected = if bad { 0.0 } else { 0.5 };
+                assert!((c.r - expected).abs() < 1e-9, "{}: {:?}", i, c);
+            }
+        }
+    }
+}
@@ -0,0 +1,296 @@
+use crate::color::{color, Color};
+use crate::hittable::HitRecord;
+use crate::render::{camera_sample, trace, RenderSettings};
+use crate::scenes::Scene;
+use crate::vec::Vec3;
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+// Samples that are NaN, infinite or negative usually come from a bug, e.g. a
+// pdf of 0 or a square root of a negative number, and the output formats
+// quietly turn them black. Diagnostics trace the samples of a render again
+// (they only depend on the pixel, index and seed, so they're the same ones),
+// count the bad ones per pixel and blame the first surface hit after which
+// the path went bad.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Problem {
+    Nan,
+    Infinite,
+    Negative,
+}
+
+impl Problem {
+    // A NaN in any channel makes the sample NaN, and so on.
+    pub fn of(c: Color) -> Option<Problem> {
+        let channels = [c.r, c.g, c.b];
+        if channels.iter().any(|x| x.is_nan()) {
+            Some(Problem::Nan)
+        } else if channels.iter().any(|x| x.is_infinite()) {
+            Some(Problem::Infinite)
+        } else if channels.iter().any(|&x| x < 0.0) {
+            Some(Problem::Negative)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Counts {
+    pub nan: u64,
+    pub infinite: u64,
+    pub negative: u64,
+}
+
+impl Counts {
+    fn add(&mut self, problem: Problem) {
+        match problem {
+            Problem::Nan => self.nan += 1,
+            Problem::Infinite => self.infinite += 1,
+            Problem::Negative => self.negative += 1,
+        }
+    }
+
+    fn merge(&mut self, other: &Counts) {
+        self.nan += other.nan;
+        self.infinite += other.infinite;
+        self.negative += other.negative;
+    }
+
+    pub fn total(&self) -> u64 {
+        self.nan + self.infinite + self.negative
+    }
+}
+
+impl fmt::Display for Counts {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} NaN, {} infinite, {} negative",
+            self.nan, self.infinite, self.negative
+        )
+    }
+}
+
+// The surface a path went bad at: its object and material, told apart by
+// identity, and the first point where it happened to describe the object by.
+#[derive(Debug, Clone, Copy)]
+pub struct Culprit {
+    pub object: usize,
+    pub material: usize,
+    pub material_name: &'static str,
+    pub point: Vec3,
+}
+
+impl Culprit {
+    pub fn of(hit: &HitRecord) -> Culprit {
+        Culprit {
+            object: hit.object,
+            material: Arc::as_ptr(&hit.mat) as usize,
+            material_name: hit.mat.name(),
+            point: hit.point,
+        }
+    }
+}
+
+// The bad samples one culprit caused. Paths that went bad without hitting
+// anything, at the background, have no culprit.
+#[derive(Debug, Clone, Copy)]
+pub struct Blame {
+    pub culprit: Option<Culprit>,
+    pub counts: Counts,
+}
+
+// Rows are stored top to bottom like the framebuffer's.
+#[derive(Debug, Clone)]
+pub struct Diagnostics {
+    pub width: usize,
+    pub height: usize,
+    pub samples: u64,
+    pub counts: Vec<Counts>,
+    // Most bad samples first.
+    pub blame: Vec<Blame>,
+}
+
+impl Diagnostics {
+    pub fn total(&self) -> Counts {
+        let mut total = Counts::default();
+        for counts in &self.counts {
+            total.merge(counts);
+        }
+        total
+    }
+
+    // Pixels with NaN samples are red, infinite green and negative blue, so
+    // mixes of them show as the mixed colors. The rest are black.
+    pub fn mask(&self) -> Vec<Color> {
+        let on = |n: u64| if n > 0 { 1.0 } else { 0.0 };
+        self.counts
+            .iter()
+            .map(|c| color(on(c.nan), on(c.infinite), on(c.negative)))
+            .collect()
+    }
+}
+
+// How many culprits the report lists.
+const REPORTED: usize = 10;
+
+impl fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let total = self.total();
+        if total.total() == 0 {
+            return write!(f, "No NaN, infinite or negative samples");
+        }
+        let pixels = self.counts.iter().filter(|c| c.total() > 0).count();
+        write!(
+            f,
+            "{} of {} samples were bad ({}) in {} pixels",
+            total.total(),
+            self.samples,
+            total,
+            pixels
+        )?;
+        for blame in self.blame.iter().take(REPORTED) {
+            match &blame.culprit {
+                Some(culprit) => {
+                    let p = culprit.point;
+                    write!(
+                        f,
+                        "\n  {}: {} material, object at ({:.3}, {:.3}, {:.3})",
+                        blame.counts, culprit.material_name, p.x, p.y, p.z
+                    )?;
+                }
+                None => write!(f, "\n  {}: background", blame.counts)?,
+            }
+        }
+        if self.blame.len() > REPORTED {
+            write!(f, "\n  and {} more", self.blame.len() - REPORTED)?;
+        }
+        Ok(())
+    }
+}
+
+struct Pixel {
+    counts: Counts,
+    blame: Vec<Blame>,
+}
+
+pub(super) fn run(
+    scene: &Scene,
+    settings: &RenderSettings,
+    seed: u64,
+    samples: &[u64],
+) -> Diagnostics {
+    let (width, height) = (settings.width, settings.height);
+    assert_eq!(samples.len(), width * height);
+    let pixels: Vec<Pixel> = (0..height)
+        .into_par_iter()
+        .flat_map_iter(|y| {
+            let mut sampler = settings.sampler.sampler(settings.samples_per_pixel, seed);
+            (0..width)
+                .map(|x| {
+                    let mut pixel = Pixel {
+                        counts: Counts::default(),
+                        blame: Vec::new(),
+                    };
+                    for i in 0..samples[y * width + x] {
+                        let (ray, _, _) =
+                            camera_sample(&scene.camera, settings, &mut sampler, x, y, i);
+                        let (sample, culprit) = trace(
+                            ray,
+                            scene.camera.background,
+                            &scene.hittables,
+                            &scene.lights,
+                            settings.max_depth,
+                            settings.rr_depth,
+                            &mut sampler,
+                        );
+                        if let Some(problem) = Problem::of(sample) {
+                            pixel.counts.add(problem);
+                            let mut counts = Counts::default();
+                            counts.add(problem);
+                            pixel.blame.push(Blame { culprit, counts });
+                        }
+                    }
+                    pixel
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect();
+
+    // Blame the same object and material together, keeping the first point.
+    let mut blame: Vec<Blame> = Vec::new();
+    let mut index: HashMap<Option<(usize, usize)>, usize> = HashMap::new();
+    for b in pixels.iter().flat_map(|pixel| pixel.blame.iter()) {
+        let key = b.culprit.map(|c| (c.object, c.material));
+        match index.get(&key) {
+            Some(&i) => blame[i].counts.merge(&b.counts),
+            None => {
+                index.insert(key, blame.len());
+                blame.push(*b);
+            }
+        }
+    }
+    blame.sort_by_key(|b| std::cmp::Reverse(b.counts.total()));
+
+    Diagnostics {
+        width,
+        height,
+        samples: samples.iter().sum(),
+        counts: pixels.iter().map(|pixel| pixel.counts).collect(),
+        blame,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittable::{hittable_list::HittableList, rect::XyRect, Hittables};
+    use crate::material::lambertian::Lambertian;
+    use crate::render::Renderer;
+    use crate::scenes::cornell_box::cornell_box;
+    use crate::texture::solidcolor::SolidColor;
+
+    #[test]
+    fn finds_the_material_that_made_nans() {
+        let settings = RenderSettings {
+            width: 10,
+            height: 10,
+            samples_per_pixel: 4,
+            seed: Some(1),
+            ..RenderSettings::default()
+        };
+        let samples = vec![4; 100];
+        let renderer = Renderer::new(settings);
+        let scene = cornell_box(0.0, 1.0, 1.0);
+        let clean = renderer.diagnose(&scene, 1, &samples);
+        assert_eq!(clean.total().total(), 0);
+        assert!(clean.blame.is_empty());
+
+        // A back wall whose albedo is NaN, with nothing else to hit.
+        let broken = Lambertian::new(SolidColor::new(f64::NAN, 0.5, 0.5));
+        let mut world = HittableList {
+            hittables: Vec::new(),
+        };
+        world.add(XyRect::new(-1e4, 1e4, -1e4, 1e4, 555.0, broken));
+        let scene = Scene {
+            hittables: Hittables::from(world),
+            lights: Hittables::from(HittableList {
+                hittables: Vec::new(),
+            }),
+            ..scene
+        };
+        let diagnostics = renderer.diagnose(&scene, 1, &samples);
+        assert_eq!(diagnostics.total().nan, 400);
+        assert_eq!(diagnostics.blame.len(), 1);
+        let culprit = diagnostics.blame[0].culprit.unwrap();
+        assert_eq!(culprit.material_name, "lambertian");
+        assert!((culprit.point.z - 555.0).abs() < 1e-6);
+        assert!(diagnostics
+            .mask()
+            .iter()
+            .all(|c| (c.r, c.g, c.b) == (1.0, 0.0, 0.0)));
+    }
+}
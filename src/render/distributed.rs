@@ -0,0 +1,687 @@
+use crate::render::checkpoint::{read_stats, read_sums, write_stats, write_sums};
+use crate::render::tile::{tiles, Tile};
+use crate::render::{pixel_finished, Accumulator, Framebuffer, TileBuffer};
+use crate::render::{RenderSettings, Renderer};
+use crate::scenes::Scene;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+// Rendering split across processes. A coordinator owns the image and hands
+// out tiles; workers connect, get the scene description and settings, and
+// render whole tiles until none are left. A tile whose worker goes away is
+// handed to the next worker that asks, so workers can come and go.
+//
+// Messages are JSON, one per line. A tile's pixels follow the message that
+// names the tile, in the checkpoint encoding, so they arrive bit for bit.
+// They include the pixels around the tile its samples spill into, which the
+// coordinator adds once every tile is back.
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Address {
+    Tcp(String),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl FromStr for Address {
+    type Err = String;
+
+    // tcp:host:port or unix:path, a bare host:port is TCP.
+    fn from_str(s: &str) -> Result<Address, String> {
+        if let Some(path) = s.strip_prefix("unix:") {
+            #[cfg(unix)]
+            return Ok(Address::Unix(PathBuf::from(path)));
+            #[cfg(not(unix))]
+            return Err(format!("unix sockets aren't supported here: {}", path));
+        }
+        let host = s.strip_prefix("tcp:").unwrap_or(s);
+        if host.contains(':') {
+            Ok(Address::Tcp(host.to_string()))
+        } else {
+            Err(format!(
+                "bad address {}, expected tcp:host:port or unix:path",
+                s
+            ))
+        }
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Address::Tcp(host) => write!(f, "tcp:{}", host),
+            #[cfg(unix)]
+            Address::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener, PathBuf),
+}
+
+impl Listener {
+    fn accept(&self) -> io::Result<Connection> {
+        match self {
+            Listener::Tcp(listener) => listener.accept().map(|(s, _)| Connection::Tcp(s)),
+            #[cfg(unix)]
+            Listener::Unix(listener, _) => listener.accept().map(|(s, _)| Connection::Unix(s)),
+        }
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        match self {
+            Listener::Tcp(listener) => listener.set_nonblocking(nonblocking),
+            #[cfg(unix)]
+            Listener::Unix(listener, _) => listener.set_nonblocking(nonblocking),
+        }
+    }
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if let Listener::Unix(_, path) = self {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+enum Connection {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Connection {
+    fn connect(address: &Address) -> io::Result<Connection> {
+        match address {
+            Address::Tcp(host) => TcpStream::connect(host).map(Connection::Tcp),
+            #[cfg(unix)]
+            Address::Unix(path) => UnixStream::connect(path).map(Connection::Unix),
+        }
+    }
+
+    fn try_clone(&self) -> io::Result<Connection> {
+        match self {
+            Connection::Tcp(stream) => stream.try_clone().map(Connection::Tcp),
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.try_clone().map(Connection::Unix),
+        }
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        match self {
+            Connection::Tcp(stream) => stream.set_nonblocking(nonblocking),
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.set_nonblocking(nonblocking),
+        }
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Connection::Tcp(stream) => stream.set_read_timeout(timeout),
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.set_read_timeout(timeout),
+        }
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Connection::Tcp(stream) => stream.set_write_timeout(timeout),
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.set_write_timeout(timeout),
+        }
+    }
+
+    fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        match self {
+            Connection::Tcp(stream) => stream.shutdown(how),
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.shutdown(how),
+        }
+    }
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Connection::Tcp(stream) => stream.read(buf),
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Connection::Tcp(stream) => stream.write(buf),
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Connection::Tcp(stream) => stream.flush(),
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+enum Message {
+    // Sent to every worker that connects.
+    Job {
+        scene: String,
+        settings: RenderSettings,
+        seed: u64,
+    },
+    // The worker wants a tile.
+    Ready,
+    // A tile to render, followed by its pixels so far.
+    Render {
+        tile: Tile,
+    },
+    // A rendered tile, followed by its pixels.
+    Finished {
+        tile: Tile,
+    },
+    // There's nothing left to render.
+    Done,
+}
+
+fn send(out: &mut impl Write, message: &Message) -> io::Result<()> {
+    serde_json::to_writer(&mut *out, message)?;
+    out.write_all(b"\n")
+}
+
+fn receive(input: &mut impl BufRead) -> io::Result<Message> {
+    let mut line = String::new();
+    if input.read_line(&mut line)? == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "connection closed",
+        ));
+    }
+    Ok(serde_json::from_str(&line)?)
+}
+
+fn unexpected(message: Message) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("unexpected message {:?}", message),
+    )
+}
+
+fn send_tile(out: &mut impl Write, message: Message, buffer: &TileBuffer) -> io::Result<()> {
+    send(out, &message)?;
+    write_sums(out, &buffer.sums, &buffer.weights)?;
+    write_stats(out, &buffer.stats)?;
+    out.flush()
+}
+
+fn receive_tile(
+    input: &mut impl Read,
+    tile: &Tile,
+    settings: &RenderSettings,
+) -> io::Result<TileBuffer> {
+    let margin = settings.filter.margin();
+    let region = tile.grow(margin, settings.width, settings.height);
+    let mut buffer = TileBuffer::new(tile, region);
+    buffer.stats.resize(tile.len(), Default::default());
+    read_sums(input, &mut buffer.sums, &mut buffer.weights)?;
+    read_stats(input, &mut buffer.stats)?;
+    Ok(buffer)
+}
+
+// How long the coordinator waits on a worker before giving its tile to
+// another one. A worker is quiet while it renders a tile, so this has to be
+// longer than any tile takes.
+const WORKER_TIMEOUT: Duration = Duration::from_secs(600);
+
+// How long a worker waits for the job after connecting. The coordinator
+// sends it as soon as it takes the connection, unless it's finished.
+const JOB_TIMEOUT: Duration = Duration::from_secs(5);
+
+struct Work {
+    // Tiles with their place in the order.
+    queue: VecDeque<(usize, Tile)>,
+    in_flight: usize,
+    accumulator: Accumulator,
+    // Finished tiles' samples that landed around them, by place.
+    spills: Vec<Option<(Tile, TileBuffer)>>,
+}
+
+// The coordinator's side: tiles waiting to be rendered, and the image.
+struct Shared {
+    work: Mutex<Work>,
+    changed: Condvar,
+}
+
+impl Shared {
+    // The next tile to render. When the queue is empty but tiles are still
+    // out, waits in case one of them comes back unfinished.
+    fn next_tile(&self) -> Option<(usize, Tile)> {
+        let mut work = self.work.lock().unwrap();
+        loop {
+            if let Some(tile) = work.queue.pop_front() {
+                work.in_flight += 1;
+                return Some(tile);
+            }
+            if work.in_flight == 0 {
+                return None;
+            }
+            work = self.changed.wait(work).unwrap();
+        }
+    }
+
+    fn finish(&self, index: usize, tile: &Tile, buffer: TileBuffer) {
+        let mut work = self.work.lock().unwrap();
+        work.accumulator.set_tile(tile, &buffer);
+        if buffer.region != *tile {
+            work.spills[index] = Some((*tile, buffer));
+        }
+        work.in_flight -= 1;
+        self.changed.notify_all();
+    }
+
+    fn give_back(&self, index: usize, tile: Tile) {
+        let mut work = self.work.lock().unwrap();
+        work.queue.push_front((index, tile));
+        work.in_flight -= 1;
+        self.changed.notify_all();
+    }
+
+    fn is_done(&self) -> bool {
+        let work = self.work.lock().unwrap();
+        work.queue.is_empty() && work.in_flight == 0
+    }
+}
+
+pub struct Coordinator {
+    listener: Listener,
+}
+
+impl Coordinator {
+    pub fn bind(address: &Address) -> io::Result<Coordinator> {
+        let listener = match address {
+            Address::Tcp(host) => Listener::Tcp(TcpListener::bind(host)?),
+            #[cfg(unix)]
+            Address::Unix(path) => {
+                // A socket left behind by an earlier run would fail the bind.
+                let _ = std::fs::remove_file(path);
+                Listener::Unix(UnixListener::bind(path)?, path.clone())
+            }
+        };
+        Ok(Coordinator { listener })
+    }
+
+    // Where workers connect, with the actual port when bound to port 0.
+    pub fn address(&self) -> io::Result<Address> {
+        match &self.listener {
+            Listener::Tcp(listener) => Ok(Address::Tcp(listener.local_addr()?.to_string())),
+            #[cfg(unix)]
+            Listener::Unix(_, path) => Ok(Address::Unix(path.clone())),
+        }
+    }
+
+    // Hand the unfinished tiles of `accumulator` to the workers that connect
+    // and return it once every tile is back. `scene` is passed on to the
+    // workers as it is; `on_tile` gets each tile as it comes back.
+    pub fn run(
+        &self,
+        scene: &str,
+        settings: &RenderSettings,
+        accumulator: Accumulator,
+        on_tile: impl Fn(&Tile, &Framebuffer) + Sync,
+    ) -> io::Result<Accumulator> {
+        let job = Message::Job {
+            scene: scene.to_string(),
+            settings: settings.clone(),
+            seed: accumulator.seed,
+        };
+        let queue: VecDeque<_> = tiles(
+            accumulator.width,
+            accumulator.height,
+            settings.tile_size,
+            settings.tile_order,
+        )
+        .into_iter()
+        .filter(|tile| {
+            let buffer = accumulator.tile(tile, 0);
+            !buffer
+                .stats
+                .iter()
+                .all(|stats| pixel_finished(stats, settings))
+        })
+        .enumerate()
+        .collect();
+        let spills = vec![None; queue.len()];
+        let shared = Shared {
+            work: Mutex::new(Work {
+                queue,
+                in_flight: 0,
+                accumulator,
+                spills,
+            }),
+            changed: Condvar::new(),
+        };
+
+        // Poll for workers so we notice when the last tile is in.
+        self.listener.set_nonblocking(true)?;
+        let accepted = thread::scope(|s| -> io::Result<()> {
+            let mut connections = Vec::new();
+            let accepted = loop {
+                if shared.is_done() {
+                    break Ok(());
+                }
+                match self.listener.accept() {
+                    Ok(connection) => {
+                        connection.set_nonblocking(false)?;
+                        connection.set_read_timeout(Some(WORKER_TIMEOUT))?;
+                        connection.set_write_timeout(Some(WORKER_TIMEOUT))?;
+                        connections.push(connection.try_clone()?);
+                        let (shared, job, on_tile) = (&shared, &job, &on_tile);
+                        // A worker that fails or disconnects just stops
+                        // getting tiles; its tile goes back in the queue.
+                        s.spawn(move || {
+                            let _ = serve_worker(connection, shared, job, settings, on_tile);
+                        });
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                        thread::sleep(Duration::from_millis(20));
+                    }
+                    Err(e) => break Err(e),
+                }
+            };
+            // No worker has a tile now, or we're giving up. Stop listening
+            // to them so the ones waiting to ask for a tile, or hung, don't
+            // keep us here; the ones telling their worker we're done still
+            // can.
+            for connection in &connections {
+                let _ = connection.shutdown(Shutdown::Read);
+            }
+            accepted
+        });
+        self.listener.set_nonblocking(false)?;
+        accepted?;
+        let work = shared.work.into_inner().unwrap();
+        let mut accumulator = work.accumulator;
+        for (tile, buffer) in work.spills.into_iter().flatten() {
+            accumulator.add_spill(&tile, &buffer);
+        }
+        Ok(accumulator)
+    }
+}
+
+fn serve_worker(
+    connection: Connection,
+    shared: &Shared,
+    job: &Message,
+    settings: &RenderSettings,
+    on_tile: &(impl Fn(&Tile, &Framebuffer) + Sync),
+) -> io::Result<()> {
+    let mut out = connection.try_clone()?;
+    let mut input = BufReader::new(connection);
+    send(&mut out, job)?;
+    out.flush()?;
+    loop {
+        match receive(&mut input)? {
+            Message::Ready => {}
+            message => return Err(unexpected(message)),
+        }
+        let (index, tile) = match shared.next_tile() {
+            Some(next) => next,
+            None => {
+                send(&mut out, &Message::Done)?;
+                return out.flush();
+            }
+        };
+        let rendered = (|| {
+            let margin = settings.filter.margin();
+            let buffer = shared.work.lock().unwrap().accumulator.tile(&tile, margin);
+            send_tile(&mut out, Message::Render { tile }, &buffer)?;
+            match receive(&mut input)? {
+                Message::Finished { tile: done } if done == tile => {}
+                message => return Err(unexpected(message)),
+            }
+            receive_tile(&mut input, &tile, settings)
+        })();
+        match rendered {
+            Ok(buffer) => {
+                let pixels = buffer.framebuffer(&tile);
+                shared.finish(index, &tile, buffer);
+                on_tile(&tile, &pixels);
+            }
+            Err(e) => {
+                shared.give_back(index, tile);
+                return Err(e);
+            }
+        }
+    }
+}
+
+// Render tiles for the coordinator at `address` over `threads` connections
+// until it has none left, and return how many were rendered. The coordinator
+// may not be listening yet, so connecting is retried for a few seconds.
+// `build_scene` makes the scene from the job's description and settings.
+pub fn work(
+    address: &Address,
+    threads: usize,
+    build_scene: impl FnOnce(&str, &RenderSettings) -> Scene,
+) -> io::Result<usize> {
+    let (first, scene_description, settings, seed) = join(address, 50)?;
+    let scene = build_scene(&scene_description, &settings);
+    let renderer = Renderer::new(settings);
+    let rendered = AtomicUsize::new(0);
+    let mut first = Some(first);
+    thread::scope(|s| {
+        let handles: Vec<_> = (0..threads.max(1))
+            .map(|_| {
+                let first = first.take();
+                let (scene, renderer, rendered) = (&scene, &renderer, &rendered);
+                s.spawn(move || -> io::Result<()> {
+                    let input = match first {
+                        Some(input) => input,
+                        // The coordinator may have finished while the
+                        // other connections were rendering.
+                        None => match join(address, 0) {
+                            Ok((input, ..)) => input,
+                            Err(e) if coordinator_gone(&e) => return Ok(()),
+                            Err(e) => return Err(e),
+                        },
+                    };
+                    render_tiles(input, scene, renderer, seed, rendered)
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect::<io::Result<Vec<()>>>()
+    })?;
+    Ok(rendered.into_inner())
+}
+
+// Connect, retrying up to `retries` times, and read the job.
+fn join(
+    address: &Address,
+    retries: usize,
+) -> io::Result<(BufReader<Connection>, String, RenderSettings, u64)> {
+    let mut attempts = 0;
+    let connection = loop {
+        match Connection::connect(address) {
+            Ok(connection) => break connection,
+            Err(_) if attempts < retries => {
+                attempts += 1;
+                thread::sleep(Duration::from_millis(100));
+            }
+            Err(e) => return Err(e),
+        }
+    };
+    // A coordinator that's finished still holds its socket until it exits,
+    // but never takes the connection.
+    connection.set_read_timeout(Some(JOB_TIMEOUT))?;
+    let mut input = BufReader::new(connection);
+    let job = receive(&mut input)?;
+    input.get_ref().set_read_timeout(None)?;
+    match job {
+        Message::Job {
+            scene,
+            settings,
+            seed,
+        } => Ok((input, scene, settings, seed)),
+        message => Err(unexpected(message)),
+    }
+}
+
+// Whether `e` is what connecting to or asking a finished coordinator for a
+// tile runs into.
+fn coordinator_gone(e: &io::Error) -> bool {
+    use io::ErrorKind::*;
+    matches!(
+        e.kind(),
+        UnexpectedEof
+            | BrokenPipe
+            | ConnectionRefused
+            | ConnectionReset
+            | NotFound
+            | WouldBlock
+            | TimedOut
+    )
+}
+
+fn render_tiles(
+    mut input: BufReader<Connection>,
+    scene: &Scene,
+    renderer: &Renderer,
+    seed: u64,
+    rendered: &AtomicUsize,
+) -> io::Result<()> {
+    let mut out = input.get_ref().try_clone()?;
+    loop {
+        // The coordinator stops listening once every tile is in, and may
+        // not get to say so.
+        let asked = send(&mut out, &Message::Ready)
+            .and_then(|_| out.flush())
+            .and_then(|_| receive(&mut input));
+        let tile = match asked {
+            Ok(Message::Render { tile }) => tile,
+            Ok(Message::Done) => return Ok(()),
+            Ok(message) => return Err(unexpected(message)),
+            Err(e) if coordinator_gone(&e) => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        let mut buffer = receive_tile(&mut input, &tile, &renderer.settings)?;
+        renderer.finish_tile(scene, &tile, seed, &mut buffer);
+        send_tile(&mut out, Message::Finished { tile }, &buffer)?;
+        rendered.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scenes::cornell_box::cornell_box;
+
+    #[test]
+    fn workers_render_the_same_image() {
+        let settings = RenderSettings {
+            width: 8,
+            height: 6,
+            samples_per_pixel: 4,
+            seed: Some(1),
+            tile_size: 3,
+            threads: 1,
+            ..RenderSettings::default()
+        };
+        let local = Renderer::new(settings.clone())
+            .render(&cornell_box(0.0, 1.0, 8.0 / 6.0))
+            .unwrap();
+
+        let coordinator = Coordinator::bind(&Address::Tcp("127.0.0.1:0".to_string())).unwrap();
+        let address = coordinator.address().unwrap();
+        let worker = thread::spawn(move || {
+            work(&address, 2, |scene, settings| {
+                assert_eq!(scene, "cornell_box");
+                let (width, height) = (settings.width as f64, settings.height as f64);
+                cornell_box(0.0, 1.0, width / height)
+            })
+        });
+        let tiles = AtomicUsize::new(0);
+        let accumulator = coordinator
+            .run(
+                "cornell_box",
+                &settings,
+                Accumulator::new(8, 6, 1),
+                |_, _| {
+                    tiles.fetch_add(1, Ordering::Relaxed);
+                },
+            )
+            .unwrap();
+        assert_eq!(worker.join().unwrap().unwrap(), 6);
+        assert_eq!(tiles.into_inner(), 6);
+
+        let distributed = accumulator.framebuffer();
+        let bits = |f: &Framebuffer| -> Vec<u64> {
+            f.pixels
+                .iter()
+                .flat_map(|c| vec![c.r.to_bits(), c.g.to_bits(), c.b.to_bits()])
+                .collect()
+        };
+        assert_eq!(bits(&local), bits(&distributed));
+    }
+
+    #[test]
+    fn idle_workers_dont_hold_up_the_coordinator() {
+        let settings = RenderSettings {
+            width: 4,
+            height: 4,
+            samples_per_pixel: 1,
+            seed: Some(1),
+            tile_size: 2,
+            threads: 1,
+            ..RenderSettings::default()
+        };
+        let coordinator = Coordinator::bind(&Address::Tcp("127.0.0.1:0".to_string())).unwrap();
+        let address = coordinator.address().unwrap();
+        // Connects and never asks for a tile.
+        let idle = match &address {
+            Address::Tcp(host) => TcpStream::connect(host).unwrap(),
+            #[cfg(unix)]
+            Address::Unix(_) => unreachable!(),
+        };
+        let worker = thread::spawn(move || {
+            work(&address, 4, |_, settings| {
+                let (width, height) = (settings.width as f64, settings.height as f64);
+                cornell_box(0.0, 1.0, width / height)
+            })
+        });
+        coordinator
+            .run(
+                "cornell_box",
+                &settings,
+                Accumulator::new(4, 4, 1),
+                |_, _| {},
+            )
+            .unwrap();
+        assert_eq!(worker.join().unwrap().unwrap(), 4);
+        drop(idle);
+    }
+}
@@ -0,0 +1,191 @@
+use serde::{Deserialize, Serialize};
+use std::f64::consts::PI;
+use std::fmt;
+use std::str::FromStr;
+
+// Reconstruction filters. Every sample is splatted into each pixel whose
+// centre is within `radius` of it, weighted by the filter, and a pixel is its
+// weighted sum divided by the sum of the weights. The filters are separable,
+// the weight is f(dx) * f(dy). Mitchell and Lanczos have negative lobes, so
+// they sharpen but can ring around bright edges.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FilterKind {
+    // Only the pixel the sample is in, the same as averaging its samples.
+    #[default]
+    Box,
+    Tent,
+    Gaussian,
+    // Mitchell-Netravali with B = C = 1/3.
+    Mitchell,
+    // Sinc windowed by a sinc stretched to the radius.
+    Lanczos,
+}
+
+impl FilterKind {
+    pub fn default_radius(&self) -> f64 {
+        match self {
+            FilterKind::Box => 0.5,
+            FilterKind::Tent => 1.0,
+            FilterKind::Gaussian => 1.5,
+            FilterKind::Mitchell => 2.0,
+            FilterKind::Lanczos => 3.0,
+        }
+    }
+}
+
+impl FromStr for FilterKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<FilterKind, String> {
+        match s {
+            "box" => Ok(FilterKind::Box),
+            "tent" => Ok(FilterKind::Tent),
+            "gaussian" => Ok(FilterKind::Gaussian),
+            "mitchell" => Ok(FilterKind::Mitchell),
+            "lanczos" => Ok(FilterKind::Lanczos),
+            _ => Err(format!(
+                "unknown filter {}, expected box, tent, gaussian, mitchell or lanczos",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for FilterKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            FilterKind::Box => "box",
+            FilterKind::Tent => "tent",
+            FilterKind::Gaussian => "gaussian",
+            FilterKind::Mitchell => "mitchell",
+            FilterKind::Lanczos => "lanczos",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// `radius` is in pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Filter {
+    pub kind: FilterKind,
+    pub radius: f64,
+}
+
+impl Default for Filter {
+    fn default() -> Filter {
+        Filter::new(FilterKind::Box)
+    }
+}
+
+impl Filter {
+    pub fn new(kind: FilterKind) -> Filter {
+        Filter {
+            kind,
+            radius: kind.default_radius(),
+        }
+    }
+
+    // Weight of a sample at offset (dx, dy) from a pixel centre.
+    pub fn evaluate(&self, dx: f64, dy: f64) -> f64 {
+        self.evaluate_1d(dx) * self.evaluate_1d(dy)
+    }
+
+    fn evaluate_1d(&self, x: f64) -> f64 {
+        let r = self.radius;
+        let x = x.abs();
+        if x > r {
+            return 0.0;
+        }
+        match self.kind {
+            FilterKind::Box => 1.0,
+            FilterKind::Tent => 1.0 - x / r,
+            FilterKind::Gaussian => {
+                // Shifted down so it reaches zero at the radius.
+                let sigma = r / 3.0;
+                let gaussian = |x: f64| (-x * x / (2.0 * sigma * sigma)).exp();
+                (gaussian(x) - gaussian(r)).max(0.0)
+            }
+            FilterKind::Mitchell => mitchell(2.0 * x / r, 1.0 / 3.0, 1.0 / 3.0),
+            FilterKind::Lanczos => sinc(x) * sinc(x / r),
+        }
+    }
+
+    // How many pixels beyond its own a sample can reach.
+    pub fn margin(&self) -> usize {
+        (self.radius - 0.5).ceil().max(0.0) as usize
+    }
+
+    // Pixels along one axis whose centres are within the radius of a sample
+    // at `p`, where pixel i covers [i, i + 1). Half open so the box filter
+    // only ever picks the pixel the sample is in.
+    pub fn reach(&self, p: f64) -> (i64, i64) {
+        let first = (p - self.radius - 0.5).floor() as i64 + 1;
+        let last = (p + self.radius - 0.5).floor() as i64;
+        (first, last)
+    }
+}
+
+// Cubic for x in [0, 2], zero beyond.
+fn mitchell(x: f64, b: f64, c: f64) -> f64 {
+    let x = x.abs();
+    let v = if x < 1.0 {
+        (12.0 - 9.0 * b - 6.0 * c) * x * x * x
+            + (-18.0 + 12.0 * b + 6.0 * c) * x * x
+            + (6.0 - 2.0 * b)
+    } else if x < 2.0 {
+        (-b - 6.0 * c) * x * x * x
+            + (6.0 * b + 30.0 * c) * x * x
+            + (-12.0 * b - 48.0 * c) * x
+            + (8.0 * b + 24.0 * c)
+    } else {
+        0.0
+    };
+    v / 6.0
+}
+
+fn sinc(x: f64) -> f64 {
+    if x.abs() < 1e-5 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn box_only_reaches_its_own_pixel() {
+        let filter = Filter::default();
+        assert_eq!(filter.margin(), 0);
+        for &p in [3.0, 3.25, 3.5, 3.999].iter() {
+            assert_eq!(filter.reach(p), (3, 3));
+        }
+        let filter = Filter::new(FilterKind::Mitchell);
+        assert_eq!(filter.margin(), 2);
+        assert_eq!(filter.reach(3.0), (1, 4));
+        assert_eq!(filter.reach(3.75), (2, 5));
+    }
+
+    #[test]
+    fn filters_peak_in_the_middle_and_vanish_at_the_radius() {
+        for &kind in [
+            FilterKind::Tent,
+            FilterKind::Gaussian,
+            FilterKind::Mitchell,
+            FilterKind::Lanczos,
+        ]
+        .iter()
+        {
+            let filter = Filter::new(kind);
+            let r = filter.radius;
+            let centre = filter.evaluate(0.0, 0.0);
+            assert!(centre > 0.0, "{}", kind);
+            assert!(filter.evaluate(0.3, 0.0) < centre, "{}", kind);
+            assert!(filter.evaluate(r, 0.0).abs() < 1e-9, "{}", kind);
+            assert_eq!(filter.evaluate(r + 0.1, 0.0), 0.0, "{}", kind);
+        }
+    }
+}
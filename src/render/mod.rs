@@ -0,0 +1,1006 @@
+use crate::camera::Camera;
+use crate::color::{color, Color};
+use crate::hittable::{Hittable, Hittables};
+use crate::material::Material;
+use crate::pdf::{power_heuristic, Pdf};
+use crate::ray::Ray;
+use crate::sampler::{Sampler, SamplerKind, SamplerType};
+use crate::scenes::Scene;
+use crate::spectrum;
+use crate::vec::Vec3;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+pub mod adaptive;
+pub mod aov;
+pub mod checkpoint;
+pub mod denoise;
+pub mod diagnostics;
+pub mod distributed;
+pub mod filter;
+pub mod tile;
+
+use adaptive::{heat, Adaptive, PixelStats};
+use aov::Aovs;
+use diagnostics::{Culprit, Diagnostics, Problem};
+use filter::Filter;
+use tile::{tiles, Tile, TileOrder};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RenderSettings {
+    pub width: usize,
+    pub height: usize,
+    // With adaptive sampling this is the most a pixel gets.
+    pub samples_per_pixel: u64,
+    pub adaptive: Option<Adaptive>,
+    // Samples each pixel takes per pass, 0 renders everything in one pass.
+    pub pass_samples: u64,
+    // Hard limit on path length; Russian roulette usually ends paths first.
+    pub max_depth: u32,
+    // Bounces before Russian roulette starts terminating paths.
+    pub rr_depth: u32,
+    // Seeds each pixel's sample stream; None draws them from entropy.
+    pub seed: Option<u64>,
+    // Sequence the pixel, lens, time, light and BSDF samples are drawn from.
+    pub sampler: SamplerKind,
+    // Reconstruction filter the samples are splatted into the pixels with.
+    pub filter: Filter,
+    // Samples brighter than this luminance are scaled down to it, which
+    // trades a little energy for fewer fireflies.
+    pub clamp: Option<f64>,
+    // Tiles are `tile_size` pixels square.
+    pub tile_size: usize,
+    pub tile_order: TileOrder,
+    // Worker threads, 0 lets rayon decide.
+    pub threads: usize,
+}
+
+impl Default for RenderSettings {
+    fn default() -> RenderSettings {
+        RenderSettings {
+            width: 400,
+            height: 400,
+            samples_per_pixel: 100,
+            adaptive: None,
+            pass_samples: 16,
+            max_depth: 50,
+            rr_depth: 5,
+            seed: None,
+            sampler: SamplerKind::Independent,
+            filter: Filter::default(),
+            clamp: None,
+            tile_size: 32,
+            tile_order: TileOrder::Hilbert,
+            threads: 0,
+        }
+    }
+}
+
+// Linear radiance, the filtered average of the samples around each pixel,
+// and the number of samples each pixel took. Rows are stored top to bottom.
+#[derive(Debug, Clone)]
+pub struct Framebuffer {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Color>,
+    pub samples: Vec<u64>,
+}
+
+impl Framebuffer {
+    pub fn new(width: usize, height: usize) -> Framebuffer {
+        Framebuffer {
+            width,
+            height,
+            pixels: vec![Color::default(); width * height],
+            samples: vec![0; width * height],
+        }
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> Color {
+        self.pixels[y * self.width + x]
+    }
+
+    // Copy `pixels`, the size of `tile`, into the tile's place.
+    pub fn set_tile(&mut self, tile: &Tile, pixels: &Framebuffer) {
+        for (i, y) in (tile.y0..tile.y1).enumerate() {
+            let row = y * self.width;
+            let from = i * tile.width()..(i + 1) * tile.width();
+            self.pixels[row + tile.x0..row + tile.x1].copy_from_slice(&pixels.pixels[from.clone()]);
+            self.samples[row + tile.x0..row + tile.x1].copy_from_slice(&pixels.samples[from]);
+        }
+    }
+
+    pub fn mean_samples(&self) -> f64 {
+        self.samples.iter().sum::<u64>() as f64 / self.samples.len().max(1) as f64
+    }
+
+    // Sample counts as an image, from blue for the fewest to red for the
+    // most samples taken by any pixel.
+    pub fn sample_heatmap(&self) -> Vec<Color> {
+        let max = self.samples.iter().copied().max().unwrap_or(0).max(1);
+        self.samples
+            .iter()
+            .map(|&n| heat(n as f64 / max as f64))
+            .collect()
+    }
+}
+
+// Running sums of every sample taken so far, which passes add to and
+// checkpoints save. `sums` are the samples splatted into each pixel times
+// their filter weights and `weights` the sums of those weights, while `stats`
+// are over the samples taken in the pixel itself. `seed` is the one actually
+// used, so a resumed render continues the same sample streams.
+#[derive(Debug, Clone)]
+pub struct Accumulator {
+    pub width: usize,
+    pub height: usize,
+    pub seed: u64,
+    pub sums: Vec<Color>,
+    pub weights: Vec<f64>,
+    pub stats: Vec<PixelStats>,
+}
+
+impl Accumulator {
+    pub fn new(width: usize, height: usize, seed: u64) -> Accumulator {
+        Accumulator {
+            width,
+            height,
+            seed,
+            sums: vec![Color::default(); width * height],
+            weights: vec![0.0; width * height],
+            stats: vec![PixelStats::default(); width * height],
+        }
+    }
+
+    pub fn framebuffer(&self) -> Framebuffer {
+        resolve(
+            self.width,
+            self.height,
+            &self.sums,
+            &self.weights,
+            &self.stats,
+        )
+    }
+
+    // Variance of each pixel's mean luminance, for the denoiser. Infinite
+    // where there are too few samples to tell.
+    pub fn variance(&self) -> Vec<f64> {
+        self.stats
+            .iter()
+            .map(|stats| {
+                if stats.n < 2 {
+                    f64::INFINITY
+                } else {
+                    stats.variance() / stats.n as f64
+                }
+            })
+            .collect()
+    }
+
+    // A copy of the pixels of `tile`, with room around them for the samples
+    // that spill `margin` pixels beyond it.
+    fn tile(&self, tile: &Tile, margin: usize) -> TileBuffer {
+        let mut buffer = TileBuffer::new(tile, tile.grow(margin, self.width, self.height));
+        for y in tile.y0..tile.y1 {
+            let row = y * self.width;
+            let at = buffer.index(tile.x0, y);
+            let to = at..at + tile.width();
+            buffer.sums[to.clone()].copy_from_slice(&self.sums[row + tile.x0..row + tile.x1]);
+            buffer.weights[to].copy_from_slice(&self.weights[row + tile.x0..row + tile.x1]);
+            buffer
+                .stats
+                .extend_from_slice(&self.stats[row + tile.x0..row + tile.x1]);
+        }
+        buffer
+    }
+
+    // Copy the pixels of `tile` back, leaving what spilled over it.
+    fn set_tile(&mut self, tile: &Tile, buffer: &TileBuffer) {
+        for (i, y) in (tile.y0..tile.y1).enumerate() {
+            let row = y * self.width;
+            let at = buffer.index(tile.x0, y);
+            let from = at..at + tile.width();
+            self.sums[row + tile.x0..row + tile.x1].copy_from_slice(&buffer.sums[from.clone()]);
+            self.weights[row + tile.x0..row + tile.x1].copy_from_slice(&buffer.weights[from]);
+            self.stats[row + tile.x0..row + tile.x1]
+                .copy_from_slice(&buffer.stats[i * tile.width()..(i + 1) * tile.width()]);
+        }
+    }
+
+    // Add the samples of `tile` that landed in the pixels around it. Other
+    // tiles may be rendering those pixels from copies, so this has to wait
+    // until none are, and tiles are added in a fixed order so the sums don't
+    // depend on which finished first.
+    fn add_spill(&mut self, tile: &Tile, buffer: &TileBuffer) {
+        let region = buffer.region;
+        for y in region.y0..region.y1 {
+            for x in region.x0..region.x1 {
+                if !tile.contains(x, y) {
+                    let (i, from) = (y * self.width + x, buffer.index(x, y));
+                    self.sums[i] += buffer.sums[from];
+                    self.weights[i] += buffer.weights[from];
+                }
+            }
+        }
+    }
+
+    fn finished(&self, i: usize, settings: &RenderSettings) -> bool {
+        pixel_finished(&self.stats[i], settings)
+    }
+
+    // Add the samples of another render of the same image. Its samples must
+    // be independent of ours, i.e. rendered with a different seed, for the
+    // result to have less noise than either.
+    pub fn merge(&mut self, other: &Accumulator) {
+        assert_eq!((self.width, self.height), (other.width, other.height));
+        for (sum, other) in self.sums.iter_mut().zip(other.sums.iter()) {
+            *sum += *other;
+        }
+        for (weight, other) in self.weights.iter_mut().zip(other.weights.iter()) {
+            *weight += *other;
+        }
+        for (stats, other) in self.stats.iter_mut().zip(other.stats.iter()) {
+            stats.merge(other);
+        }
+    }
+
+    pub fn is_done(&self, settings: &RenderSettings) -> bool {
+        (0..self.stats.len()).all(|i| self.finished(i, settings))
+    }
+}
+
+// One tile's pixels, copied out of the accumulator to be rendered without
+// holding it, in `region`: the tile and the pixels around it its samples
+// can reach. Those start out empty and collect what spills over.
+#[derive(Debug, Clone)]
+struct TileBuffer {
+    region: Tile,
+    sums: Vec<Color>,
+    weights: Vec<f64>,
+    // Only for the tile's own pixels.
+    stats: Vec<PixelStats>,
+}
+
+impl TileBuffer {
+    fn new(tile: &Tile, region: Tile) -> TileBuffer {
+        TileBuffer {
+            region,
+            sums: vec![Color::default(); region.len()],
+            weights: vec![0.0; region.len()],
+            stats: Vec::with_capacity(tile.len()),
+        }
+    }
+
+    fn index(&self, x: usize, y: usize) -> usize {
+        (y - self.region.y0) * self.region.width() + x - self.region.x0
+    }
+
+    // Add `sample`, taken at (px, py), to every pixel in the region the
+    // filter reaches. The position is in pixels with y running up like the
+    // camera's v, so row y of the image covers [height - y - 1, height - y).
+    fn splat(&mut self, filter: &Filter, height: usize, px: f64, py: f64, sample: Color) {
+        let region = self.region;
+        let (x0, x1) = filter.reach(px);
+        let (h0, h1) = filter.reach(py);
+        let (h0, h1) = (
+            h0.max((height - region.y1) as i64),
+            h1.min((height - region.y0) as i64 - 1),
+        );
+        let (x0, x1) = (x0.max(region.x0 as i64), x1.min(region.x1 as i64 - 1));
+        for h in h0..=h1 {
+            let y = height - h as usize - 1;
+            for x in x0..=x1 {
+                let weight = filter.evaluate(x as f64 + 0.5 - px, h as f64 + 0.5 - py);
+                let i = self.index(x as usize, y);
+                self.sums[i] += sample * weight;
+                self.weights[i] += weight;
+            }
+        }
+    }
+
+    // The tile's own pixels as they are so far.
+    fn framebuffer(&self, tile: &Tile) -> Framebuffer {
+        let mut sums = Vec::with_capacity(tile.len());
+        let mut weights = Vec::with_capacity(tile.len());
+        for y in tile.y0..tile.y1 {
+            let at = self.index(tile.x0, y);
+            sums.extend_from_slice(&self.sums[at..at + tile.width()]);
+            weights.extend_from_slice(&self.weights[at..at + tile.width()]);
+        }
+        resolve(tile.width(), tile.height(), &sums, &weights, &self.stats)
+    }
+}
+
+// Tiles are counted over all passes.
+#[derive(Debug, Clone, Copy)]
+pub struct Progress {
+    pub tiles_done: usize,
+    pub tiles: usize,
+    pub pass: usize,
+    pub passes: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "render cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+type ProgressFn = Box<dyn Fn(Progress) + Send + Sync>;
+type PassFn = Box<dyn Fn(&Accumulator) + Send + Sync>;
+type TileFn = Box<dyn Fn(&Tile, &Framebuffer) + Send + Sync>;
+
+pub struct Renderer {
+    pub settings: RenderSettings,
+    progress: Option<ProgressFn>,
+    pass_done: Option<PassFn>,
+    tile_done: Option<TileFn>,
+    cancel: Arc<AtomicBool>,
+}
+
+impl Renderer {
+    pub fn new(settings: RenderSettings) -> Renderer {
+        Renderer {
+            settings,
+            progress: None,
+            pass_done: None,
+            tile_done: None,
+            cancel: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    // Called from the worker threads each time a tile is finished.
+    pub fn on_progress(mut self, f: impl Fn(Progress) + Send + Sync + 'static) -> Renderer {
+        self.progress = Some(Box::new(f));
+        self
+    }
+
+    // Called after every pass with everything accumulated so far, e.g. to
+    // write a checkpoint or a preview.
+    pub fn on_pass(mut self, f: impl Fn(&Accumulator) + Send + Sync + 'static) -> Renderer {
+        self.pass_done = Some(Box::new(f));
+        self
+    }
+
+    // Called from the worker threads with each tile as soon as it's finished,
+    // averaged over the samples so far, to show or save a partial image
+    // while the rest renders.
+    pub fn on_tile(mut self, f: impl Fn(&Tile, &Framebuffer) + Send + Sync + 'static) -> Renderer {
+        self.tile_done = Some(Box::new(f));
+        self
+    }
+
+    // Setting the flag makes `render` stop after the tiles in flight and
+    // return `Cancelled`. It can be set from another thread or a callback.
+    pub fn cancel_flag(&self) -> Arc<AtomicBool> {
+        self.cancel.clone()
+    }
+
+    pub fn render(&self, scene: &Scene) -> Result<Framebuffer, Cancelled> {
+        let seed = self.settings.seed.unwrap_or_else(rand::random);
+        let accumulator = Accumulator::new(self.settings.width, self.settings.height, seed);
+        self.resume(scene, accumulator)
+            .map(|accumulator| accumulator.framebuffer())
+    }
+
+    // Keep adding passes to `accumulator`, e.g. one read from a checkpoint,
+    // until every pixel has its samples. The samples of a pixel only depend
+    // on the seed, the pixel and the sample index, so a resumed render is the
+    // same as one that was never interrupted.
+    pub fn resume(
+        &self,
+        scene: &Scene,
+        accumulator: Accumulator,
+    ) -> Result<Accumulator, Cancelled> {
+        assert_eq!(
+            (accumulator.width, accumulator.height),
+            (self.settings.width, self.settings.height),
+            "Accumulator doesn't match the render size"
+        );
+        self.pool()
+            .install(|| self.render_passes(scene, accumulator))
+    }
+
+    // What the first `samples` camera rays of each pixel hit, see `aov`.
+    pub fn render_aovs(&self, scene: &Scene, seed: u64, samples: u64) -> Aovs {
+        self.pool()
+            .install(|| aov::render(scene, &self.settings, seed, samples))
+    }
+
+    // Trace the samples of a render again, `samples[i]` in pixel i, looking
+    // for ones that aren't finite or are negative. See `diagnostics`.
+    pub fn diagnose(&self, scene: &Scene, seed: u64, samples: &[u64]) -> Diagnostics {
+        self.pool()
+            .install(|| diagnostics::run(scene, &self.settings, seed, samples))
+    }
+
+    fn pool(&self) -> rayon::ThreadPool {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(self.settings.threads)
+            .build()
+            .expect("Unable to start render threads")
+    }
+
+    fn pass_samples(&self) -> u64 {
+        match self.settings.pass_samples {
+            0 => self.settings.samples_per_pixel,
+            n => n,
+        }
+        .max(1)
+    }
+
+    fn render_passes(
+        &self,
+        scene: &Scene,
+        mut accumulator: Accumulator,
+    ) -> Result<Accumulator, Cancelled> {
+        // Every pass takes each unfinished pixel `pass_samples` further, so
+        // the pixel with the fewest samples sets the number of passes.
+        let fewest = (0..accumulator.stats.len())
+            .filter(|&i| !accumulator.finished(i, &self.settings))
+            .map(|i| accumulator.stats[i].n)
+            .min();
+        let passes = match fewest {
+            Some(n) => {
+                let remaining = self.settings.samples_per_pixel - n;
+                remaining.div_ceil(self.pass_samples()) as usize
+            }
+            None => 0,
+        };
+        for pass in 0..passes {
+            if accumulator.is_done(&self.settings) {
+                break;
+            }
+            self.render_pass(scene, &mut accumulator, pass, passes)?;
+            if let Some(pass_done) = &self.pass_done {
+                pass_done(&accumulator);
+            }
+        }
+        Ok(accumulator)
+    }
+
+    fn render_pass(
+        &self,
+        scene: &Scene,
+        accumulator: &mut Accumulator,
+        pass: usize,
+        passes: usize,
+    ) -> Result<(), Cancelled> {
+        let settings = &self.settings;
+        let tiles = tiles(
+            accumulator.width,
+            accumulator.height,
+            settings.tile_size,
+            settings.tile_order,
+        );
+        let seed = accumulator.seed;
+        let margin = settings.filter.margin();
+        let accumulator = Mutex::new(accumulator);
+        let spills = Mutex::new(vec![None; tiles.len()]);
+        // Threads take the next tile as they finish one, so tiles are started
+        // in order however long each one takes.
+        let next = AtomicUsize::new(0);
+        let tiles_done = AtomicUsize::new(0);
+        rayon::scope(|s| {
+            for _ in 0..rayon::current_num_threads() {
+                s.spawn(|_| loop {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    if index >= tiles.len() || self.cancel.load(Ordering::Relaxed) {
+                        break;
+                    }
+                    let tile = &tiles[index];
+                    let mut buffer = accumulator.lock().unwrap().tile(tile, margin);
+                    self.render_tile(scene, tile, seed, &mut buffer);
+                    accumulator.lock().unwrap().set_tile(tile, &buffer);
+                    if let Some(tile_done) = &self.tile_done {
+                        tile_done(tile, &buffer.framebuffer(tile));
+                    }
+                    if margin > 0 {
+                        spills.lock().unwrap()[index] = Some(buffer);
+                    }
+                    let done = tiles_done.fetch_add(1, Ordering::Relaxed) + 1;
+                    if let Some(progress) = &self.progress {
+                        progress(Progress {
+                            tiles_done: pass * tiles.len() + done,
+                            tiles: passes * tiles.len(),
+                            pass,
+                            passes,
+                        });
+                    }
+                });
+            }
+        });
+        if self.cancel.load(Ordering::Relaxed) {
+            return Err(Cancelled);
+        }
+        let accumulator = accumulator.into_inner().unwrap();
+        for (tile, buffer) in tiles.iter().zip(spills.into_inner().unwrap()) {
+            if let Some(buffer) = buffer {
+                accumulator.add_spill(tile, &buffer);
+            }
+        }
+        Ok(())
+    }
+
+    fn render_tile(&self, scene: &Scene, tile: &Tile, seed: u64, buffer: &mut TileBuffer) {
+        let settings = &self.settings;
+        let mut sampler = settings.sampler.sampler(settings.samples_per_pixel, seed);
+        let camera = &scene.camera;
+        for i in 0..tile.len() {
+            let mut stats = buffer.stats[i];
+            if pixel_finished(&stats, settings) {
+                continue;
+            }
+            let x = tile.x0 + i % tile.width();
+            let y = tile.y0 + i / tile.width();
+            let end = (stats.n + self.pass_samples()).min(settings.samples_per_pixel);
+            for i in stats.n..end {
+                // Samples only depend on the pixel and index, so the image
+                // doesn't depend on which thread rendered what.
+                let (ray, px, py) = camera_sample(camera, settings, &mut sampler, x, y, i);
+                let sample = ray_color(
+                    ray,
+                    camera.background,
+                    &scene.hittables,
+                    &scene.lights,
+                    settings.max_depth,
+                    settings.rr_depth,
+                    &mut sampler,
+                );
+                let sample = match settings.clamp {
+                    Some(max) => clamp_luminance(sample, max),
+                    None => drop_non_finite(sample),
+                };
+                buffer.splat(&settings.filter, settings.height, px, py, sample);
+                stats.add(sample.luminance());
+                if let Some(adaptive) = &settings.adaptive {
+                    if adaptive.converged(&stats) {
+                        break;
+                    }
+                }
+            }
+            buffer.stats[i] = stats;
+        }
+    }
+
+    // Render the pixels of one tile until they all have their samples, for
+    // workers that are handed whole tiles.
+    fn finish_tile(&self, scene: &Scene, tile: &Tile, seed: u64, buffer: &mut TileBuffer) {
+        while !buffer
+            .stats
+            .iter()
+            .all(|stats| pixel_finished(stats, &self.settings))
+        {
+            self.render_tile(scene, tile, seed, buffer);
+        }
+    }
+}
+
+// Start sample `index` of pixel (x, y) and make its camera ray. Also returns
+// where on the image the sample is, in pixels with y running up like the
+// camera's v.
+fn camera_sample(
+    camera: &Camera,
+    settings: &RenderSettings,
+    sampler: &mut SamplerType,
+    x: usize,
+    y: usize,
+    index: u64,
+) -> (Ray, f64, f64) {
+    let (width, height) = (settings.width, settings.height);
+    sampler.start_pixel_sample(x, y, index);
+    let (du, dv) = sampler.get_2d();
+    let (px, py) = (x as f64 + du, (height - y - 1) as f64 + dv);
+    let u = px / (width - 1) as f64;
+    let v = py / (height - 1) as f64;
+    (camera.get_ray(u, v, sampler), px, py)
+}
+
+// Pixels with less filter weight than this are black.
+const MIN_WEIGHT: f64 = 1e-6;
+
+// Divide the weighted sums by the weights. Filters with negative lobes can
+// leave a pixel with next to no weight, or less than none, which is black
+// rather than the sums blown up.
+fn resolve(
+    width: usize,
+    height: usize,
+    sums: &[Color],
+    weights: &[f64],
+    stats: &[PixelStats],
+) -> Framebuffer {
+    Framebuffer {
+        width,
+        height,
+        pixels: sums
+            .iter()
+            .zip(weights.iter())
+            .map(|(&sum, &weight)| {
+                if weight <= MIN_WEIGHT {
+                    Color::default()
+                } else {
+                    sum * (1.0 / weight)
+                }
+            })
+            .collect(),
+        samples: stats.iter().map(|stats| stats.n).collect(),
+    }
+}
+
+// Samples that aren't finite would spoil their pixel, and through the filter
+// and the denoiser its neighbours, so they're dropped as the output formats
+// would; the diagnostics find where they come from.
+fn drop_non_finite(c: Color) -> Color {
+    match Problem::of(c) {
+        Some(Problem::Nan) | Some(Problem::Infinite) => Color::default(),
+        _ => c,
+    }
+}
+
+// Scale `c` down to luminance `max`, after dropping it if it isn't finite.
+fn clamp_luminance(c: Color, max: f64) -> Color {
+    let c = drop_non_finite(c);
+    let l = c.luminance();
+    if l > max {
+        c * (max / l)
+    } else {
+        c
+    }
+}
+
+fn pixel_finished(stats: &PixelStats, settings: &RenderSettings) -> bool {
+    stats.n >= settings.samples_per_pixel
+        || settings
+            .adaptive
+            .is_some_and(|adaptive| adaptive.converged(stats))
+}
+
+// Radiance arriving along `ray`, traced as an iterative path. Every diffuse
+// bounce samples a light directly and samples the BSDF for the next bounce;
+// both pick up emission and are combined with the power heuristic. After
+// `rr_depth` bounces paths are terminated by Russian roulette on their
+// throughput, `max_depth` is only a safety limit.
+pub fn ray_color(
+    ray: Ray,
+    background: Color,
+    world: &Hittables,
+    lights: &Hittables,
+    max_depth: u32,
+    rr_depth: u32,
+    sampler: &mut SamplerType,
+) -> Color {
+    trace(ray, background, world, lights, max_depth, rr_depth, sampler).0
+}
+
+// `ray_color`, also returning the first hit after which the path's radiance
+// or throughput stopped being finite and positive.
+fn trace(
+    ray: Ray,
+    background: Color,
+    world: &Hittables,
+    lights: &Hittables,
+    max_depth: u32,
+    rr_depth: u32,
+    sampler: &mut SamplerType,
+) -> (Color, Option<Culprit>) {
+    let mut culprit = None;
+    let mut radiance = color(0.0, 0.0, 0.0);
+    let mut throughput = color(1.0, 1.0, 1.0);
+    let mut ray = ray;
+    // Origin and BSDF pdf of the bounce that produced `ray`; None for camera
+    // rays and specular bounces, whose emission is counted in full.
+    let mut prev: Option<(Vec3, f64)> = None;
+
+    for depth in 0..max_depth {
+        let hit = match world.hit(&ray, 0.0001, f64::MAX, sampler.rng()) {
+            Some(hit) => hit,
+            None => {
+                radiance += throughput * background;
+                break;
+            }
+        };
+
+        let mut emitted = hit.mat.emitted(&ray, &hit, hit.u, hit.v, hit.point);
+        if let Some((origin, bsdf_pdf)) = prev {
+            if lights.length() > 0 && !is_black(emitted) {
+                let light_pdf = lights.pdf_value(origin, ray.direction, sampler.rng());
+                emitted = emitted * power_heuristic(bsdf_pdf, light_pdf);
+            }
+        }
+        radiance += throughput * emitted;
+        if culprit.is_none() && !is_valid(radiance) {
+            culprit = Some(Culprit::of(&hit));
+        }
+
+        // A dispersive material sends each wavelength its own way, so the
+        // path picks a hero wavelength there and carries only that from
+        // then on, weighted by the wavelength's color.
+        if ray.wavelength.is_none() && hit.mat.dispersive() {
+            let (wavelength, pdf) = spectrum::sample_visible(sampler.get_1d());
+            throughput = throughput * spectrum::to_rgb(wavelength) * (1.0 / pdf);
+            ray.wavelength = Some(wavelength);
+        }
+
+        let scatter = match hit.mat.scatter(&ray, &hit, sampler) {
+            Some(scatter) => scatter,
+            None => break,
+        };
+        match &scatter.pdf {
+            None => {
+                throughput = throughput * scatter.attenuation;
+                ray = scatter.ray;
+                prev = None;
+            }
+            Some(pdf) => {
+                // Light sample
+                if lights.length() > 0 {
+                    let light_ray = Ray {
+                        origin: hit.point,
+                        direction: lights.random(hit.point, sampler),
+                        time: ray.time,
+                        wavelength: ray.wavelength,
+                    };
+                    let light_pdf = lights.pdf_value(hit.point, light_ray.direction, sampler.rng());
+                    let scattering = hit.mat.scattering(&ray, &hit, &scatter, &light_ray);
+                    if light_pdf > 0.0 && !is_black(scattering) {
+                        if let Some(light_hit) =
+                            world.hit(&light_ray, 0.0001, f64::MAX, sampler.rng())
+                        {
+                            let light = light_hit.mat.emitted(
+                                &light_ray,
+                                &light_hit,
+                                light_hit.u,
+                                light_hit.v,
+                                light_hit.point,
+                            );
+                            let bsdf_pdf = pdf.value(light_ray.direction, sampler.rng());
+                            radiance += throughput
+                                * scattering
+                                * light
+                                * (power_heuristic(light_pdf, bsdf_pdf) / light_pdf);
+                        }
+                    }
+                }
+                if culprit.is_none() && !is_valid(radiance) {
+                    culprit = Some(Culprit::of(&hit));
+                }
+
+                // BSDF sample
+                let scattered = Ray {
+                    origin: hit.point,
+                    direction: pdf.generate(sampler),
+                    time: ray.time,
+                    wavelength: ray.wavelength,
+                };
+                let bsdf_pdf = pdf.value(scattered.direction, sampler.rng());
+                if bsdf_pdf <= 0.0 {
+                    break;
+                }
+                throughput = throughput
+                    * hit.mat.scattering(&ray, &hit, &scatter, &scattered)
+                    * (1.0 / bsdf_pdf);
+                prev = Some((hit.point, bsdf_pdf));
+                ray = scattered;
+            }
+        }
+        if culprit.is_none() && !(is_valid(radiance) && is_valid(throughput)) {
+            culprit = Some(Culprit::of(&hit));
+        }
+
+        // Russian roulette
+        if depth + 1 >= rr_depth {
+            let survive = throughput.r.max(throughput.g).max(throughput.b).min(0.95);
+            if survive <= 0.0 || sampler.get_1d() >= survive {
+                break;
+            }
+            throughput = throughput * (1.0 / survive);
+        }
+    }
+    (radiance, culprit)
+}
+
+fn is_valid(c: Color) -> bool {
+    c.r >= 0.0 && c.g >= 0.0 && c.b >= 0.0 && (c.r + c.g + c.b).is_finite()
+}
+
+fn is_black(c: Color) -> bool {
+    c.r <= 0.0 && c.g <= 0.0 && c.b <= 0.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::checkpoint::Checkpoint;
+    use crate::scenes::cornell_box::cornell_box;
+
+    fn settings() -> RenderSettings {
+        RenderSettings {
+            width: 8,
+            height: 6,
+            samples_per_pixel: 4,
+            seed: Some(1),
+            tile_size: 4,
+            threads: 2,
+            ..RenderSettings::default()
+        }
+    }
+
+    #[test]
+    fn clamping_drops_samples_that_arent_finite() {
+        let bright = clamp_luminance(color(10.0, 10.0, 10.0), 2.0);
+        assert!((bright.luminance() - 2.0).abs() < 1e-12);
+        let dim = color(0.1, 0.2, 0.3);
+        assert_eq!(clamp_luminance(dim, 2.0).luminance(), dim.luminance());
+        for &x in [f64::INFINITY, f64::NAN].iter() {
+            let c = clamp_luminance(color(x, 1.0, 1.0), 2.0);
+            assert_eq!((c.r, c.g, c.b), (0.0, 0.0, 0.0));
+        }
+    }
+
+    #[test]
+    fn renders_a_framebuffer() {
+        let scene = cornell_box(0.0, 1.0, 8.0 / 6.0);
+        let tiles = Arc::new(AtomicUsize::new(0));
+        let counter = tiles.clone();
+        let framebuffer = Renderer::new(settings())
+            .on_progress(move |_| {
+                counter.fetch_add(1, Ordering::Relaxed);
+            })
+            .render(&scene)
+            .unwrap();
+        assert_eq!(framebuffer.pixels.len(), 8 * 6);
+        assert!(framebuffer.samples.iter().all(|&n| n == 4));
+        assert_eq!(tiles.load(Ordering::Relaxed), 4);
+        assert!(framebuffer.pixels.iter().any(|c| c.r > 0.0));
+    }
+
+    #[test]
+    fn seeded_renders_are_reproducible() {
+        let scene = cornell_box(0.0, 1.0, 8.0 / 6.0);
+        let a = Renderer::new(settings()).render(&scene).unwrap();
+        let b = Renderer::new(RenderSettings {
+            threads: 3,
+            ..settings()
+        })
+        .render(&scene)
+        .unwrap();
+        assert_eq!(bits(&a), bits(&b));
+    }
+
+    #[test]
+    fn splatted_renders_are_reproducible() {
+        let scene = cornell_box(0.0, 1.0, 8.0 / 6.0);
+        let mitchell = RenderSettings {
+            filter: Filter::new(filter::FilterKind::Mitchell),
+            pass_samples: 2,
+            ..settings()
+        };
+        let a = Renderer::new(mitchell.clone()).render(&scene).unwrap();
+        let b = Renderer::new(RenderSettings {
+            threads: 3,
+            ..mitchell
+        })
+        .render(&scene)
+        .unwrap();
+        assert_eq!(bits(&a), bits(&b));
+        let boxed = Renderer::new(settings()).render(&scene).unwrap();
+        assert_ne!(bits(&a), bits(&boxed));
+    }
+
+    #[test]
+    fn adaptive_sampling_stops_early_on_flat_pixels() {
+        let scene = cornell_box(0.0, 1.0, 8.0 / 6.0);
+        let framebuffer = Renderer::new(RenderSettings {
+            samples_per_pixel: 256,
+            adaptive: Some(Adaptive {
+                threshold: 0.05,
+                min_samples: 8,
+            }),
+            ..settings()
+        })
+        .render(&scene)
+        .unwrap();
+        let samples = &framebuffer.samples;
+        assert!(samples
+            .iter()
+            .all(|&n| (8..=256).contains(&n) && n.is_multiple_of(8)));
+        assert!(framebuffer.mean_samples() < 256.0);
+    }
+
+    fn bits(f: &Framebuffer) -> Vec<u64> {
+        f.pixels
+            .iter()
+            .flat_map(|c| vec![c.r.to_bits(), c.g.to_bits(), c.b.to_bits()])
+            .collect()
+    }
+
+    #[test]
+    fn passes_match_a_single_pass() {
+        let scene = cornell_box(0.0, 1.0, 8.0 / 6.0);
+        let single = Renderer::new(RenderSettings {
+            pass_samples: 0,
+            ..settings()
+        })
+        .render(&scene)
+        .unwrap();
+        let passes = Arc::new(AtomicUsize::new(0));
+        let counter = passes.clone();
+        let progressive = Renderer::new(RenderSettings {
+            pass_samples: 1,
+            ..settings()
+        })
+        .on_pass(move |_| {
+            counter.fetch_add(1, Ordering::Relaxed);
+        })
+        .render(&scene)
+        .unwrap();
+        assert_eq!(passes.load(Ordering::Relaxed), 4);
+        assert_eq!(bits(&single), bits(&progressive));
+    }
+
+    #[test]
+    fn resume_from_checkpoint() {
+        let scene = cornell_box(0.0, 1.0, 8.0 / 6.0);
+        let full = Renderer::new(settings()).render(&scene).unwrap();
+
+        let half = RenderSettings {
+            samples_per_pixel: 2,
+            ..settings()
+        };
+        let accumulator = Accumulator::new(8, 6, 1);
+        let accumulator = Renderer::new(half.clone())
+            .resume(&scene, accumulator)
+            .unwrap();
+        let mut bytes = Vec::new();
+        Checkpoint {
+            scene: "cornell_box".to_string(),
+            settings: half,
+            accumulator,
+        }
+        .write_to(&mut bytes)
+        .unwrap();
+
+        let checkpoint = Checkpoint::read_from(&mut bytes.as_slice()).unwrap();
+        assert_eq!(checkpoint.scene, "cornell_box");
+        assert_eq!(checkpoint.settings.samples_per_pixel, 2);
+        let resumed = Renderer::new(settings())
+            .resume(&scene, checkpoint.accumulator)
+            .unwrap()
+            .framebuffer();
+        assert_eq!(bits(&full), bits(&resumed));
+    }
+
+    #[test]
+    fn tiles_are_flushed_and_the_image_matches() {
+        let scene = cornell_box(0.0, 1.0, 8.0 / 6.0);
+        let a = Renderer::new(settings()).render(&scene).unwrap();
+        let flushed = Arc::new(Mutex::new(Framebuffer::new(8, 6)));
+        let preview = flushed.clone();
+        let b = Renderer::new(RenderSettings {
+            tile_size: 3,
+            tile_order: TileOrder::Spiral,
+            ..settings()
+        })
+        .on_tile(move |tile, pixels| preview.lock().unwrap().set_tile(tile, pixels))
+        .render(&scene)
+        .unwrap();
+        assert_eq!(bits(&a), bits(&b));
+        assert_eq!(bits(&a), bits(&flushed.lock().unwrap()));
+    }
+
+    #[test]
+    fn cancel_from_progress_callback() {
+        let scene = cornell_box(0.0, 1.0, 8.0 / 6.0);
+        let renderer = Renderer::new(settings());
+        let cancel = renderer.cancel_flag();
+        let renderer = renderer.on_progress(move |_| cancel.store(true, Ordering::Relaxed));
+        assert_eq!(renderer.render(&scene).unwrap_err(), Cancelled);
+    }
+}
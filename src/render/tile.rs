@@ -0,0 +1,198 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+// A rectangle of pixels, `x1` and `y1` exclusive. Rows run top to bottom like
+// the framebuffer's.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Tile {
+    pub x0: usize,
+    pub y0: usize,
+    pub x1: usize,
+    pub y1: usize,
+}
+
+impl Tile {
+    pub fn width(&self) -> usize {
+        self.x1 - self.x0
+    }
+
+    pub fn height(&self) -> usize {
+        self.y1 - self.y0
+    }
+
+    pub fn len(&self) -> usize {
+        self.width() * self.height()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn contains(&self, x: usize, y: usize) -> bool {
+        (self.x0..self.x1).contains(&x) && (self.y0..self.y1).contains(&y)
+    }
+
+    // The tile with `margin` more pixels on every side, clipped to a width x
+    // height image.
+    pub fn grow(&self, margin: usize, width: usize, height: usize) -> Tile {
+        Tile {
+            x0: self.x0.saturating_sub(margin),
+            y0: self.y0.saturating_sub(margin),
+            x1: (self.x1 + margin).min(width),
+            y1: (self.y1 + margin).min(height),
+        }
+    }
+}
+
+// The order tiles are handed to the render threads in. Spiral starts in the
+// middle of the image, where the subject usually is, and Hilbert keeps
+// consecutive tiles next to each other so they share cached geometry.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TileOrder {
+    Scanline,
+    Spiral,
+    #[default]
+    Hilbert,
+}
+
+impl FromStr for TileOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<TileOrder, String> {
+        match s {
+            "scanline" => Ok(TileOrder::Scanline),
+            "spiral" => Ok(TileOrder::Spiral),
+            "hilbert" => Ok(TileOrder::Hilbert),
+            _ => Err(format!(
+                "unknown tile order {}, expected scanline, spiral or hilbert",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for TileOrder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            TileOrder::Scanline => "scanline",
+            TileOrder::Spiral => "spiral",
+            TileOrder::Hilbert => "hilbert",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// Cover a width x height image with tiles of `size` pixels square, smaller at
+// the right and bottom edges, in the given order.
+pub fn tiles(width: usize, height: usize, size: usize, order: TileOrder) -> Vec<Tile> {
+    let size = size.max(1);
+    let nx = width.div_ceil(size);
+    let ny = height.div_ceil(size);
+    let cells = match order {
+        TileOrder::Scanline => (0..ny)
+            .flat_map(|ty| (0..nx).map(move |tx| (tx, ty)))
+            .collect(),
+        TileOrder::Spiral => spiral(nx, ny),
+        TileOrder::Hilbert => hilbert(nx, ny),
+    };
+    cells
+        .into_iter()
+        .map(|(tx, ty)| Tile {
+            x0: tx * size,
+            y0: ty * size,
+            x1: ((tx + 1) * size).min(width),
+            y1: ((ty + 1) * size).min(height),
+        })
+        .collect()
+}
+
+// Walk outwards from the middle cell in a square spiral, right, down, left,
+// up with legs of 1, 1, 2, 2, 3, 3... keeping the cells inside the grid.
+fn spiral(nx: usize, ny: usize) -> Vec<(usize, usize)> {
+    let mut cells = Vec::with_capacity(nx * ny);
+    let (mut x, mut y) = ((nx as i64 - 1) / 2, (ny as i64 - 1) / 2);
+    let directions = [(1, 0), (0, 1), (-1, 0), (0, -1)];
+    let mut leg = 1;
+    let mut turn = 0;
+    while cells.len() < nx * ny {
+        for _ in 0..2 {
+            let (dx, dy) = directions[turn % 4];
+            for _ in 0..leg {
+                if x >= 0 && y >= 0 && (x as usize) < nx && (y as usize) < ny {
+                    cells.push((x as usize, y as usize));
+                }
+                x += dx;
+                y += dy;
+            }
+            turn += 1;
+        }
+        leg += 1;
+    }
+    cells
+}
+
+// Cells along a Hilbert curve over the smallest power of two square that
+// covers the grid, skipping the ones outside it.
+fn hilbert(nx: usize, ny: usize) -> Vec<(usize, usize)> {
+    let n = nx.max(ny).next_power_of_two();
+    (0..n * n)
+        .map(|d| hilbert_cell(n, d))
+        .filter(|&(x, y)| x < nx && y < ny)
+        .collect()
+}
+
+// Position `d` along the Hilbert curve filling an n x n square, n a power of two.
+fn hilbert_cell(n: usize, d: usize) -> (usize, usize) {
+    let (mut x, mut y) = (0, 0);
+    let mut t = d;
+    let mut s = 1;
+    while s < n {
+        let rx = 1 & (t / 2);
+        let ry = 1 & (t ^ rx);
+        if ry == 0 {
+            if rx == 1 {
+                x = s - 1 - x;
+                y = s - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        x += s * rx;
+        y += s * ry;
+        t /= 4;
+        s *= 2;
+    }
+    (x, y)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tiles_cover_every_pixel_once() {
+        for order in [TileOrder::Scanline, TileOrder::Spiral, TileOrder::Hilbert].iter() {
+            let (width, height) = (37, 21);
+            let mut covered = vec![0; width * height];
+            for tile in tiles(width, height, 8, *order) {
+                for y in tile.y0..tile.y1 {
+                    for x in tile.x0..tile.x1 {
+                        covered[y * width + x] += 1;
+                    }
+                }
+            }
+            assert!(covered.iter().all(|&n| n == 1), "{}", order);
+        }
+    }
+
+    #[test]
+    fn hilbert_steps_between_neighbours() {
+        let cells = hilbert(8, 8);
+        for pair in cells.windows(2) {
+            let (a, b) = (pair[0], pair[1]);
+            let distance = (a.0 as i64 - b.0 as i64).abs() + (a.1 as i64 - b.1 as i64).abs();
+            assert_eq!(distance, 1);
+        }
+    }
+}
@@ -0,0 +1,86 @@
+use crate::sampler::{mix, sample_seed, stratified::permutation_element, Sampler, SamplerType};
+use rand::prelude::*;
+use rand::rngs::SmallRng;
+
+const PRIMES: [u64; 32] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131,
+];
+
+// The Halton sequence, one prime base per dimension, Owen-scrambled for each
+// pixel. Scrambling every digit, leading zeros included, matters for the
+// larger bases: their first few points sit close to 0 and a plain rotation
+// would leave them clustered. Past the last prime the points are too
+// correlated to be useful and dimensions fall back to random values.
+#[derive(Debug, Clone)]
+pub struct HaltonSampler {
+    pub seed: u64,
+    pub pixel: u64,
+    pub index: u64,
+    pub dimension: usize,
+    pub rng: SmallRng,
+}
+
+impl HaltonSampler {
+    pub fn new(seed: u64) -> SamplerType {
+        SamplerType::from(HaltonSampler {
+            seed,
+            pixel: 0,
+            index: 0,
+            dimension: 0,
+            rng: SmallRng::seed_from_u64(seed),
+        })
+    }
+
+    fn next(&mut self) -> f64 {
+        let dimension = self.dimension;
+        self.dimension += 1;
+        if dimension >= PRIMES.len() {
+            return self.rng.gen();
+        }
+        let hash = mix(self.pixel, dimension as u64);
+        scrambled_radical_inverse(PRIMES[dimension], self.index, hash)
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn start_pixel_sample(&mut self, x: usize, y: usize, index: u64) {
+        self.pixel = sample_seed(self.seed, x, y, 0);
+        self.index = index;
+        self.dimension = 0;
+        self.rng = SmallRng::seed_from_u64(sample_seed(self.seed, x, y, index));
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        self.next()
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        let u = self.next();
+        (u, self.next())
+    }
+
+    fn rng(&mut self) -> &mut SmallRng {
+        &mut self.rng
+    }
+}
+
+// `index` with its digits in `base` mirrored around the radix point. Every
+// digit, down to the precision of an f64, is permuted by a hash of `hash`
+// and the digits before it.
+pub fn scrambled_radical_inverse(base: u64, index: u64, hash: u64) -> f64 {
+    let inv_base = 1.0 / base as f64;
+    let mut inv_base_n = 1.0;
+    let mut reversed = 0u64;
+    let mut index = index;
+    while 1.0 - inv_base_n < 1.0 {
+        let next = index / base;
+        let digit = (index - next * base) as u32;
+        let p = mix(hash, reversed) as u32;
+        let digit = permutation_element(digit, base as u32, p);
+        reversed = reversed * base + digit as u64;
+        inv_base_n *= inv_base;
+        index = next;
+    }
+    (reversed as f64 * inv_base_n).min(1.0 - f64::EPSILON)
+}
@@ -0,0 +1,37 @@
+use crate::sampler::{sample_seed, Sampler, SamplerType};
+use rand::prelude::*;
+use rand::rngs::SmallRng;
+
+// Uniform random values for every dimension.
+#[derive(Debug, Clone)]
+pub struct IndependentSampler {
+    pub seed: u64,
+    pub rng: SmallRng,
+}
+
+impl IndependentSampler {
+    pub fn new(seed: u64) -> SamplerType {
+        SamplerType::from(IndependentSampler {
+            seed,
+            rng: SmallRng::seed_from_u64(seed),
+        })
+    }
+}
+
+impl Sampler for IndependentSampler {
+    fn start_pixel_sample(&mut self, x: usize, y: usize, index: u64) {
+        self.rng = SmallRng::seed_from_u64(sample_seed(self.seed, x, y, index));
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        self.rng.gen()
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        (self.rng.gen(), self.rng.gen())
+    }
+
+    fn rng(&mut self) -> &mut SmallRng {
+        &mut self.rng
+    }
+}
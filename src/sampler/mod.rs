@@ -0,0 +1,169 @@
+use crate::sampler::{
+    halton::HaltonSampler, independent::IndependentSampler, sobol::SobolSampler,
+    stratified::StratifiedSampler,
+};
+use enum_dispatch::enum_dispatch;
+use rand::rngs::SmallRng;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+pub mod halton;
+pub mod independent;
+pub mod sobol;
+pub mod stratified;
+
+// Source of the sample values used to trace one path. Each sample of a pixel
+// asks for a sequence of 1D and 2D values, one dimension after another: the
+// pixel position, the lens, the time, then the light and BSDF choices of
+// every bounce. Values come from the sampler's sequence for that dimension,
+// so the samples of a pixel are well distributed in every one of them.
+#[enum_dispatch]
+pub trait Sampler {
+    // Start sample `index` of pixel (x, y) and go back to the first dimension.
+    fn start_pixel_sample(&mut self, x: usize, y: usize, index: u64);
+    fn get_1d(&mut self) -> f64;
+    fn get_2d(&mut self) -> (f64, f64);
+    // Plain random numbers for the sample, e.g. for media and intersection
+    // code that has no use for well distributed values.
+    fn rng(&mut self) -> &mut SmallRng;
+}
+
+#[enum_dispatch(Sampler)]
+#[derive(Debug, Clone)]
+pub enum SamplerType {
+    IndependentSampler,
+    StratifiedSampler,
+    HaltonSampler,
+    SobolSampler,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SamplerKind {
+    #[default]
+    Independent,
+    Stratified,
+    Halton,
+    Sobol,
+}
+
+impl SamplerKind {
+    pub fn sampler(self, samples_per_pixel: u64, seed: u64) -> SamplerType {
+        match self {
+            SamplerKind::Independent => IndependentSampler::new(seed),
+            SamplerKind::Stratified => StratifiedSampler::new(samples_per_pixel, seed),
+            SamplerKind::Halton => HaltonSampler::new(seed),
+            SamplerKind::Sobol => SobolSampler::new(seed),
+        }
+    }
+}
+
+impl FromStr for SamplerKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<SamplerKind, String> {
+        match s {
+            "independent" => Ok(SamplerKind::Independent),
+            "stratified" => Ok(SamplerKind::Stratified),
+            "halton" => Ok(SamplerKind::Halton),
+            "sobol" => Ok(SamplerKind::Sobol),
+            _ => Err(format!(
+                "unknown sampler {}, expected independent, stratified, halton or sobol",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for SamplerKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            SamplerKind::Independent => "independent",
+            SamplerKind::Stratified => "stratified",
+            SamplerKind::Halton => "halton",
+            SamplerKind::Sobol => "sobol",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// SplitMix64 finalizer of `a` combined with `b`.
+pub fn mix(a: u64, b: u64) -> u64 {
+    let mut z = a.wrapping_add(b.wrapping_add(1).wrapping_mul(0x9e3779b97f4a7c15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+// Seed of the random numbers for one sample of one pixel. It only depends on
+// its arguments, so samples can be taken in any order, on any thread.
+pub fn sample_seed(seed: u64, x: usize, y: usize, index: u64) -> u64 {
+    mix(mix(mix(seed, x as u64), y as u64), index)
+}
+
+// Maps 32 random bits to [0, 1).
+pub fn to_unit(bits: u32) -> f64 {
+    bits as f64 * (1.0 / 4294967296.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KINDS: [SamplerKind; 4] = [
+        SamplerKind::Independent,
+        SamplerKind::Stratified,
+        SamplerKind::Halton,
+        SamplerKind::Sobol,
+    ];
+
+    #[test]
+    fn samples_are_in_the_unit_square() {
+        for kind in KINDS.iter() {
+            let mut sampler = kind.sampler(16, 7);
+            for i in 0..16 {
+                sampler.start_pixel_sample(3, 5, i);
+                for _ in 0..40 {
+                    let u = sampler.get_1d();
+                    let (v, w) = sampler.get_2d();
+                    assert!((0.0..1.0).contains(&u), "{} gave {}", kind, u);
+                    assert!((0.0..1.0).contains(&v) && (0.0..1.0).contains(&w));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn one_sample_per_stratum() {
+        // Stratified and Sobol samples cover 16 strata of every dimension
+        // exactly once over 16 samples.
+        for kind in [SamplerKind::Stratified, SamplerKind::Sobol].iter() {
+            let mut sampler = kind.sampler(16, 7);
+            let mut strata = vec![[0; 16]; 10];
+            for i in 0..16 {
+                sampler.start_pixel_sample(1, 2, i);
+                for count in strata.iter_mut() {
+                    count[(sampler.get_1d() * 16.0) as usize] += 1;
+                }
+            }
+            for (d, count) in strata.iter().enumerate() {
+                assert!(count.iter().all(|&n| n == 1), "{} dimension {}", kind, d);
+            }
+        }
+    }
+
+    #[test]
+    fn samples_are_reproducible() {
+        for kind in KINDS.iter() {
+            let mut a = kind.sampler(8, 1);
+            let mut b = kind.sampler(8, 1);
+            a.start_pixel_sample(4, 4, 3);
+            b.start_pixel_sample(4, 4, 3);
+            for _ in 0..10 {
+                assert_eq!(a.get_2d(), b.get_2d());
+                assert_eq!(a.get_1d(), b.get_1d());
+            }
+        }
+    }
+}
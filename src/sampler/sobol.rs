@@ -0,0 +1,100 @@
+use crate::sampler::{mix, sample_seed, to_unit, Sampler, SamplerType};
+use rand::prelude::*;
+use rand::rngs::SmallRng;
+
+// Owen-scrambled Sobol points, padded to any number of dimensions as in
+// Burley, "Practical Hash-based Owen Scrambling". Every 1D or 2D request
+// draws from the first one or two Sobol dimensions with its own shuffle of
+// the sample index and its own scramble, so there is no limit on dimensions.
+#[derive(Debug, Clone)]
+pub struct SobolSampler {
+    pub seed: u64,
+    pub pixel: u64,
+    pub index: u32,
+    pub dimension: u64,
+    pub rng: SmallRng,
+}
+
+impl SobolSampler {
+    pub fn new(seed: u64) -> SamplerType {
+        SamplerType::from(SobolSampler {
+            seed,
+            pixel: 0,
+            index: 0,
+            dimension: 0,
+            rng: SmallRng::seed_from_u64(seed),
+        })
+    }
+
+    // Shuffled sample index and the scramble seed for the next dimension.
+    fn next(&mut self) -> (u32, u32) {
+        let seed = mix(self.pixel, self.dimension) as u32;
+        self.dimension += 1;
+        (nested_uniform_scramble(self.index, seed), seed)
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn start_pixel_sample(&mut self, x: usize, y: usize, index: u64) {
+        self.pixel = sample_seed(self.seed, x, y, 0);
+        self.index = index as u32;
+        self.dimension = 0;
+        self.rng = SmallRng::seed_from_u64(sample_seed(self.seed, x, y, index));
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let (index, seed) = self.next();
+        let x = index.reverse_bits();
+        to_unit(nested_uniform_scramble(x, hash_combine(seed, 0)))
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        let (index, seed) = self.next();
+        let x = index.reverse_bits();
+        let y = sobol_dimension_1(index);
+        (
+            to_unit(nested_uniform_scramble(x, hash_combine(seed, 0))),
+            to_unit(nested_uniform_scramble(y, hash_combine(seed, 1))),
+        )
+    }
+
+    fn rng(&mut self) -> &mut SmallRng {
+        &mut self.rng
+    }
+}
+
+// Second Sobol dimension, whose direction numbers follow from the primitive
+// polynomial x + 1: each is the previous one xored with itself shifted right.
+fn sobol_dimension_1(index: u32) -> u32 {
+    let mut v = 1u32 << 31;
+    let mut x = 0;
+    let mut index = index;
+    while index != 0 {
+        if index & 1 != 0 {
+            x ^= v;
+        }
+        v ^= v >> 1;
+        index >>= 1;
+    }
+    x
+}
+
+fn laine_karras_permutation(x: u32, seed: u32) -> u32 {
+    let mut x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+    x
+}
+
+pub fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+}
+
+fn hash_combine(seed: u32, v: u32) -> u32 {
+    seed ^ (v
+        .wrapping_add(seed << 6)
+        .wrapping_add(seed >> 2)
+        .wrapping_add(0x9e3779b9))
+}
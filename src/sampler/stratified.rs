@@ -0,0 +1,103 @@
+use crate::sampler::{mix, sample_seed, Sampler, SamplerType};
+use rand::prelude::*;
+use rand::rngs::SmallRng;
+
+// Jittered stratified samples. Each dimension splits the unit interval (or
+// square) into one stratum per sample and the samples of a pixel visit the
+// strata in their own pseudo-random order, so dimensions stay uncorrelated.
+#[derive(Debug, Clone)]
+pub struct StratifiedSampler {
+    pub samples_per_pixel: u32,
+    pub seed: u64,
+    pub pixel: u64,
+    pub index: u32,
+    pub dimension: u64,
+    pub rng: SmallRng,
+}
+
+impl StratifiedSampler {
+    pub fn new(samples_per_pixel: u64, seed: u64) -> SamplerType {
+        SamplerType::from(StratifiedSampler {
+            samples_per_pixel: samples_per_pixel.clamp(1, u32::MAX as u64) as u32,
+            seed,
+            pixel: 0,
+            index: 0,
+            dimension: 0,
+            rng: SmallRng::seed_from_u64(seed),
+        })
+    }
+
+    // Stratum of the current sample among `n` in the current dimension.
+    fn stratum(&mut self, n: u32) -> u32 {
+        let p = mix(self.pixel, self.dimension) as u32;
+        self.dimension += 1;
+        permutation_element(self.index % n, n, p)
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_pixel_sample(&mut self, x: usize, y: usize, index: u64) {
+        self.pixel = sample_seed(self.seed, x, y, 0);
+        self.index = index as u32;
+        self.dimension = 0;
+        self.rng = SmallRng::seed_from_u64(sample_seed(self.seed, x, y, index));
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let n = self.samples_per_pixel;
+        let stratum = self.stratum(n);
+        (stratum as f64 + self.rng.gen::<f64>()) / n as f64
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        // A grid of at least one cell per sample, as square as possible.
+        let nx = (self.samples_per_pixel as f64).sqrt().ceil() as u32;
+        let ny = self.samples_per_pixel.div_ceil(nx);
+        let stratum = self.stratum(nx * ny);
+        let (x, y) = (stratum % nx, stratum / nx);
+        (
+            (x as f64 + self.rng.gen::<f64>()) / nx as f64,
+            (y as f64 + self.rng.gen::<f64>()) / ny as f64,
+        )
+    }
+
+    fn rng(&mut self) -> &mut SmallRng {
+        &mut self.rng
+    }
+}
+
+// Element `i` of a random permutation of 0..l chosen by `p`, without storing
+// the permutation (Kensler, "Correlated Multi-Jittered Sampling").
+pub fn permutation_element(i: u32, l: u32, p: u32) -> u32 {
+    let mut w = l.wrapping_sub(1);
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    let mut i = i;
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < l {
+            break;
+        }
+    }
+    ((i as u64 + p as u64) % l as u64) as u32
+}
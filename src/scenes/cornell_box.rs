@@ -16,7 +16,7 @@ use crate::material::{
 };
 use crate::scenes::Scene;
 use crate::texture::solidcolor::SolidColor;
-use crate::vec::{vec3, Vec3};
+use crate::vec::vec3;
 
 use std::sync::Arc;
 
@@ -62,7 +62,14 @@ pub fn cornell_box(t0: f64, t1: f64, aspect_ratio: f64) -> Scene {
     let box2 = RotateY::new(Arc::new(box2), -18.0);
     let box2 = Translate::new(Arc::new(box2), vec3(130.0, 0.0, 65.0));
 
-    let light = XzRect::new(213.0, 343.0, 227.0, 332.0, 554.0, light.clone());
+    let light = FlipFace::new(XzRect::new(
+        213.0,
+        343.0,
+        227.0,
+        332.0,
+        554.0,
+        light.clone(),
+    ));
 
     let mut world = HittableList {
         hittables: Vec::new(),
@@ -75,12 +82,22 @@ pub fn cornell_box(t0: f64, t1: f64, aspect_ratio: f64) -> Scene {
     world.add(box1);
     world.add(box2);
     world.add(light);
+
+    let mut lights = HittableList {
+        hittables: Vec::new(),
+    };
+    lights.add(XzRect::new(
+        213.0,
+        343.0,
+        227.0,
+        332.0,
+        554.0,
+        Arc::new(MaterialType::default()),
+    ));
     return Scene {
         camera: camera,
         hittables: Hittables::from(BvhNode::new(world, t0, t1)),
-        lights: Hittables::from(HittableList {
-            hittables: Vec::new(),
-        }),
+        lights: Hittables::from(lights),
     };
 }
 
@@ -153,11 +170,6 @@ pub fn cornell_box_sphere(t0: f64, t1: f64, aspect_ratio: f64) -> Scene {
         554.0,
         Arc::new(MaterialType::default()),
     ));
-    lights.add(Sphere::new(
-        Vec3::new(190.0, 90.0, 190.0),
-        90.0,
-        Arc::new(MaterialType::default()),
-    ));
     return Scene {
         camera: camera,
         hittables: Hittables::from(BvhNode::new(world, t0, t1)),
@@ -17,7 +17,7 @@ use rand::rngs::SmallRng;
 const GRID_SIZE: i32 = 11;
 
 #[allow(dead_code)]
-pub fn random_world_original(t0: f64, t1: f64, aspect_ratio: f64) -> Scene {
+pub fn random_world_original(t0: f64, t1: f64, aspect_ratio: f64, seed: u64) -> Scene {
     let camera = Camera::new(CameraConfig {
         lookfrom: vec3(13.0, 2.0, 3.0),
         lookat: vec3(0.0, 0.0, 0.0),
@@ -30,7 +30,7 @@ pub fn random_world_original(t0: f64, t1: f64, aspect_ratio: f64) -> Scene {
         time1: t1,
         background: color(0.7, 0.8, 1.0),
     });
-    let mut rng = SmallRng::from_entropy();
+    let mut rng = SmallRng::seed_from_u64(seed);
     let mut world = HittableList {
         hittables: Vec::new(),
     };
@@ -89,7 +89,7 @@ pub fn random_world_original(t0: f64, t1: f64, aspect_ratio: f64) -> Scene {
 }
 
 #[allow(dead_code)]
-pub fn random_world_checkered(t0: f64, t1: f64, aspect_ratio: f64) -> Scene {
+pub fn random_world_checkered(t0: f64, t1: f64, aspect_ratio: f64, seed: u64) -> Scene {
     let camera = Camera::new(CameraConfig {
         lookfrom: vec3(13.0, 2.0, 3.0),
         lookat: vec3(0.0, 0.0, 0.0),
@@ -102,7 +102,7 @@ pub fn random_world_checkered(t0: f64, t1: f64, aspect_ratio: f64) -> Scene {
         time1: t1,
         background: color(0.7, 0.8, 1.0),
     });
-    let mut rng = SmallRng::from_entropy();
+    let mut rng = SmallRng::seed_from_u64(seed);
     let mut world = HittableList {
         hittables: Vec::new(),
     };
@@ -164,7 +164,7 @@ pub fn random_world_checkered(t0: f64, t1: f64, aspect_ratio: f64) -> Scene {
 }
 
 #[allow(dead_code)]
-pub fn random_world(t0: f64, t1: f64, aspect_ratio: f64) -> Scene {
+pub fn random_world(t0: f64, t1: f64, aspect_ratio: f64, seed: u64) -> Scene {
     let camera = Camera::new(CameraConfig {
         lookfrom: vec3(13.0, 2.0, 3.0),
         lookat: vec3(0.0, 0.0, 0.0),
@@ -177,7 +177,7 @@ pub fn random_world(t0: f64, t1: f64, aspect_ratio: f64) -> Scene {
         time1: t1,
         background: color(0.7, 0.8, 1.0),
     });
-    let mut rng = SmallRng::from_entropy();
+    let mut rng = SmallRng::seed_from_u64(seed);
     let mut world = HittableList {
         hittables: Vec::new(),
     };
@@ -239,7 +239,7 @@ pub fn random_world(t0: f64, t1: f64, aspect_ratio: f64) -> Scene {
 }
 
 #[allow(dead_code)]
-pub fn random_world_earth(t0: f64, t1: f64, aspect_ratio: f64) -> Scene {
+pub fn random_world_earth(t0: f64, t1: f64, aspect_ratio: f64, seed: u64) -> Scene {
     let camera = Camera::new(CameraConfig {
         lookfrom: vec3(13.0, 2.0, 3.0),
         lookat: vec3(0.0, 0.0, 0.0),
@@ -252,7 +252,7 @@ pub fn random_world_earth(t0: f64, t1: f64, aspect_ratio: f64) -> Scene {
         time1: t1,
         background: color(0.7, 0.8, 1.0),
     });
-    let mut rng = SmallRng::from_entropy();
+    let mut rng = SmallRng::seed_from_u64(seed);
     let mut world = HittableList {
         hittables: Vec::new(),
     };
@@ -3,11 +3,13 @@ use crate::color::color;
 use crate::hittable::{
     bvh::BvhNode, hittable_list::HittableList, rect::XyRect, sphere::Sphere, Hittables,
 };
-use crate::material::{diffuse::Diffuse, lambertian::Lambertian};
+use crate::material::{diffuse::Diffuse, lambertian::Lambertian, MaterialType};
 use crate::scenes::Scene;
 use crate::texture::{marble::MarbleTexture, solidcolor::SolidColor};
 use crate::vec::vec3;
 
+use std::sync::Arc;
+
 #[allow(dead_code)]
 pub fn simple_light(t0: f64, t1: f64, aspect_ratio: f64) -> Scene {
     let camera = Camera::new(CameraConfig {
@@ -36,11 +38,26 @@ pub fn simple_light(t0: f64, t1: f64, aspect_ratio: f64) -> Scene {
     world.add(sphere2);
     world.add(sphere3);
     world.add(rect);
+
+    let mut lights = HittableList {
+        hittables: Vec::new(),
+    };
+    lights.add(XyRect::new(
+        5.0,
+        7.0,
+        1.0,
+        3.0,
+        -2.0,
+        Arc::new(MaterialType::default()),
+    ));
+    lights.add(Sphere::new(
+        vec3(0.0, 7.0, 0.0),
+        2.0,
+        Arc::new(MaterialType::default()),
+    ));
     return Scene {
         camera: camera,
         hittables: Hittables::from(BvhNode::new(world, t0, t1)),
-        lights: Hittables::from(HittableList {
-            hittables: Vec::new(),
-        }),
+        lights: Hittables::from(lights),
     };
 }
@@ -0,0 +1,99 @@
+use crate::color::{color, Color};
+
+// Single wavelengths of light, for materials that bend each one differently.
+// Wavelengths are in nanometres, over the range the eye is sensitive to.
+pub const MIN_WAVELENGTH: f64 = 360.0;
+pub const MAX_WAVELENGTH: f64 = 830.0;
+
+// The Fraunhofer d line, where a material's index of refraction is usually
+// quoted.
+pub const D_LINE: f64 = 587.6;
+
+// A wavelength from a uniform number, with its density. Wavelengths the eye
+// is most sensitive to are more likely (Radziszewski et al., "An Improved
+// Technique for Full Spectral Rendering", 2009).
+pub fn sample_visible(u: f64) -> (f64, f64) {
+    let wavelength = 538.0 - 138.888889 * (0.85691062 - 1.82750197 * u).atanh();
+    (wavelength, visible_pdf(wavelength))
+}
+
+pub fn visible_pdf(wavelength: f64) -> f64 {
+    if !(MIN_WAVELENGTH..=MAX_WAVELENGTH).contains(&wavelength) {
+        return 0.0;
+    }
+    0.0039398042 / (0.0072 * (wavelength - 538.0)).cosh().powi(2)
+}
+
+// The CIE 1931 color matching functions, from the multi-lobe fit of Wyman
+// et al., "Simple Analytic Approximations to the CIE XYZ Color Matching
+// Functions", 2013.
+fn xyz(wavelength: f64) -> (f64, f64, f64) {
+    let g = |mu: f64, below: f64, above: f64| {
+        let sigma = if wavelength < mu { below } else { above };
+        let t = (wavelength - mu) / sigma;
+        (-0.5 * t * t).exp()
+    };
+    let x =
+        1.056 * g(599.8, 37.9, 31.0) + 0.362 * g(442.0, 16.0, 26.7) - 0.065 * g(501.1, 20.4, 26.2);
+    let y = 0.821 * g(568.8, 46.9, 40.5) + 0.286 * g(530.9, 16.3, 31.1);
+    let z = 1.217 * g(437.0, 11.8, 36.0) + 0.681 * g(459.0, 26.0, 13.8);
+    (x, y, z)
+}
+
+// Integrals of the clamped linear sRGB response below over the range, so
+// that light of every wavelength in equal measure adds up to white.
+const RGB_INTEGRAL: (f64, f64, f64) = (176.182670, 115.392178, 109.348235);
+
+// The linear sRGB color of a single wavelength, per nanometre. Pure
+// spectral colors are outside sRGB; the negative parts are cut off, which
+// leaves them a little less saturated.
+pub fn to_rgb(wavelength: f64) -> Color {
+    let (x, y, z) = xyz(wavelength);
+    let r = 3.2406 * x - 1.5372 * y - 0.4986 * z;
+    let g = -0.9689 * x + 1.8758 * y + 0.0415 * z;
+    let b = 0.0557 * x - 0.2040 * y + 1.0570 * z;
+    let (nr, ng, nb) = RGB_INTEGRAL;
+    color(r.max(0.0) / nr, g.max(0.0) / ng, b.max(0.0) / nb)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampler::mix;
+
+    #[test]
+    fn sampled_wavelengths_average_to_white() {
+        let n = 100_000;
+        let mut sum = Color::default();
+        for i in 0..n {
+            let u = (i as f64 + 0.5) / n as f64;
+            let (wavelength, pdf) = sample_visible(u);
+            assert!((MIN_WAVELENGTH..=MAX_WAVELENGTH).contains(&wavelength));
+            sum += to_rgb(wavelength) * (1.0 / pdf);
+        }
+        let white = sum * (1.0 / n as f64);
+        for &c in [white.r, white.g, white.b].iter() {
+            assert!((c - 1.0).abs() < 0.01, "{:?}", white);
+        }
+        // And the density integrates to one.
+        let total: f64 = (0..n)
+            .map(|i| {
+                let u = (mix(i, 1) >> 11) as f64 / (1u64 << 53) as f64;
+                visible_pdf(MIN_WAVELENGTH + u * (MAX_WAVELENGTH - MIN_WAVELENGTH))
+            })
+            .sum::<f64>()
+            * (MAX_WAVELENGTH - MIN_WAVELENGTH)
+            / n as f64;
+        assert!((total - 1.0).abs() < 0.01, "{}", total);
+    }
+
+    #[test]
+    fn wavelengths_have_their_colors() {
+        let blue = to_rgb(450.0);
+        assert!(blue.b > blue.r && blue.b > blue.g);
+        let green = to_rgb(530.0);
+        assert!(green.g > green.r && green.g > green.b);
+        let red = to_rgb(640.0);
+        assert!(red.r > red.g && red.r > red.b);
+    }
+}
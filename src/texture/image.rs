@@ -3,6 +3,7 @@ use crate::texture::{Texture, TextureColor};
 use crate::util::clamp;
 use crate::vec::Vec3;
 use image::io::Reader as ImageReader;
+use image::ImageResult;
 use std::sync::Arc;
 
 // Image
@@ -16,39 +17,35 @@ pub struct ImageTexture {
 }
 
 impl ImageTexture {
+    // Falls back to a single cyan pixel, with a warning, if the image can't
+    // be read.
     pub fn new(filename: &str) -> Texture {
-        let default = Texture::from(ImageTexture {
-            data: Arc::new(vec![0, 1, 1]),
-            width: 1,
-            height: 1,
-            bytes_per_pixel: 3,
-            bytes_per_scanline: 3,
-        });
-        let img = ImageReader::open(filename);
-        let img = match img {
-            Ok(img) => img,
-            Err(error) => {
-                eprintln!("Problem opening image: {:?}", error);
-                return default;
-            }
-        };
-        let img = match img.decode() {
-            Ok(img) => img,
+        match ImageTexture::open(filename) {
+            Ok(texture) => texture,
             Err(error) => {
-                eprintln!("Problem decoding image: {:?}", error);
-                return default;
+                eprintln!("Problem reading image {}: {}", filename, error);
+                Texture::from(ImageTexture {
+                    data: Arc::new(vec![0, 1, 1]),
+                    width: 1,
+                    height: 1,
+                    bytes_per_pixel: 3,
+                    bytes_per_scanline: 3,
+                })
             }
-        };
+        }
+    }
+
+    pub fn open(filename: &str) -> ImageResult<Texture> {
+        let img = ImageReader::open(filename)?.decode()?;
         let rgb8 = img.to_rgb8();
         let bytes: Vec<u8> = rgb8.as_raw().to_vec();
-        let asdf = Texture::from(ImageTexture {
+        Ok(Texture::from(ImageTexture {
             data: Arc::new(bytes),
             width: rgb8.width(),
             height: rgb8.height(),
             bytes_per_pixel: 3,
             bytes_per_scanline: 3 * rgb8.width(),
-        });
-        asdf
+        }))
     }
 }
 
@@ -24,9 +24,52 @@ pub fn random_double(rng: &mut SmallRng) -> f64 {
 pub fn random_to_sphere(radius: f64, dist_squared: f64, rng: &mut SmallRng) -> Vec3 {
     let r1 = random_double(rng);
     let r2 = random_double(rng);
+    sample_to_sphere(radius, dist_squared, (r1, r2))
+}
+
+// The functions below warp a 2D sample in [0, 1)^2 into a direction or point.
+
+// Direction inside the cone of a sphere of `radius` seen from `dist_squared`
+// away, uniform over the solid angle, with the sphere along +z.
+pub fn sample_to_sphere(radius: f64, dist_squared: f64, u: (f64, f64)) -> Vec3 {
+    let (r1, r2) = u;
     let phi = 2.0 * std::f64::consts::PI * r1;
     let z = 1.0 + r2 * (f64::sqrt(1.0 - radius * radius / dist_squared) - 1.0);
     let x = f64::cos(phi) * f64::sqrt(1.0 - z * z);
     let y = f64::sin(phi) * f64::sqrt(1.0 - z * z);
     vec3(x, y, z)
 }
+
+// Cosine weighted direction about +z.
+pub fn sample_cosine_direction(u: (f64, f64)) -> Vec3 {
+    let (r1, r2) = u;
+    let phi = 2.0 * std::f64::consts::PI * r1;
+    let x = f64::cos(phi) * f64::sqrt(r2);
+    let y = f64::sin(phi) * f64::sqrt(r2);
+    let z = f64::sqrt(1.0 - r2);
+    vec3(x, y, z)
+}
+
+// Uniform direction over the whole sphere.
+pub fn sample_unit_sphere(u: (f64, f64)) -> Vec3 {
+    let z = 1.0 - 2.0 * u.0;
+    let r = f64::sqrt(f64::max(0.0, 1.0 - z * z));
+    let phi = 2.0 * std::f64::consts::PI * u.1;
+    vec3(r * f64::cos(phi), r * f64::sin(phi), z)
+}
+
+// Point in the unit disk in the z = 0 plane, by Shirley's concentric mapping,
+// which keeps strata of the square compact on the disk.
+pub fn sample_in_unit_disk(u: (f64, f64)) -> Vec3 {
+    let (a, b) = (2.0 * u.0 - 1.0, 2.0 * u.1 - 1.0);
+    if a == 0.0 && b == 0.0 {
+        return Vec3::zero();
+    }
+    let quarter_pi = std::f64::consts::FRAC_PI_4;
+    let (r, theta) = if a.abs() > b.abs() {
+        (a, quarter_pi * (b / a))
+    } else {
+        (b, 2.0 * quarter_pi - quarter_pi * (a / b))
+    };
+    vec3(r * theta.cos(), r * theta.sin(), 0.0)
+}